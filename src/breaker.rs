@@ -0,0 +1,33 @@
+// src/breaker.rs
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Schutzschalter für `--max-failures`: zählt fehlgeschlagene Schritte über
+/// alle Phasen und parallelen Schritte. Ist `max` erreicht, startet kein
+/// weiterer Schritt mehr; laufende werden noch abgewartet.
+#[derive(Debug)]
+pub struct Breaker {
+    max: usize,
+    failed: AtomicUsize,
+}
+
+impl Breaker {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            failed: AtomicUsize::new(0),
+        }
+    }
+
+    /// Zählt einen Fehler; `true` genau bei dem, der den Schalter auslöst.
+    pub fn fail(&self) -> bool {
+        self.failed.fetch_add(1, Ordering::SeqCst) + 1 == self.max
+    }
+
+    pub fn tripped(&self) -> bool {
+        self.failed.load(Ordering::SeqCst) >= self.max
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
@@ -0,0 +1,64 @@
+// src/checksum.rs
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256, Sha512};
+use std::path::Path;
+
+/// Erwartete Prüfsumme aus `"sha256:<hex>"` bzw. `"sha512:<hex>"`.
+pub struct Checksum {
+    pub algo: Algo,
+    pub hex: String, // kleingeschrieben
+}
+
+#[derive(Clone, Copy)]
+pub enum Algo {
+    Sha256,
+    Sha512,
+}
+
+impl Checksum {
+    pub fn parse(s: &str) -> Result<Self> {
+        let (algo, hex) = s
+            .split_once(':')
+            .with_context(|| format!("Prüfsumme '{}' (erwartet sha256:<hex>)", s))?;
+        let (algo, len) = match algo.trim() {
+            "sha256" => (Algo::Sha256, 64),
+            "sha512" => (Algo::Sha512, 128),
+            other => anyhow::bail!(
+                "unbekannter Prüfsummen-Algorithmus '{}' (sha256|sha512)",
+                other
+            ),
+        };
+        let hex = hex.trim().to_ascii_lowercase();
+        if hex.len() != len || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            anyhow::bail!("Prüfsumme '{}': {} Hex-Zeichen erwartet", s, len);
+        }
+        Ok(Self { algo, hex })
+    }
+
+    /// `true`, wenn die Datei existiert und die Prüfsumme stimmt.
+    pub fn matches(&self, path: &Path) -> Result<bool> {
+        if !path.is_file() {
+            return Ok(false);
+        }
+        Ok(digest(self.algo, path)? == self.hex)
+    }
+}
+
+/// Prüfsumme einer Datei als Hex-String, gelesen in Blöcken.
+pub fn digest(algo: Algo, path: &Path) -> Result<String> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("{} lesen", path.display()))?;
+    let bytes = match algo {
+        Algo::Sha256 => {
+            let mut h = Sha256::new();
+            std::io::copy(&mut file, &mut h)?;
+            h.finalize().to_vec()
+        }
+        Algo::Sha512 => {
+            let mut h = Sha512::new();
+            std::io::copy(&mut file, &mut h)?;
+            h.finalize().to_vec()
+        }
+    };
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
@@ -0,0 +1,107 @@
+// src/config.rs
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Dateiname, nach dem im aktuellen Verzeichnis gesucht wird.
+pub const FILE_NAME: &str = ".rust-runner.yaml";
+
+/// Voreinstellungen für CLI-Flags. Flags auf der Kommandozeile haben Vorrang,
+/// fehlende Werte fallen auf die eingebauten Defaults zurück.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub verbose: Option<u8>,
+    #[serde(default)]
+    pub quiet: bool, // wie --quiet, ein -v auf der Kommandozeile hebt es auf
+    #[serde(default)]
+    pub timestamps: bool,
+    #[serde(default)]
+    pub plain_prefix: bool,
+    #[serde(default)]
+    pub strict_vars: bool,
+    #[serde(default)]
+    pub diff: bool,
+    #[serde(default)]
+    pub non_interactive: bool,
+    #[serde(default)]
+    pub keep_tmp: bool,
+    #[serde(default)]
+    pub progress: bool, // nur mit Feature `progress`
+    #[serde(default)]
+    pub jobs: Option<usize>,
+    #[serde(default)]
+    pub timeout: Option<u64>, // wie --timeout, Sekunden
+    #[serde(default)]
+    pub max_retries_global: Option<u32>,
+    #[serde(default)]
+    pub max_failures: Option<usize>,
+    // Pfade gelten relativ zur Konfigurationsdatei
+    #[serde(default)]
+    pub workdir: Option<String>,
+    #[serde(default)]
+    pub template_dir: Option<String>,
+    #[serde(default)]
+    pub lock_file: Option<String>,
+    #[serde(default)]
+    pub lock_wait: bool,
+    #[serde(default)]
+    pub junit: Option<String>,
+    #[serde(default)]
+    pub inventory: Option<String>,
+    #[serde(default)]
+    pub output_dir: Option<String>,
+}
+
+/// Lädt `explicit` (`--config`) oder die erste vorhandene Datei aus
+/// `./.rust-runner.yaml` und `$XDG_CONFIG_HOME/rust-runner/config.yaml`
+/// (default `~/.config`). Ohne Datei `None`; eine explizite muss existieren.
+pub fn load(explicit: Option<&str>) -> Result<Option<(PathBuf, Config)>> {
+    let path = match explicit {
+        Some(p) => PathBuf::from(crate::paths::expand(p).context("--config")?),
+        None => match candidates().into_iter().find(|p| p.is_file()) {
+            Some(p) => p,
+            None => return Ok(None),
+        },
+    };
+    let raw = std::fs::read_to_string(&path)
+        .with_context(|| format!("Konfiguration {} lesen", path.display()))?;
+    // leere Datei = keine Voreinstellungen
+    let mut cfg = match serde_yaml::from_str::<Option<Config>>(&raw) {
+        Ok(cfg) => cfg.unwrap_or_default(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Konfiguration {} ungültig", path.display()));
+        }
+    };
+    let base = path.parent().unwrap_or(Path::new(""));
+    for p in [
+        &mut cfg.workdir,
+        &mut cfg.template_dir,
+        &mut cfg.lock_file,
+        &mut cfg.junit,
+        &mut cfg.inventory,
+        &mut cfg.output_dir,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        *p = base
+            .join(crate::paths::expand(p)?)
+            .to_string_lossy()
+            .into_owned();
+    }
+    Ok(Some((path, cfg)))
+}
+
+fn candidates() -> Vec<PathBuf> {
+    let mut out = vec![PathBuf::from(FILE_NAME)];
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".config")));
+    if let Some(dir) = config_home {
+        out.push(dir.join("rust-runner").join("config.yaml"));
+    }
+    out
+}
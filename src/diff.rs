@@ -0,0 +1,114 @@
+// src/diff.rs
+
+/// Kontextzeilen um jede Änderung, wie bei `diff -u`.
+const CONTEXT: usize = 3;
+
+/// Zeilenweiser Unified-Diff von `old` nach `new`; leer, wenn beide gleich sind.
+/// Eine fehlende Datei entspricht leerem `old`.
+pub fn unified(label: &str, old: &str, new: &str) -> String {
+    let (a, b) = (
+        old.lines().collect::<Vec<_>>(),
+        new.lines().collect::<Vec<_>>(),
+    );
+    let ops = edit_script(&a, &b);
+    if ops.iter().all(|op| matches!(op, Op::Same(..))) {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", label, label);
+    let mut i = 0;
+    while i < ops.len() {
+        // nächste Änderung suchen, dann den Hunk bis zur nächsten größeren Lücke ausdehnen
+        let Some(first) = ops[i..].iter().position(|op| !matches!(op, Op::Same(..))) else {
+            break;
+        };
+        let start = (i + first).saturating_sub(CONTEXT).max(i);
+        let mut end = i + first;
+        let mut same_run = 0;
+        for (k, op) in ops.iter().enumerate().skip(i + first) {
+            if matches!(op, Op::Same(..)) {
+                same_run += 1;
+                if same_run > 2 * CONTEXT {
+                    break;
+                }
+            } else {
+                same_run = 0;
+                end = k;
+            }
+        }
+        let end = (end + CONTEXT + 1).min(ops.len());
+        let hunk = &ops[start..end];
+        let (a_start, b_start) = positions(&ops[..start]);
+        let (a_len, b_len) = positions(hunk);
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(a_start, a_len),
+            range(b_start, b_len)
+        ));
+        for op in hunk {
+            match op {
+                Op::Same(l) => out.push_str(&format!(" {}\n", l)),
+                Op::Del(l) => out.push_str(&format!("-{}\n", l)),
+                Op::Add(l) => out.push_str(&format!("+{}\n", l)),
+            }
+        }
+        i = end;
+    }
+    out
+}
+
+enum Op<'a> {
+    Same(&'a str),
+    Del(&'a str),
+    Add(&'a str),
+}
+
+/// Edit-Skript über die längste gemeinsame Teilfolge (O(n·m), für Konfigurationsdateien ausreichend).
+fn edit_script<'a>(a: &[&'a str], b: &[&'a str]) -> Vec<Op<'a>> {
+    let (n, m) = (a.len(), b.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut ops = Vec::with_capacity(n + m);
+    while i < n && j < m {
+        if a[i] == b[j] {
+            ops.push(Op::Same(a[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push(Op::Del(a[i]));
+            i += 1;
+        } else {
+            ops.push(Op::Add(b[j]));
+            j += 1;
+        }
+    }
+    ops.extend(a[i..].iter().map(|l| Op::Del(l)));
+    ops.extend(b[j..].iter().map(|l| Op::Add(l)));
+    ops
+}
+
+/// Anzahl der Zeilen auf alter und neuer Seite.
+fn positions(ops: &[Op]) -> (usize, usize) {
+    ops.iter().fold((0, 0), |(a, b), op| match op {
+        Op::Same(_) => (a + 1, b + 1),
+        Op::Del(_) => (a + 1, b),
+        Op::Add(_) => (a, b + 1),
+    })
+}
+
+/// `start,len` im Hunk-Header; Zeilen ab 1, bei leerem Bereich die Zeile davor.
+fn range(before: usize, len: usize) -> String {
+    if len == 0 {
+        format!("{},0", before)
+    } else {
+        format!("{},{}", before + 1, len)
+    }
+}
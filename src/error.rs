@@ -0,0 +1,50 @@
+// src/error.rs
+use crate::executor::{RunTimeout, StepTimeout};
+use crate::template::TemplateError;
+
+/// Fehlerklasse eines Laufs; bestimmt den Exit-Code, damit CI Konfigurationsfehler
+/// von echten Kommandofehlern unterscheiden kann. Dient auch als anyhow-Kontext.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Parse,
+    Template,
+    Step,
+    Timeout,
+}
+
+impl ErrorKind {
+    /// Klassifiziert den Fehler eines Schritts.
+    pub fn of(e: &anyhow::Error) -> Self {
+        if let Some(kind) = e.downcast_ref::<ErrorKind>() {
+            *kind
+        } else if e.downcast_ref::<TemplateError>().is_some() {
+            ErrorKind::Template
+        } else if e.downcast_ref::<StepTimeout>().is_some()
+            || e.downcast_ref::<RunTimeout>().is_some()
+        {
+            ErrorKind::Timeout
+        } else {
+            ErrorKind::Step
+        }
+    }
+
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Parse => 2,
+            ErrorKind::Template => 3,
+            ErrorKind::Step => 4,
+            ErrorKind::Timeout => 124,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ErrorKind::Parse => "YAML parsen",
+            ErrorKind::Template => "Template rendern",
+            ErrorKind::Step => "Schritt fehlgeschlagen",
+            ErrorKind::Timeout => "Timeout",
+        })
+    }
+}
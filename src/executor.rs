@@ -1,249 +1,3949 @@
 // src/executor.rs
+use crate::checksum::{self, Algo, Checksum};
+use crate::lineinfile::{self, Anchor, Edit};
+use crate::mode::parse_mode;
+use crate::observer::{ConsoleObserver, IDLE_AFTER, Observer, OutputLine, StepStart};
+use crate::privilege::{Become, Method};
+use crate::remote::{CHANGED_MARKER, RemoteWrite};
 use crate::schema::*;
-use crate::template::Renderer;
+use crate::ssh::SshConn;
+use crate::template::{Renderer, TemplateError};
 use anyhow::{Context, Result};
+use futures_util::stream::{FuturesUnordered, StreamExt};
 use serde_yaml::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
     process::Command,
+    sync::mpsc,
 };
 
+/// Gerenderte Umgebung (Name → Wert).
+type Env = std::collections::HashMap<String, String>;
+
+#[cfg(windows)]
+const DEFAULT_SHELL: &str = "powershell -Command";
+#[cfg(not(windows))]
+const DEFAULT_SHELL: &str = "bash -c";
+
+/// Interpreter für `ssh.script`, unabhängig vom lokalen System.
+const REMOTE_INTERPRETER: &str = "bash";
+
+/// Legt remote eine Temp-Datei `$t` an, füllt sie von stdin und entfernt sie
+/// beim Beenden der Shell wieder; der Exit-Code des Skripts bleibt erhalten.
+const SCRIPT_UPLOAD: &str = "t=$(mktemp) && trap 'rm -f \"$t\"' EXIT && cat > \"$t\" && ";
+
+/// Obergrenze für gesammelte stdout/stderr pro Stream ohne `capture_limit`.
+const DEFAULT_CAPTURE_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Name, unter dem `template_macros` importiert wird.
+const MACROS_TEMPLATE: &str = "macros";
+
+/// Interpreter für `shell.script`, bekommt den Pfad der Skriptdatei angehängt.
+#[cfg(windows)]
+const DEFAULT_INTERPRETER: &str = "powershell -File";
+#[cfg(not(windows))]
+const DEFAULT_INTERPRETER: &str = "bash";
+
+tokio::task_local! {
+    /// Kontext eines Schritts mit eigenen `vars`; ohne gilt `Executor::ctx`.
+    static STEP_CTX: Value;
+    /// Bezeichnung des aktuellen `loop`-Elements für Header und Präfixe.
+    static LOOP_ITEM: String;
+}
+
+/// Laufweite Einstellungen aus der CLI.
+#[derive(Debug, Default)]
+pub struct Options {
+    pub verbose: u8, // -v 1, -vv 2, -vvv 3
+    pub dry_run: bool,
+    pub timestamps: bool,
+    pub plain_prefix: bool,
+    pub strict_vars: bool,
+    pub check: bool,
+    pub diff: bool, // Diffs für alle conf-Schritte, per `diff: false` abschaltbar
+    pub workdir: Option<String>, // Basis für relative Pfade, default: Prozess-cwd
+    pub playbook_dir: Option<String>, // None bei stdin
+    pub tmpdir: String, // laufweites Scratch-Verzeichnis, `run.tmpdir`
+    pub template_dir: Option<String>, // Tera-Templates für include/extends
+    pub ssh_program: Option<String>, // --ssh-program, vor `defaults.ssh.program`
+    pub profile: bool, // CPU-Zeit und Peak-RSS der Kindprozesse je Schritt
+    pub output_dir: Option<String>, // je Schritt `NNN-<name>.out`/`.err` zusätzlich zur Konsole
+    pub env_precedence: EnvPrecedence,
+    pub preflight: Option<Preflight>, // ssh-Ziele vor dem ersten Schritt prüfen
+}
+
+/// Was `--preflight` bei einem nicht erreichbaren Host tut.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preflight {
+    Abort, // Lauf vor dem ersten Schritt abbrechen
+    Warn,  // nur melden
+}
+
+impl Preflight {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "abort" => Ok(Self::Abort),
+            "warn" => Ok(Self::Warn),
+            other => anyhow::bail!("unbekannter Modus '{}' (abort|warn)", other),
+        }
+    }
+}
+
+/// Wer bei gleichnamigen Variablen in geerbter Umgebung und `env` gewinnt
+/// (`--env-precedence`). Gilt nur für lokale Prozesse, ssh erbt nichts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EnvPrecedence {
+    #[default]
+    ExplicitFirst, // deklariertes `env` überschreibt geerbte Variablen
+    ProcessFirst, // geerbte Variablen bleiben, gleichnamige `env`-Einträge entfallen
+}
+
+impl EnvPrecedence {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "explicit-first" => Ok(Self::ExplicitFirst),
+            "process-first" => Ok(Self::ProcessFirst),
+            other => anyhow::bail!(
+                "unbekannte Reihenfolge '{}' (explicit-first|process-first)",
+                other
+            ),
+        }
+    }
+}
+
 pub struct Executor {
     renderer: Renderer,
-    ctx: Value,
-    #[allow(dead_code)]
-    verbose: bool,
-    dry_run: bool,
+    ctx: RwLock<Value>, // `steps` wächst mit jedem beendeten Schritt
+    cwd: Option<String>,
+    defaults: Defaults,
+    opts: Options,
+    observer: Arc<dyn Observer>,
+    running: Mutex<BTreeSet<usize>>, // bleibt gesetzt, wenn ein Schritt abgebrochen wird
+    inventory: Inventory,
+    state: Option<(Arc<crate::state::State>, Option<String>)>, // --incremental, mit Phase
+    ended: AtomicBool,                                         // `meta: end_run` wurde ausgeführt
+    usage: Mutex<BTreeMap<usize, crate::profile::Usage>>,      // --profile, je Schritt
+    commands: Mutex<BTreeMap<usize, Vec<String>>>,             // wie im Header, für Reports
+    audit_opened: Mutex<BTreeSet<std::path::PathBuf>>, // --output-dir, in diesem Lauf schon geleert
+    retry_budget: Option<Arc<crate::retry::Budget>>,   // --max-retries-global
+    breaker: Option<Arc<crate::breaker::Breaker>>,     // --max-failures
 }
 
 impl Executor {
-    pub fn new(globals: Value, verbose: bool, dry_run: bool) -> Self {
-        Self {
-            renderer: Renderer::new(),
-            ctx: globals,
-            verbose,
-            dry_run,
+    pub fn new(
+        globals: Value,
+        cwd: Option<String>,
+        defaults: Defaults,
+        opts: Options,
+    ) -> Result<Self> {
+        let mut ctx = match globals {
+            Value::Mapping(m) => m,
+            _ => Default::default(),
+        };
+        // eigene `facts` in den Globals haben Vorrang
+        if !ctx.contains_key("facts") {
+            ctx.insert("facts".into(), crate::facts::gather());
         }
+        let mut run = serde_yaml::Mapping::new();
+        run.insert("tmpdir".into(), opts.tmpdir.clone().into());
+        ctx.insert("run".into(), Value::Mapping(run));
+        ctx.insert("steps".into(), Value::Mapping(Default::default()));
+        let mut renderer = Renderer::new();
+        renderer.set_strict(opts.strict_vars);
+        // wie `template_file`: ab --workdir, sonst ab dem Playbook
+        let base = opts.workdir.as_ref().or(opts.playbook_dir.as_ref());
+        renderer.set_base_dir(base.map(std::path::PathBuf::from));
+        if let Some(dir) = &opts.template_dir {
+            renderer.load_dir(Path::new(dir))?;
+        }
+        let observer = Arc::new(ConsoleObserver {
+            verbose: opts.verbose,
+            timestamps: opts.timestamps,
+            spinner: crate::output::status_supported(),
+        });
+        Ok(Self {
+            renderer,
+            ctx: RwLock::new(Value::Mapping(ctx)),
+            cwd,
+            defaults,
+            opts,
+            observer,
+            running: Default::default(),
+            inventory: Default::default(),
+            state: None,
+            ended: AtomicBool::new(false),
+            usage: Default::default(),
+            commands: Default::default(),
+            audit_opened: Default::default(),
+            retry_budget: None,
+            breaker: None,
+        })
     }
 
-    pub async fn run_step(&self, step: &Step, idx: usize) -> Result<()> {
-        if let Some(false) = step.when {
-            return Ok(());
-        }
+    /// Ersetzt die Konsolenausgabe durch einen eigenen Observer.
+    pub fn set_observer(&mut self, observer: Arc<dyn Observer>) {
+        self.observer = observer;
+    }
 
-        if let Some(shell) = &step.shell {
-            self.run_shell(step, shell, idx).await
-        } else if let Some(exec) = &step.exec {
-            self.run_exec(step, exec, idx).await
-        } else if let Some(conf) = &step.conf {
-            self.run_conf(step, conf, idx).await
-        } else if let Some(ssh) = &step.ssh {
-            self.run_ssh(step, ssh, idx).await
+    /// Host-Gruppen, auf die `ssh.host` verweisen kann.
+    pub fn set_inventory(&mut self, inventory: Inventory) {
+        self.inventory = inventory;
+    }
+
+    /// `template_macros` des Dokuments als Template `macros`, in allen
+    /// gerenderten Feldern per `{% import "macros" as m %}` verfügbar.
+    pub fn set_template_macros(&mut self, src: &str) -> Result<()> {
+        self.renderer
+            .add_template(MACROS_TEMPLATE, src)
+            .context("template_macros")
+    }
+
+    /// Rendert eine Template-Datei gegen globals und Dokumentkontext, ohne
+    /// einen Schritt auszuführen (Befehl `template`).
+    pub fn render_file(&self, path: &Path) -> Result<String> {
+        let src =
+            std::fs::read_to_string(path).with_context(|| format!("{} lesen", path.display()))?;
+        self.render(&src, &path.display().to_string())
+    }
+
+    /// Überspringt Schritte, deren Definition seit dem letzten Erfolg gleich
+    /// geblieben ist. `phase` unterscheidet gleichnamige Schritte mehrerer Phasen.
+    pub fn set_state(&mut self, state: Arc<crate::state::State>, phase: Option<String>) {
+        self.state = Some((state, phase));
+    }
+
+    /// Gemeinsame Obergrenze für Wiederholungen, siehe `--max-retries-global`.
+    pub fn set_retry_budget(&mut self, budget: Arc<crate::retry::Budget>) {
+        self.retry_budget = Some(budget);
+    }
+
+    /// Gemeinsamer Zähler fehlgeschlagener Schritte, siehe `--max-failures`.
+    pub fn set_breaker(&mut self, breaker: Arc<crate::breaker::Breaker>) {
+        self.breaker = Some(breaker);
+    }
+
+    /// Hat ein Schritt mit `meta: end_run` den Lauf beendet? Weitere Schritte
+    /// werden dann nicht mehr gestartet.
+    pub fn ended(&self) -> bool {
+        self.ended.load(Ordering::SeqCst)
+    }
+
+    /// Warum keine weiteren Schritte starten: `meta: end_run` oder ein
+    /// ausgelöster `--max-failures`-Schalter.
+    pub fn stop_reason(&self) -> Option<&'static str> {
+        if self.ended() {
+            Some("meta: end_run")
+        } else if self.breaker.as_ref().is_some_and(|b| b.tripped()) {
+            Some("--max-failures erreicht")
         } else {
-            anyhow::bail!("Step {} hat keinen ausführbaren Block", idx)
+            None
         }
     }
 
-    async fn run_shell(&self, step: &Step, spec: &ShellSpec, idx: usize) -> Result<()> {
-        let cmd_str = self.renderer.render_str(&spec.command, &self.ctx)?;
-        let shell = spec.shell.clone().unwrap_or_else(|| "bash -c".into());
-        let mut parts = shell
-            .split_whitespace()
-            .map(|s| s.to_string())
-            .collect::<Vec<_>>();
-        let (prg, mut args) = (parts.remove(0), parts);
-        args.push(cmd_str.clone());
+    /// Verbrauch der Kindprozesse eines beendeten Schritts, nur mit `profile`
+    /// und auf Plattformen mit getrusage.
+    pub fn usage(&self, idx: usize) -> Option<crate::profile::Usage> {
+        self.usage
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&idx)
+            .copied()
+    }
 
-        let env = self.merge_env(&step.env, &spec.env)?;
-        self.print_header(idx, step.name.as_deref().unwrap_or("shell"), &cmd_str);
+    /// Ausgeführte Kommandos eines Schritts, wie sie im Header stehen (Secrets
+    /// aus `env` maskiert, bei `no_log` nur `***`), je Schleifendurchlauf eines.
+    pub fn commands(&self, idx: usize) -> Vec<String> {
+        self.commands
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&idx)
+            .cloned()
+            .unwrap_or_default()
+    }
 
-        if self.dry_run {
-            return Ok(());
+    /// Indizes der Schritte, die gestartet, aber nicht beendet wurden – nach
+    /// einem Abbruch von außen (z. B. `--timeout`) die unterbrochenen.
+    pub fn running(&self) -> Vec<usize> {
+        let running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        running.iter().copied().collect()
+    }
+
+    pub async fn run_step(&self, step: &Step, idx: usize) -> Result<StepOutcome> {
+        self.running
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(idx);
+        let before = self.opts.profile.then(crate::profile::snapshot).flatten();
+        let group = step
+            .ssh
+            .as_ref()
+            .and_then(|s| self.inventory.get(&s.target.host));
+        let body = async {
+            let state = self.state_hash(step, idx);
+            if let Some((st, key, Some(hash))) = &state
+                && st.unchanged(key, hash)
+            {
+                let reason = "unverändert seit dem letzten Erfolg";
+                println!("[skip] {} ({})", key, reason);
+                let skipped = StepOutcome {
+                    skipped: true,
+                    skip_reason: Some(reason.into()),
+                    ..Default::default()
+                };
+                return (Ok(skipped), None);
+            }
+            let out = match (&step.r#loop, group) {
+                (Some(_), Some(_)) => (
+                    Err(anyhow::anyhow!(
+                        "loop und eine Host-Gruppe in ssh.host schließen sich aus"
+                    )),
+                    None,
+                ),
+                (Some(spec), None) => match self.loop_items(spec) {
+                    Ok(items) => self.run_loop(step, idx, Fan::Loop, items).await,
+                    Err(e) => (Err(e), None),
+                },
+                (None, Some(group)) => {
+                    let hosts = group.hosts.iter().map(|h| h.name().into()).collect();
+                    self.run_loop(step, idx, Fan::Hosts, hosts).await
+                }
+                (None, None) => (self.run_step_inner(step, idx).await, None),
+            };
+            // Dry-Run und --check führen nichts aus, der alte Stand bleibt
+            if let Some((st, key, hash)) = state
+                && !self.opts.dry_run
+                && !self.opts.check
+            {
+                match &out.0 {
+                    Ok(o) if o.skipped => {}
+                    Ok(_) => st.record(&key, hash),
+                    Err(_) => st.record(&key, None),
+                }
+            }
+            out
+        };
+        let (res, results) = match &step.vars {
+            Value::Null => body.await,
+            vars => {
+                // `vars` gelten nur für diesen Schritt, Globals bleiben unverändert
+                let mut ctx = self.with_ctx(Value::clone);
+                crate::schema::merge_value(&mut ctx, vars.clone());
+                STEP_CTX.scope(ctx, body).await
+            }
+        };
+        if let Some(name) = &step.name {
+            let mut entry = self.entry(step, &res);
+            if let Some(results) = results {
+                entry.insert("results".into(), Value::Sequence(results));
+            }
+            let mut ctx = self.ctx.write().unwrap_or_else(|e| e.into_inner());
+            if let Some(steps) = ctx.get_mut("steps").and_then(Value::as_mapping_mut) {
+                steps.insert(name.as_str().into(), Value::Mapping(entry));
+            }
+        }
+        let name = step
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("step {}", idx + 1));
+        if let (Some(before), Some(after)) = (before, crate::profile::snapshot()) {
+            self.usage
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(idx, crate::profile::delta(before, after));
         }
+        self.observer.on_step_end(idx, &name, &res);
+        self.running
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&idx);
+        res
+    }
 
-        let mut child = Command::new(&prg)
-            .args(&args)
-            .envs(env)
-            .current_dir(spec.cwd.clone().unwrap_or_else(|| ".".into()))
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn()
-            .context("shell spawn")?;
+    /// Schlüssel und Hash des Schritts für `--incremental`: die Definition mit
+    /// gerenderten Templates. Ohne Hash (`None`), wenn ein Template im
+    /// aktuellen Kontext nicht auswertbar ist, z. B. `item` in einem `loop`.
+    fn state_hash(
+        &self,
+        step: &Step,
+        idx: usize,
+    ) -> Option<(&crate::state::State, String, Option<String>)> {
+        let (state, phase) = self.state.as_ref()?;
+        let name = step
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("step {}", idx + 1));
+        let key = match phase {
+            Some(p) => format!("{}/{}", p, name),
+            None => name,
+        };
+        let hash = serde_yaml::to_value(step).ok().and_then(|mut v| {
+            self.with_ctx(|ctx| render_leaves(&self.renderer, &mut v, ctx))
+                .ok()?;
+            Some(crate::state::hash(&serde_yaml::to_string(&v).ok()?))
+        });
+        Some((state, key, hash))
+    }
 
-        self.stream_child(&mut child, "shell").await
+    /// Eintrag unter `steps.<name>` für `when`/Templates späterer Schritte.
+    fn entry(&self, step: &Step, res: &Result<StepOutcome>) -> serde_yaml::Mapping {
+        let (rc, changed, stdout, stderr, skipped, parsed) = match res {
+            Ok(o) => (
+                o.rc,
+                o.changed,
+                o.stdout.as_str(),
+                o.stderr.as_str(),
+                o.skipped,
+                o.parsed.as_ref(),
+            ),
+            Err(e) => match e.downcast_ref::<ProcessFailed>() {
+                Some(pf) => (
+                    pf.rc.unwrap_or(-1),
+                    false,
+                    pf.stdout.as_str(),
+                    pf.stderr.as_str(),
+                    false,
+                    None,
+                ),
+                None => (-1, false, "", "", false, None),
+            },
+        };
+        let signal = match res {
+            Ok(o) => o.signal,
+            Err(e) => e.downcast_ref::<ProcessFailed>().and_then(|pf| pf.signal),
+        };
+        let mut entry = serde_yaml::Mapping::new();
+        entry.insert("rc".into(), rc.into());
+        // z. B. "SIGKILL", sonst null
+        entry.insert(
+            "signal".into(),
+            signal.map_or(Value::Null, |s| signal_name(s).into()),
+        );
+        entry.insert("changed".into(), changed.into());
+        entry.insert("stdout".into(), stdout.into());
+        entry.insert("stderr".into(), stderr.into());
+        entry.insert("skipped".into(), skipped.into());
+        entry.insert("failed".into(), res.is_err().into());
+        // Platzhalter ohne echte Ausgabe, für `when: not steps.x.dry_run`
+        entry.insert("dry_run".into(), self.dry_run(step).into());
+        if let Some(json) = parsed {
+            entry.insert("json".into(), json.clone());
+        }
+        entry
     }
 
-    async fn run_exec(&self, step: &Step, spec: &ExecSpec, idx: usize) -> Result<()> {
-        let cmd = self.renderer.render_str(&spec.cmd, &self.ctx)?;
-        let args = spec
-            .args
-            .iter()
-            .map(|a| self.renderer.render_str(a, &self.ctx))
-            .collect::<Result<Vec<_>>>()?;
-        let env = self.merge_env(&step.env, &spec.env)?;
-        let line = format!("{} {}", cmd, shell_escape::escape(args.join(" ").into()));
-        self.print_header(idx, step.name.as_deref().unwrap_or("exec"), &line);
+    /// `loop`: ein Durchlauf je Element mit `item` und `item_index` im Kontext,
+    /// höchstens `loop_parallel` gleichzeitig. Jeder Durchlauf wird einzeln
+    /// gemeldet und landet in `results`. Nach einem Fehler starten keine
+    /// weiteren (laufende werden abgewartet), außer mit `loop_continue_on_failure`.
+    /// Mit `Fan::Hosts` dasselbe für die Hosts einer Gruppe, unter `inventory_host`.
+    async fn run_loop(
+        &self,
+        step: &Step,
+        idx: usize,
+        fan: Fan,
+        items: Vec<Value>,
+    ) -> (Result<StepOutcome>, Option<Vec<Value>>) {
+        let total = items.len();
+        let started = Instant::now();
+        let base = self.with_ctx(Value::clone);
+        let run_item = |i: usize, item: Value| {
+            let mut ctx = base.clone();
+            if let Value::Mapping(m) = &mut ctx {
+                m.insert(fan.var().into(), item.clone());
+                if let Fan::Loop = fan {
+                    m.insert("item_index".into(), i.into());
+                }
+            }
+            let label = item_label(&item, i);
+            async move {
+                let res = STEP_CTX.scope(ctx, self.run_step_inner(step, idx));
+                let res = LOOP_ITEM.scope(label.clone(), res).await;
+                (i, item, label, res)
+            }
+        };
+
+        let mut pending = items.clone().into_iter().enumerate();
+        let mut running = FuturesUnordered::new();
+        let mut done = (0..total).map(|_| None).collect::<Vec<_>>();
+        let mut failed = false;
+        loop {
+            while running.len() < step.loop_parallel.unwrap_or(1).max(1)
+                && (!failed || step.loop_continue_on_failure)
+            {
+                let Some((i, item)) = pending.next() else {
+                    break;
+                };
+                running.push(run_item(i, item));
+            }
+            let Some((i, item, label, res)) = running.next().await else {
+                break;
+            };
+            let state = match &res {
+                Ok(o) if o.skipped => "übersprungen".to_string(),
+                Ok(o) => format!("ok (rc {})", o.rc),
+                Err(e) => format!("Fehler: {:#}", e),
+            };
+            println!("[{}] {}/{} {}: {}", fan.tag(), i + 1, total, label, state);
+            failed |= res.is_err();
+            done[i] = Some((item, res));
+        }
+
+        let mut results = Vec::with_capacity(total);
+        let mut outcome = StepOutcome {
+            skipped: true,
+            skip_reason: Some(format!("{} ohne Elemente", fan.tag())),
+            ..Default::default()
+        };
+        let (mut first_err, mut failures, mut not_run) = (None, 0, 0);
+        for (slot, item) in done.into_iter().zip(items) {
+            let Some((item, res)) = slot else {
+                // nach einem Fehler nicht mehr gestartet
+                not_run += 1;
+                let mut entry = self.entry(
+                    step,
+                    &Ok(StepOutcome {
+                        skipped: true,
+                        ..Default::default()
+                    }),
+                );
+                entry.insert(fan.var().into(), item);
+                results.push(Value::Mapping(entry));
+                continue;
+            };
+            let mut entry = self.entry(step, &res);
+            entry.insert(fan.var().into(), item);
+            results.push(Value::Mapping(entry));
+            match res {
+                Ok(o) => {
+                    outcome.rc = outcome.rc.max(o.rc);
+                    outcome.changed |= o.changed;
+                    outcome.stdout.push_str(&o.stdout);
+                    outcome.stderr.push_str(&o.stderr);
+                    if !o.skipped {
+                        (outcome.skipped, outcome.skip_reason) = (false, None);
+                    } else if outcome.skipped {
+                        outcome.skip_reason = o.skip_reason;
+                    }
+                }
+                Err(e) => {
+                    failures += 1;
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        outcome.duration = started.elapsed();
+        let res = match first_err {
+            Some(e) => {
+                let mut msg = format!(
+                    "{}: {} von {} {} fehlgeschlagen",
+                    fan.tag(),
+                    failures,
+                    total,
+                    fan.noun()
+                );
+                if not_run > 0 {
+                    msg.push_str(&format!(", {} nicht gestartet", not_run));
+                }
+                Err(e.context(msg))
+            }
+            None => Ok(outcome),
+        };
+        (res, Some(results))
+    }
+
+    /// Elemente von `loop`: eine Liste oder `"{{ ausdruck }}"`, der eine Liste ergibt.
+    fn loop_items(&self, spec: &Value) -> Result<Vec<Value>> {
+        match spec {
+            Value::Sequence(items) => Ok(items.clone()),
+            Value::String(s) => {
+                let expr = s
+                    .trim()
+                    .strip_prefix("{{")
+                    .and_then(|r| r.strip_suffix("}}"))
+                    .with_context(|| {
+                        format!(
+                            "loop: Liste oder \"{{{{ variable }}}}\" erwartet, nicht '{}'",
+                            s
+                        )
+                    })?;
+                let json = self.render(
+                    &format!("{{{{ {} | json_encode() }}}}", expr.trim()),
+                    "loop",
+                )?;
+                match serde_json::from_str::<Value>(&json).context("loop")? {
+                    Value::Sequence(items) => Ok(items),
+                    _ => anyhow::bail!("loop: '{}' ist keine Liste", expr.trim()),
+                }
+            }
+            _ => anyhow::bail!("loop: Liste oder \"{{{{ variable }}}}\" erwartet"),
+        }
+    }
+
+    async fn run_step_inner(&self, step: &Step, idx: usize) -> Result<StepOutcome> {
+        for cond in &step.when {
+            let run = self
+                .with_ctx(|ctx| self.renderer.eval_bool(cond, ctx))
+                .context("when")?;
+            if !run {
+                return Ok(StepOutcome {
+                    skipped: true,
+                    skip_reason: Some(format!("when: {}", cond)),
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Pausen zählen zur Laufzeit des Schritts
+        let started = std::time::Instant::now();
+        if let Some(secs) = step.delay_before {
+            self.pause(step, "delay_before", secs).await;
+        }
+        let mut res = self.run_hooked(step, idx).await;
+        if let (Some(secs), Ok(o)) = (step.delay_after, &res)
+            && !o.skipped
+        {
+            self.pause(step, "delay_after", secs).await;
+        }
+        // vor no_log, das stdout leert; übersprungene Schritte haben keine Ausgabe,
+        // im Dry-Run steht ein leeres Objekt für spätere Templates bereit
+        if let (Some(Parse::Json), Ok(o)) = (step.parse, &mut res)
+            && !o.skipped
+        {
+            match serde_json::from_str::<serde_json::Value>(&o.stdout) {
+                _ if self.dry_run(step) => o.parsed = Some(Value::Mapping(Default::default())),
+                Ok(json) => o.parsed = Some(serde_yaml::to_value(json)?),
+                Err(e) => {
+                    res = Err(anyhow::anyhow!(
+                        "parse: json: stdout ist kein gültiges JSON ({})",
+                        e
+                    ))
+                }
+            }
+        }
+        if step.no_log {
+            // auch der Report bekommt keine Ausgabe zu sehen
+            match &mut res {
+                Ok(o) => (o.stdout, o.stderr) = Default::default(),
+                Err(e) => {
+                    if let Some(pf) = e.downcast_mut::<ProcessFailed>() {
+                        (pf.stdout, pf.stderr) = Default::default();
+                    }
+                }
+            }
+        }
+        res.map(|mut o| {
+            o.duration = started.elapsed();
+            // mit `check: true` markierte Kommandos sind lesend; conf vergleicht
+            // selbst, auch wenn `from_command` dafür laufen darf
+            if self.opts.check && step.check && step.conf.is_none() {
+                o.changed = false;
+            }
+            o
+        })
+        .map_err(|e| {
+            if e.downcast_ref::<TemplateError>().is_some() {
+                let name = step
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("step {}", idx + 1));
+                e.context(format!("Template-Fehler in Schritt {} ({})", idx + 1, name))
+            } else {
+                e
+            }
+        })
+    }
+
+    /// Hauptaktion mit `pre`/`post`-Hooks; `post_always` führt `post` auch nach
+    /// einem Fehler aus, der ursprüngliche Fehler bleibt dann maßgeblich.
+    /// Danach je nach Ergebnis `on_success` oder `on_failure`.
+    async fn run_hooked(&self, step: &Step, idx: usize) -> Result<StepOutcome> {
+        if let Some(pre) = &step.pre
+            && let Err(e) = self.run_hook(step, idx, "pre", pre, &[]).await
+        {
+            let res = Err(e.context("pre-Hook fehlgeschlagen"));
+            return self.run_result_hook(step, idx, res).await;
+        }
+        let live = !self.dry_run(step) && !self.check_skips(step);
+        let res = match (&step.until, step.r#async) {
+            (Some(_), Some(_)) => anyhow::bail!("until und async schließen sich aus"),
+            (Some(cond), None) if live => self.run_until(step, idx, cond).await,
+            (None, Some(max)) if live => self.run_async(step, idx, max).await,
+            _ => self.run_with_retry(step, idx).await,
+        };
+        let res = match &step.post {
+            Some(post) if res.is_ok() || step.post_always => {
+                let hook = self.run_hook(step, idx, "post", post, &[]).await;
+                match (res, hook) {
+                    (Ok(o), Ok(())) => Ok(o),
+                    (Ok(_), Err(e)) => Err(e.context("post-Hook fehlgeschlagen")),
+                    (Err(e), Err(h)) => {
+                        eprintln!("[post] fehlgeschlagen: {:#}", h);
+                        Err(e)
+                    }
+                    (Err(e), Ok(())) => Err(e),
+                }
+            }
+            _ => res,
+        };
+        self.run_result_hook(step, idx, res).await
+    }
+
+    /// `on_success` bzw. `on_failure` mit `RR_STEP`, `RR_STEP_INDEX` und `RR_RC`,
+    /// nach einem Fehler zusätzlich `RR_SIGNAL`, `RR_ERROR` und `RR_STDERR` wie bei den
+    /// Benachrichtigungen. Übersprungene Schritte lösen nichts aus. Ein
+    /// fehlgeschlagener Hook wird nur gemeldet, mit `fail_on_hook_error`
+    /// scheitert ein erfolgreicher Schritt daran.
+    async fn run_result_hook(
+        &self,
+        step: &Step,
+        idx: usize,
+        res: Result<StepOutcome>,
+    ) -> Result<StepOutcome> {
+        let name = step
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("step {}", idx + 1));
+        let (kind, src, vars) = match &res {
+            Ok(o) if o.skipped => return res,
+            Ok(o) => (
+                "on_success",
+                &step.on_success,
+                vec![("RR_RC", o.rc.to_string())],
+            ),
+            Err(e) => {
+                let f = crate::notify::Failure::new(idx + 1, name.clone(), e);
+                let rc = f.rc.map(|rc| rc.to_string()).unwrap_or_default();
+                let vars = vec![
+                    ("RR_RC", rc),
+                    ("RR_SIGNAL", f.signal.unwrap_or_default()),
+                    ("RR_ERROR", f.error),
+                    ("RR_STDERR", f.stderr),
+                ];
+                ("on_failure", &step.on_failure, vars)
+            }
+        };
+        let Some(src) = src else {
+            return res;
+        };
+        let mut extra = vec![("RR_STEP", name), ("RR_STEP_INDEX", (idx + 1).to_string())];
+        extra.extend(vars);
+        let hook = self.run_hook(step, idx, kind, src, &extra).await;
+        match (res, hook) {
+            (Ok(_), Err(e)) if step.fail_on_hook_error => {
+                Err(e.context("on_success-Hook fehlgeschlagen"))
+            }
+            (res, Err(e)) => {
+                eprintln!("[{}] fehlgeschlagen: {:#}", kind, e);
+                res
+            }
+            (res, Ok(())) => res,
+        }
+    }
+
+    /// Führt einen Hook über die Default-Shell im cwd des Dokuments aus;
+    /// `extra` kommt ungerendert zur Umgebung des Schritts dazu.
+    async fn run_hook(
+        &self,
+        step: &Step,
+        idx: usize,
+        kind: &str,
+        src: &str,
+        extra: &[(&str, String)],
+    ) -> Result<()> {
+        let cmd = self.render(src, kind)?;
+        let (prg, args) = shell_argv(None, &cmd)?;
+        let (_, mut env) = self.step_env(step, &Default::default())?;
+        env.extend(extra.iter().map(|(k, v)| (k.to_string(), v.clone())));
+        let cwd = self.resolve_cwd(None)?;
+        println!("[{}] {}", kind, if step.no_log { "(no_log)" } else { &cmd });
 
-        if self.dry_run {
+        if self.dry_run(step) {
+            return Ok(());
+        }
+        if self.check_skips(step) {
+            println!("[check] {} übersprungen", kind);
             return Ok(());
         }
 
-        let mut child = Command::new(&cmd)
+        let mut child = Command::new(&prg)
             .args(&args)
+            .env_clear()
             .envs(env)
-            .current_dir(spec.cwd.clone().unwrap_or_else(|| ".".into()))
+            .current_dir(&cwd)
             .stdout(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
             .spawn()
-            .context("exec spawn")?;
+            .with_context(|| format!("{} spawn", kind))?;
+        self.stream_child(
+            &mut child,
+            step,
+            format!("{}[{}]", self.line_prefix(step, idx, "step"), kind),
+            idx,
+            OutputFiles::default(),
+            false,
+        )
+        .await?;
+        Ok(())
+    }
 
-        self.stream_child(&mut child, "exec").await
+    /// Wiederholt den Schritt, bis `until` (über `rc`/`stdout`/`stderr`) truthy ist.
+    /// Ein fehlgeschlagener Prozess zählt als "noch nicht bereit".
+    async fn run_until(&self, step: &Step, idx: usize, cond: &str) -> Result<StepOutcome> {
+        let retries = step.retries.unwrap_or(3);
+        let delay = std::time::Duration::from_secs(step.delay.unwrap_or(5));
+        for attempt in 0..=retries {
+            let outcome = match self.run_with_retry(step, idx).await {
+                Ok(o) => o,
+                Err(e) => match e.downcast::<ProcessFailed>() {
+                    Ok(pf) => StepOutcome {
+                        rc: pf.rc.unwrap_or(-1),
+                        signal: pf.signal,
+                        changed: true,
+                        stdout: pf.stdout,
+                        stderr: pf.stderr,
+                        ..Default::default()
+                    },
+                    Err(e) => return Err(e),
+                },
+            };
+            let ctx = self.ctx_with([
+                ("rc", Value::from(outcome.rc)),
+                ("stdout", Value::from(outcome.stdout.as_str())),
+                ("stderr", Value::from(outcome.stderr.as_str())),
+            ]);
+            if self.renderer.eval_bool(cond, &ctx).context("until")? {
+                return Ok(outcome);
+            }
+            if attempt < retries {
+                eprintln!(
+                    "[until] Bedingung nicht erfüllt ({}/{}), warte {}s",
+                    attempt + 1,
+                    retries + 1,
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+        anyhow::bail!(
+            "until-Bedingung '{}' nach {} Versuchen nicht erfüllt",
+            cond,
+            retries + 1
+        )
     }
 
-    async fn run_conf(&self, step: &Step, spec: &ConfSpec, idx: usize) -> Result<()> {
-        let dest = self.renderer.render_str(&spec.dest, &self.ctx)?;
-        let content = self.renderer.render_str(&spec.template, &self.ctx)?;
-        self.print_header(
-            idx,
-            step.name.as_deref().unwrap_or("conf"),
-            &format!("write {}", dest),
-        );
+    /// `async`: startet das Kommando und führt parallel alle `poll` Sekunden
+    /// `status_cmd` aus. Erfolg, sobald `status_cmd` mit 0 endet und das
+    /// Kommando fertig ist; beides zusammen höchstens `async` Sekunden.
+    async fn run_async(&self, step: &Step, idx: usize, max: u64) -> Result<StepOutcome> {
+        let status_cmd = step
+            .status_cmd
+            .as_ref()
+            .context("async braucht status_cmd")?;
+        let status_cmd = self.render(status_cmd, "status_cmd")?;
+        let poll = std::time::Duration::from_secs(step.poll.unwrap_or(10).max(1));
+        let started = tokio::time::Instant::now();
+        let deadline = started + std::time::Duration::from_secs(max);
 
-        if self.dry_run {
-            println!("Content preview:\n{}", content);
-            return Ok(());
+        // das Kommando selbst wird über `timeout` auf `async` begrenzt
+        let action = self.run_with_retry(step, idx);
+        tokio::pin!(action);
+        let mut ticks = tokio::time::interval_at(started + poll, poll);
+        let mut outcome = None;
+        let mut ready = false;
+        while !(ready && outcome.is_some()) {
+            tokio::select! {
+                res = &mut action, if outcome.is_none() => outcome = Some(res?),
+                _ = ticks.tick(), if !ready => {
+                    ready = self.poll_status(step, &status_cmd, deadline.saturating_duration_since(tokio::time::Instant::now())).await?;
+                    println!(
+                        "[poll] status_cmd {} nach {}s",
+                        if ready { "erfolgreich" } else { "noch nicht bereit" },
+                        started.elapsed().as_secs()
+                    );
+                }
+                _ = tokio::time::sleep_until(deadline), if outcome.is_some() => {
+                    return Err(anyhow::Error::new(StepTimeout(max))
+                        .context(format!("status_cmd '{}' nicht erfolgreich", status_cmd)));
+                }
+            }
         }
+        Ok(outcome.unwrap_or_default())
+    }
 
-        let path = Path::new(&dest);
-        if spec.backup && path.exists() {
-            let bak = format!("{}.bak", dest);
-            std::fs::copy(&dest, &bak).context("backup copy")?;
-            println!("[conf] backup -> {}", bak);
-        }
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)?;
-        }
-        std::fs::write(&dest, content)?;
-        if let Some(mode) = &spec.mode {
-            use std::os::unix::fs::PermissionsExt;
-            let m = u32::from_str_radix(mode, 8).unwrap_or(0o644);
-            std::fs::set_permissions(&dest, std::fs::Permissions::from_mode(m))?;
+    /// Ein `status_cmd`-Aufruf über die Default-Shell, im cwd und mit der
+    /// Umgebung des Schritts; die Ausgabe erscheint nur mit `-v`.
+    async fn poll_status(&self, step: &Step, cmd: &str, left: std::time::Duration) -> Result<bool> {
+        let (prg, args) = shell_argv(None, cmd)?;
+        let (local_env, cwd) = match (&step.shell, &step.exec) {
+            (Some(s), _) => (&s.env, s.cwd.as_ref()),
+            (None, Some(e)) => (&e.env, e.cwd.as_ref()),
+            _ => (&EnvList::new(), None),
+        };
+        let (_, env) = self.step_env(step, local_env)?;
+        let child = Command::new(&prg)
+            .args(&args)
+            .env_clear()
+            .envs(env)
+            .current_dir(self.resolve_cwd(cwd)?)
+            .kill_on_drop(true)
+            .output();
+        let Ok(output) = tokio::time::timeout(left, child).await else {
+            return Ok(false);
+        };
+        let output = output.context("status_cmd spawn")?;
+        if self.opts.verbose >= 1 && !step.no_log {
+            for line in String::from_utf8_lossy(&output.stdout).lines() {
+                println!("[poll][out] {}", line);
+            }
+            for line in String::from_utf8_lossy(&output.stderr).lines() {
+                eprintln!("[poll][err] {}", line);
+            }
         }
-        Ok(())
+        Ok(output.status.success())
     }
 
-    async fn run_ssh(&self, step: &Step, spec: &SshSpec, idx: usize) -> Result<()> {
-        // Variante A: openssh crate, nutzt lokales ssh
-        let host = self.renderer.render_str(&spec.host, &self.ctx)?;
-        let user = if let Some(u) = &spec.user {
-            self.renderer.render_str(u, &self.ctx)?
-        } else {
-            "root".to_string()
+    async fn run_with_retry(&self, step: &Step, idx: usize) -> Result<StepOutcome> {
+        let stderr_re = match &step.retry_on_stderr {
+            Some(r) => Some(regex::Regex::new(r).context("retry_on_stderr")?),
+            None => None,
         };
-        let command = self.renderer.render_str(&spec.command, &self.ctx)?;
-        let env = self.renderer.render_map(&spec.env, &self.ctx)?;
+        let attempts = step.retry.or(self.defaults.retry).unwrap_or(0) + 1;
+        let backoff = step
+            .retry_backoff
+            .as_ref()
+            .or(self.defaults.retry_backoff.as_ref())
+            .cloned()
+            .unwrap_or_default();
+        let mut attempt = 1;
+        loop {
+            match self.run_action(step, idx).await {
+                Err(e) if attempt < attempts && should_retry(step, stderr_re.as_ref(), &e) => {
+                    if let Some(budget) = &self.retry_budget
+                        && !budget.take()
+                    {
+                        eprintln!(
+                            "[retry] --max-retries-global {} erreicht, keine weitere Wiederholung",
+                            budget.max()
+                        );
+                        return Err(e);
+                    }
+                    let wait = match &step.retry_delay {
+                        Some(tpl) => match self.retry_delay(tpl, attempt, attempts - 1) {
+                            Ok(wait) => wait,
+                            Err(delay_err) => {
+                                eprintln!(
+                                    "[retry] Versuch {}/{} fehlgeschlagen: {}",
+                                    attempt, attempts, e
+                                );
+                                return Err(delay_err);
+                            }
+                        },
+                        None => crate::retry::backoff_delay(&backoff, attempt),
+                    };
+                    eprintln!(
+                        "[retry] Versuch {}/{} fehlgeschlagen: {}, nächster in {:.1}s",
+                        attempt,
+                        attempts,
+                        e,
+                        wait.as_secs_f64()
+                    );
+                    tokio::time::sleep(wait).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
 
-        let mut ssh_cmd = vec!["ssh".to_string()];
-        match spec.check_host.as_deref() {
-            Some("no") | None => ssh_cmd.extend(
-                [
-                    "-o",
-                    "StrictHostKeyChecking=no",
-                    "-o",
-                    "UserKnownHostsFile=/dev/null",
-                ]
-                .map(String::from),
+    /// Rendert `retry_delay` nach dem fehlgeschlagenen Versuch `attempt` (ab 1),
+    /// mit `attempt` und `retries` im Kontext; das Ergebnis muss eine Zahl sein.
+    fn retry_delay(&self, tpl: &str, attempt: u32, retries: u32) -> Result<std::time::Duration> {
+        let ctx = self.ctx_with([("attempt", attempt.into()), ("retries", retries.into())]);
+        let raw = self.renderer.render_str(tpl, &ctx).context("retry_delay")?;
+        match raw.trim().parse::<f64>() {
+            Ok(secs) if secs.is_finite() && secs >= 0.0 => {
+                Ok(std::time::Duration::from_secs_f64(secs))
+            }
+            _ => anyhow::bail!(
+                "retry_delay: '{}' ist keine Wartezeit in Sekunden (Zahl >= 0)",
+                raw.trim()
             ),
-            Some("yes") => {}
-            Some("fingerprint") => {} // TODO: known_hosts Handling
-            _ => {}
         }
-        // Key/Passwort: für openssh via ssh-Optionen; Passwort interaktiv wird vermieden
-        if let Some(auth) = &spec.auth
-            && auth.kind == "key"
-            && let Some(k) = &auth.key_path
-        {
-            let key = self.renderer.render_str(k, &self.ctx)?;
-            ssh_cmd.extend(["-i", &key].iter().map(|s| s.to_string()));
+    }
+
+    /// Ruft `f` mit dem Kontext des laufenden Schritts (Globals plus `vars`).
+    fn with_ctx<R>(&self, f: impl FnOnce(&Value) -> R) -> R {
+        let mut f = Some(f);
+        let mut call = |ctx: &Value| f.take().expect("einmal aufgerufen")(ctx);
+        STEP_CTX
+            .try_with(|ctx| call(ctx))
+            .unwrap_or_else(|_| call(&self.ctx.read().unwrap_or_else(|e| e.into_inner())))
+    }
+
+    /// Kontext mit zusätzlichen Top-Level-Schlüsseln (überschreiben Globals).
+    fn ctx_with<const N: usize>(&self, extra: [(&str, Value); N]) -> Value {
+        let mut map = self.with_ctx(|ctx| ctx.as_mapping().cloned().unwrap_or_default());
+        for (k, v) in extra {
+            map.insert(Value::from(k), v);
         }
-        ssh_cmd.push(format!("{}@{}", user, host));
-        // ENV inline export
-        let env_export = if env.is_empty() {
-            "".to_string()
-        } else {
-            let assigns = env
-                .iter()
-                .map(|(k, v)| format!("{}={}", k, shell_escape::escape(v.into())))
-                .collect::<Vec<_>>()
-                .join(" ");
-            format!("{} ", assigns)
+        Value::Mapping(map)
+    }
+
+    /// Wie `render`, danach `~` und `${VAR}` im Ergebnis expandieren (siehe
+    /// `paths::expand`); Werte aus `{{ ENV.X }}` bleiben dabei unverändert.
+    fn render_path(&self, src: &str, field: &str) -> Result<String> {
+        self.trace_tpl(src, field);
+        let rendered = self
+            .with_ctx(|ctx| self.renderer.render_path(src, ctx))
+            .context(field.to_string())?;
+        crate::paths::expand(&rendered).context(field.to_string())
+    }
+
+    /// `become` des Schritts mit gerendertem Benutzer, sonst `None`.
+    fn resolve_become(&self, step: &Step) -> Result<Option<Become>> {
+        if !step.r#become {
+            return Ok(None);
+        }
+        let method = Method::parse(step.become_method.as_deref())?;
+        let user = match &step.become_user {
+            Some(u) => self.render(u, "become_user")?,
+            None => "root".into(),
+        };
+        let password = match &step.become_password {
+            Some(_) if method != Method::Sudo => {
+                anyhow::bail!("become_password gibt es nur mit become_method: sudo")
+            }
+            Some(pw) => Some(self.render(pw, "become_password")?),
+            None => None,
         };
-        ssh_cmd.push(format!("{}{}", env_export, command));
+        Ok(Some(Become {
+            method,
+            user,
+            password,
+        }))
+    }
+
+    /// Stellt bei `become` das Präfix der Methode vor Programm und Argumente.
+    fn apply_become(
+        &self,
+        step: &Step,
+        prg: String,
+        args: Vec<String>,
+    ) -> Result<(String, Vec<String>)> {
+        let Some(b) = self.resolve_become(step)? else {
+            return Ok((prg, args));
+        };
+        let mut argv = b.wrap_argv(std::iter::once(prg).chain(args).collect());
+        let prg = argv.remove(0);
+        Ok((prg, argv))
+    }
 
-        let line = ssh_cmd.join(" ");
-        self.print_header(idx, step.name.as_deref().unwrap_or("ssh"), &line);
+    /// Im Dry-Run laufen nur shell/exec/ssh mit `dry_run_safe: true` (samt Hooks).
+    fn dry_run(&self, step: &Step) -> bool {
+        self.opts.dry_run && !step.dry_run_safe
+    }
 
-        if self.dry_run {
-            return Ok(());
+    /// Feste Pause aus `delay_before`/`delay_after`; im Dry-Run und wo `--check`
+    /// das Kommando auslässt, wird sie nur angezeigt.
+    async fn pause(&self, step: &Step, field: &str, secs: u64) {
+        if self.dry_run(step) || self.check_skips(step) {
+            println!("[dry-run] {}: {}s", field, secs);
+            return;
         }
+        println!("[delay] {}s ({})", secs, field);
+        tokio::time::sleep(std::time::Duration::from_secs(secs)).await;
+    }
 
-        let mut child = Command::new(&ssh_cmd[0])
-            .args(&ssh_cmd[1..])
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn()
-            .context("ssh spawn")?;
+    /// Im Check-Modus laufen Kommandos nur mit `check: true`.
+    fn check_skips(&self, step: &Step) -> bool {
+        self.opts.check && !step.check
+    }
 
-        self.stream_child(&mut child, "ssh").await
+    /// Meldet den übersprungenen Schritt im Check-Modus; er würde ausgeführt
+    /// und zählt daher als Änderung.
+    fn check_skipped(&self) -> StepOutcome {
+        println!("[check] übersprungen (kein check: true)");
+        StepOutcome {
+            changed: true,
+            skipped: true,
+            skip_reason: Some("--check, kein check: true".into()),
+            ..Default::default()
+        }
     }
 
-    fn merge_env(
+    /// `creates`/`removes`: überspringt den Schritt, wenn das Kommando schon
+    /// gewirkt hat. Relative Pfade gelten ab `cwd`.
+    fn guard_skip(
         &self,
-        step_env: &std::collections::HashMap<String, String>,
-        local_env: &std::collections::HashMap<String, String>,
-    ) -> Result<std::collections::HashMap<String, String>> {
-        let mut env = std::env::vars().collect::<std::collections::HashMap<_, _>>();
-        for (k, v) in step_env {
-            env.insert(k.clone(), self.renderer.render_str(v, &self.ctx)?);
+        kind: &str,
+        creates: Option<&String>,
+        removes: Option<&String>,
+        cwd: &str,
+    ) -> Result<Option<StepOutcome>> {
+        let path = |raw: &String, field: &str| -> Result<std::path::PathBuf> {
+            Ok(Path::new(cwd).join(self.render_path(raw, &format!("{}.{}", kind, field))?))
+        };
+        let mut reason = None;
+        if let Some(raw) = creates {
+            let p = path(raw, "creates")?;
+            if p.exists() {
+                reason = Some(format!("creates: {} existiert", p.display()));
+            }
         }
-        for (k, v) in local_env {
-            env.insert(k.clone(), self.renderer.render_str(v, &self.ctx)?);
+        if let (None, Some(raw)) = (&reason, removes) {
+            let p = path(raw, "removes")?;
+            if !p.exists() {
+                reason = Some(format!("removes: {} existiert nicht", p.display()));
+            }
+        }
+        let Some(reason) = reason else {
+            return Ok(None);
+        };
+        println!("[skip] {}", reason);
+        Ok(Some(StepOutcome {
+            skipped: true,
+            skip_reason: Some(reason),
+            ..Default::default()
+        }))
+    }
+
+    /// Rendert ein Feld gegen den Kontext; der Feldname landet im Fehler.
+    fn render(&self, src: &str, field: &str) -> Result<String> {
+        self.trace_tpl(src, field);
+        self.with_ctx(|ctx| self.renderer.render_str(src, ctx))
+            .context(field.to_string())
+    }
+
+    /// Wie `render`, aber jede `{{ }}`-Ausgabe wird für die (Remote-)Shell
+    /// gequotet, damit Werte mit Leerzeichen oder `;` ein Wort bleiben.
+    fn render_quoted(&self, src: &str, field: &str) -> Result<String> {
+        self.trace_tpl(src, field);
+        self.with_ctx(|ctx| self.renderer.render_shell(src, ctx))
+            .context(field.to_string())
+    }
+
+    /// Ab `-vv` die Quelle eines Templates; der gerenderte Wert kann
+    /// Secrets enthalten und erscheint nur im Schrittkopf.
+    fn trace_tpl(&self, src: &str, field: &str) {
+        if self.opts.verbose >= 2 && (src.contains("{{") || src.contains("{%")) {
+            println!("[tpl] {}: {}", field, src);
         }
-        Ok(env)
     }
 
-    fn print_header(&self, idx: usize, kind: &str, rendered: &str) {
-        println!("\n==[{}] {} ==", idx + 1, kind);
-        println!("-> {}", rendered);
+    async fn run_action(&self, step: &Step, idx: usize) -> Result<StepOutcome> {
+        if step.delegate_to.is_some() && step.shell.is_none() && step.exec.is_none() {
+            anyhow::bail!("delegate_to gilt nur für shell und exec");
+        }
+        if let Some(shell) = &step.shell {
+            self.run_shell(step, shell, idx).await
+        } else if let Some(exec) = &step.exec {
+            self.run_exec(step, exec, idx).await
+        } else if let Some(conf) = &step.conf {
+            self.run_conf(step, conf, idx).await
+        } else if let Some(ssh) = &step.ssh {
+            self.run_ssh(step, ssh, idx).await
+        } else if let Some(assert) = &step.assert {
+            self.run_assert(step, assert, idx)
+        } else if let Some(download) = &step.download {
+            self.run_download(step, download, idx).await
+        } else if let Some(lineinfile) = &step.lineinfile {
+            self.run_lineinfile(step, lineinfile, idx)
+        } else if let Some(meta) = step.meta {
+            self.run_meta(step, meta, idx)
+        } else {
+            anyhow::bail!("Step {} hat keinen ausführbaren Block", idx)
+        }
     }
 
-    async fn stream_child(&self, child: &mut tokio::process::Child, prefix: &str) -> Result<()> {
-        let stdout = child.stdout.take().unwrap();
-        let stderr = child.stderr.take().unwrap();
+    async fn run_shell(&self, step: &Step, spec: &ShellSpec, idx: usize) -> Result<StepOutcome> {
+        if let Some(host) = &step.delegate_to {
+            let cmd = match (&spec.command, &spec.script) {
+                (Some(c), None) => self.render(c, "shell.command")?,
+                _ => anyhow::bail!(
+                    "delegate_to: shell braucht command, script wird nicht übertragen"
+                ),
+            };
+            if spec.creates.is_some() || spec.removes.is_some() {
+                anyhow::bail!("delegate_to: creates/removes prüfen nur lokal");
+            }
+            if spec.stdin_from.is_some() {
+                anyhow::bail!("delegate_to: stdin_from wird nicht übertragen");
+            }
+            // ohne `shell` nimmt der Remote-Host seine Login-Shell, wie bei `ssh`
+            let cmd = match &spec.shell {
+                Some(sh) => {
+                    let (prg, args) = shell_argv(Some(sh), &cmd)?;
+                    join_argv(&prg, &args)
+                }
+                None => cmd,
+            };
+            let call =
+                self.delegate(step, host, cmd, &spec.env, spec.cwd.as_ref(), &spec.output)?;
+            return self.run_remote(step, idx, "shell", call, true).await;
+        }
+        let (prg, args, shown, script) = match (&spec.command, &spec.script) {
+            (Some(c), None) => {
+                let cmd_str = self.render(c, "shell.command")?;
+                let (prg, args) = shell_argv(spec.shell.as_deref(), &cmd_str)?;
+                (prg, args, cmd_str, None)
+            }
+            (None, Some(s)) => {
+                let script = self.render(s, "shell.script")?;
+                let path = Path::new(&self.opts.tmpdir).join(format!("step-{}.script", idx + 1));
+                let mut parts = spec
+                    .executable
+                    .as_deref()
+                    .unwrap_or(DEFAULT_INTERPRETER)
+                    .split_whitespace()
+                    .map(str::to_string);
+                let prg = parts.next().context("leere executable-Angabe")?;
+                let mut args = parts.collect::<Vec<_>>();
+                args.push(path.to_string_lossy().into_owned());
+                let shown = format!("{} <script, {} Zeilen>", prg, script.lines().count());
+                (prg, args, shown, Some((path, script)))
+            }
+            _ => anyhow::bail!("shell braucht genau eines von command oder script"),
+        };
+        let (prg, args) = self.apply_become(step, prg, args)?;
+
+        let (declared, env) = self.step_env(step, &spec.env)?;
+        let cwd = self.resolve_cwd(spec.cwd.as_ref())?;
+        self.print_header(step, idx, "shell", &shown);
+        let guard = self.guard_skip("shell", spec.creates.as_ref(), spec.removes.as_ref(), &cwd)?;
+        if let Some(skipped) = guard {
+            return Ok(skipped);
+        }
 
-        let mut out_reader = BufReader::new(stdout).lines();
-        let mut err_reader = BufReader::new(stderr).lines();
+        if self.dry_run(step) {
+            let argv = std::iter::once(prg).chain(args).collect::<Vec<_>>();
+            self.print_preview(step, &argv, &cwd, &declared);
+            if let (Some((_, script)), false) = (&script, step.no_log) {
+                println!("[dry-run] script:\n{}", script);
+            }
+            return Ok(StepOutcome::default());
+        }
+        if self.check_skips(step) {
+            return Ok(self.check_skipped());
+        }
 
-        let prefix_owned = prefix.to_string();
-        let prefix_owned2 = prefix.to_string();
+        if let Some((path, content)) = &script {
+            std::fs::write(path, content)
+                .with_context(|| format!("script nach {} schreiben", path.display()))?;
+        }
+        self.trace_spawn(step, &prg, &args, &cwd, &declared);
+        let files = self.open_outputs(&spec.output, &cwd)?;
+        let stdin = self.stdin_from(spec.stdin_from.as_ref())?;
+        let res = async {
+            let mut cmd = Command::new(&prg);
+            if stdin.is_some() {
+                cmd.stdin(std::process::Stdio::piped());
+            }
+            let mut child = cmd
+                .args(&args)
+                .env_clear()
+                .envs(env)
+                .current_dir(&cwd)
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| spawn_error(e, &prg, SHELL_HINT))?;
+            feed_stdin(&mut child, stdin);
+            self.stream_child(
+                &mut child,
+                step,
+                self.line_prefix(step, idx, "shell"),
+                idx,
+                files,
+                true,
+            )
+            .await
+        }
+        .await;
+        // auch nach Fehlern aufräumen; der Rest des Scratch-Verzeichnisses folgt am Ende
+        if let Some((path, _)) = &script {
+            let _ = std::fs::remove_file(path);
+        }
+        res
+    }
 
-        let out_task = tokio::spawn(async move {
-            while let Ok(Some(line)) = out_reader.next_line().await {
-                println!("[{}][out] {}", prefix_owned, line);
+    async fn run_exec(&self, step: &Step, spec: &ExecSpec, idx: usize) -> Result<StepOutcome> {
+        let cmd = self.render(&spec.cmd, "exec.cmd")?;
+        if let Some(host) = &step.delegate_to {
+            if spec.creates.is_some() || spec.removes.is_some() {
+                anyhow::bail!("delegate_to: creates/removes prüfen nur lokal");
             }
-        });
-        let err_task = tokio::spawn(async move {
-            while let Ok(Some(line)) = err_reader.next_line().await {
-                eprintln!("[{}][err] {}", prefix_owned2, line);
+            if spec.stdin_from.is_some() {
+                anyhow::bail!("delegate_to: stdin_from wird nicht übertragen");
             }
-        });
+            // args_file wird lokal gelesen
+            let base = self.opts.workdir.clone().unwrap_or_else(|| ".".into());
+            let args = self.exec_args(spec, &base)?;
+            let call = self.delegate(
+                step,
+                host,
+                join_argv(&cmd, &args),
+                &spec.env,
+                spec.cwd.as_ref(),
+                &spec.output,
+            )?;
+            return self.run_remote(step, idx, "exec", call, true).await;
+        }
+        let cwd = self.resolve_cwd(spec.cwd.as_ref())?;
+        let args = self.exec_args(spec, &cwd)?;
+        let (cmd, args) = self.apply_become(step, cmd, args)?;
+        let (declared, env) = self.step_env(step, &spec.env)?;
+        let line = join_argv(&cmd, &args);
+        self.print_header(step, idx, "exec", &line);
+        let guard = self.guard_skip("exec", spec.creates.as_ref(), spec.removes.as_ref(), &cwd)?;
+        if let Some(skipped) = guard {
+            return Ok(skipped);
+        }
 
-        let status = child.wait().await?;
-        let _ = tokio::join!(out_task, err_task);
-        if !status.success() {
-            anyhow::bail!("Prozess endete mit Status {}", status);
+        if self.dry_run(step) {
+            let argv = std::iter::once(cmd).chain(args).collect::<Vec<_>>();
+            self.print_preview(step, &argv, &cwd, &declared);
+            return Ok(StepOutcome::default());
         }
-        Ok(())
+        if self.check_skips(step) {
+            return Ok(self.check_skipped());
+        }
+
+        self.trace_spawn(step, &cmd, &args, &cwd, &declared);
+        let files = self.open_outputs(&spec.output, &cwd)?;
+        let stdin = self.stdin_from(spec.stdin_from.as_ref())?;
+        let mut command = Command::new(&cmd);
+        if stdin.is_some() {
+            command.stdin(std::process::Stdio::piped());
+        }
+        let mut child = command
+            .args(&args)
+            .env_clear()
+            .envs(env)
+            .current_dir(&cwd)
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| spawn_error(e, &cmd, EXEC_HINT))?;
+        feed_stdin(&mut child, stdin);
+
+        self.stream_child(
+            &mut child,
+            step,
+            self.line_prefix(step, idx, "exec"),
+            idx,
+            files,
+            true,
+        )
+        .await
+    }
+
+    /// Gesammeltes stdout des Schritts `name` für `stdin_from`, aus `steps.<name>`.
+    fn stdin_from(&self, name: Option<&String>) -> Result<Option<String>> {
+        let Some(name) = name else {
+            return Ok(None);
+        };
+        let stdout = self.with_ctx(|ctx| {
+            ctx.get("steps")
+                .and_then(|s| s.get(name.as_str()))
+                .map(|r| r.get("stdout").and_then(Value::as_str).map(String::from))
+        });
+        match stdout {
+            Some(Some(out)) => Ok(Some(out)),
+            Some(None) => anyhow::bail!("stdin_from: steps.{} hat kein stdout", name),
+            None => anyhow::bail!(
+                "stdin_from: kein Ergebnis für Schritt '{}' (später oder ohne depends_on?)",
+                name
+            ),
+        }
+    }
+
+    /// Lädt per `curl` in eine temporäre Datei neben `dest`, prüft die
+    /// Prüfsumme und ersetzt `dest` nur bei geändertem Inhalt. Stimmt die
+    /// Prüfsumme einer vorhandenen Datei, wird nichts heruntergeladen.
+    async fn run_download(
+        &self,
+        step: &Step,
+        spec: &DownloadSpec,
+        idx: usize,
+    ) -> Result<StepOutcome> {
+        let url = self.render(&spec.url, "download.url")?;
+        let dest = self.in_workdir(self.render_path(&spec.dest, "download.dest")?);
+        let checksum = match &spec.checksum {
+            Some(c) => Some(Checksum::parse(&self.render(c, "download.checksum")?)?),
+            None => None,
+        };
+        let path = Path::new(&dest);
+        self.print_header(step, idx, "download", &format!("{} -> {}", url, dest));
+
+        if let Some(sum) = &checksum
+            && sum.matches(path)?
+        {
+            println!("[download] {} vorhanden, Prüfsumme stimmt", dest);
+            return Ok(StepOutcome::default());
+        }
+        if self.opts.dry_run {
+            println!("[dry-run] download: {} -> {}", url, dest);
+            return Ok(StepOutcome::default());
+        }
+        if self.opts.check {
+            println!("[check] würde herunterladen: {}", dest);
+            return Ok(StepOutcome {
+                changed: true,
+                ..Default::default()
+            });
+        }
+
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Verzeichnis {} anlegen", parent.display()))?;
+        }
+        let tmp = tmp_path(path);
+        let fetched = async {
+            let mut child = Command::new("curl")
+                .args(["-fsSL", "-o"])
+                .arg(&tmp)
+                .arg(&url)
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| spawn_error(e, "curl", CURL_HINT))?;
+            self.stream_child(
+                &mut child,
+                step,
+                self.line_prefix(step, idx, "download"),
+                idx,
+                OutputFiles::default(),
+                false,
+            )
+            .await?;
+            if let Some(sum) = &checksum {
+                let got = checksum::digest(sum.algo, &tmp)?;
+                if got != sum.hex {
+                    anyhow::bail!(
+                        "Prüfsumme von {} stimmt nicht: erwartet {}, erhalten {}",
+                        url,
+                        sum.hex,
+                        got
+                    );
+                }
+            }
+            // ohne Prüfsumme entscheidet der Inhalt über `changed`
+            let same = path.is_file()
+                && checksum::digest(Algo::Sha256, path)? == checksum::digest(Algo::Sha256, &tmp)?;
+            Ok(same)
+        }
+        .await;
+        let same = match fetched {
+            Ok(same) => same,
+            Err(e) => {
+                let _ = std::fs::remove_file(&tmp);
+                return Err(e);
+            }
+        };
+        if same {
+            std::fs::remove_file(&tmp)?;
+            println!("[download] {} unverändert", dest);
+        } else {
+            std::fs::rename(&tmp, path).with_context(|| format!("{} ersetzen", dest))?;
+            println!("[download] {} geschrieben", dest);
+        }
+        Ok(StepOutcome {
+            changed: !same,
+            ..Default::default()
+        })
+    }
+
+    /// Argumente aus `args` (Liste oder zerlegter String) und `args_file`
+    /// (eine Zeile pro Argument, unverändert, leere Zeilen zählen nicht).
+    fn exec_args(&self, spec: &ExecSpec, cwd: &str) -> Result<Vec<String>> {
+        let mut args = match &spec.args {
+            Args::List(list) => list
+                .iter()
+                .enumerate()
+                .map(|(i, a)| self.render(a, &format!("exec.args[{}]", i)))
+                .collect::<Result<Vec<_>>>()?,
+            Args::Line(line) => {
+                crate::words::split(&self.render(line, "exec.args")?).context("exec.args")?
+            }
+        };
+        if let Some(raw) = &spec.args_file {
+            let path = Path::new(cwd).join(self.render_path(raw, "exec.args_file")?);
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("exec.args_file {} lesen", path.display()))?;
+            args.extend(
+                content
+                    .lines()
+                    .filter(|l| !l.trim().is_empty())
+                    .map(str::to_string),
+            );
+        }
+        Ok(args)
+    }
+
+    async fn run_conf(&self, step: &Step, spec: &ConfSpec, idx: usize) -> Result<StepOutcome> {
+        let template_file = match &spec.template_file {
+            Some(f) => Some(self.render(f, "conf.template_file")?),
+            None => None,
+        };
+        // from_command läuft erst nach dem Schrittkopf, siehe `conf_from_command`
+        let content = match (&spec.template, &template_file, &spec.from_command) {
+            (Some(t), None, None) if spec.raw => Some(t.clone()),
+            (Some(t), None, None) => Some(self.render(t, "conf.template")?),
+            (None, Some(f), None) => Some({
+                let path = self.template_path(f);
+                let src = std::fs::read_to_string(&path)
+                    .with_context(|| format!("template_file {} lesen", path.display()))?;
+                if spec.raw {
+                    src
+                } else {
+                    self.render(&src, "conf.template_file")?
+                }
+            }),
+            (None, None, Some(_)) => None,
+            _ => anyhow::bail!(
+                "conf braucht genau eines von template, template_file oder from_command"
+            ),
+        };
+        if let Some(target) = &spec.remote {
+            return self
+                .run_remote_conf(step, spec, target, idx, content, template_file.as_deref())
+                .await;
+        }
+        let dest = self.conf_dest(&spec.dest, template_file.as_deref())?;
+        let path = Path::new(&dest);
+        // Modus vor dem Schreiben prüfen, damit ein Tippfehler den Lauf stoppt
+        let (umask, dir_mode) = conf_modes(spec)?;
+        let mode = match (&spec.mode, umask) {
+            (Some(m), _) => Some(
+                parse_mode(m, current_mode(path))
+                    .with_context(|| format!("conf mode für {}", dest))?,
+            ),
+            (None, Some(u)) if !path.exists() => Some(0o666 & !u),
+            (None, _) => None,
+        };
+        self.print_header(step, idx, "conf", &format!("write {}", dest));
+        let content = match content {
+            Some(c) => c,
+            None => match self.conf_from_command(step, spec, idx).await? {
+                Some(c) => c,
+                None => return Ok(self.conf_deferred(&dest)),
+            },
+        };
+
+        if self.opts.dry_run {
+            println!("[dry-run] dest: {}", dest);
+            if let Some(m) = mode {
+                println!("[dry-run] mode: {:04o}", m);
+            }
+            if self.wants_diff(step, spec) {
+                self.print_diff(path, &content);
+            } else if !step.no_log {
+                println!("Content preview:\n{}", content);
+            }
+            return Ok(StepOutcome::default());
+        }
+
+        if spec.rollback_on_failure && !spec.backup {
+            anyhow::bail!("rollback_on_failure erfordert backup: true");
+        }
+        let changed = std::fs::read_to_string(path).ok().as_deref() != Some(content.as_str())
+            || mode.is_some_and(|m| !path.exists() || current_mode(path) != m);
+        if self.wants_diff(step, spec) {
+            self.print_diff(path, &content);
+        }
+        if self.opts.check {
+            // nur vergleichen, nichts schreiben oder validieren
+            let state = if changed {
+                "würde geändert"
+            } else {
+                "unverändert"
+            };
+            println!("[check] {}: {}", dest, state);
+            return Ok(StepOutcome {
+                changed,
+                ..Default::default()
+            });
+        }
+
+        if let Some(parent) = path.parent() {
+            create_parents(parent, dir_mode)?;
+        }
+        match spec.validate.as_deref() {
+            Some(v) if v.contains("%s") => {
+                // erst prüfen, dann atomar an den Zielort verschieben
+                let tmp = tmp_path(path);
+                std::fs::write(&tmp, content)?;
+                if let Err(e) = self.validate_conf(step, idx, v, &tmp).await {
+                    let _ = std::fs::remove_file(&tmp);
+                    return Err(e.context(format!("validate für {} fehlgeschlagen", dest)));
+                }
+                self.backup_if_needed(path, spec, changed)?;
+                std::fs::rename(&tmp, &dest).context("conf rename")?;
+            }
+            Some(v) => {
+                // ohne `%s` prüft das Kommando die installierte Datei
+                let existed = path.exists();
+                let bak = self.backup_if_needed(path, spec, changed)?;
+                std::fs::write(&dest, content)?;
+                if let Err(e) = self.validate_conf(step, idx, v, path).await {
+                    if spec.rollback_on_failure {
+                        rollback(path, bak.as_deref(), existed)?;
+                    }
+                    return Err(e.context(format!("validate für {} fehlgeschlagen", dest)));
+                }
+            }
+            None => {
+                self.backup_if_needed(path, spec, changed)?;
+                std::fs::write(&dest, content)?;
+            }
+        }
+        #[cfg(unix)]
+        if let Some(m) = mode {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&dest, std::fs::Permissions::from_mode(m))?;
+        }
+        #[cfg(not(unix))]
+        if mode.is_some() {
+            eprintln!("[conf] mode wird auf dieser Plattform ignoriert");
+        }
+        Ok(StepOutcome {
+            changed,
+            ..Default::default()
+        })
+    }
+
+    /// Ändert nur die betroffene Zeile und schreibt die Datei nur, wenn sich
+    /// dadurch etwas ändert; Dry-Run und `--check` lesen die Datei trotzdem.
+    fn run_lineinfile(
+        &self,
+        step: &Step,
+        spec: &LineInFileSpec,
+        idx: usize,
+    ) -> Result<StepOutcome> {
+        let dest = self.in_workdir(self.render_path(&spec.path, "lineinfile.path")?);
+        let line = match &spec.line {
+            Some(l) => Some(self.render(l, "lineinfile.line")?),
+            None => None,
+        };
+        let regex = |raw: &str, field: &str| -> Result<regex::Regex> {
+            let src = self.render(raw, field)?;
+            regex::Regex::new(&src).with_context(|| format!("{}: ungültige Regex", field))
+        };
+        let regexp = match &spec.regexp {
+            Some(r) => Some(regex(r, "lineinfile.regexp")?),
+            None => None,
+        };
+        let anchor = match (spec.insertafter.as_deref(), spec.insertbefore.as_deref()) {
+            (Some(_), Some(_)) => anyhow::bail!("insertafter und insertbefore schließen sich aus"),
+            (Some("EOF"), None) | (None, None) => Anchor::Eof,
+            (None, Some("BOF")) => Anchor::Bof,
+            (Some(a), None) => Anchor::After(regex(a, "lineinfile.insertafter")?),
+            (None, Some(b)) => Anchor::Before(regex(b, "lineinfile.insertbefore")?),
+        };
+        match spec.state {
+            LineState::Present if line.is_none() => {
+                anyhow::bail!("lineinfile mit state: present braucht line")
+            }
+            LineState::Absent if line.is_none() && regexp.is_none() => {
+                anyhow::bail!("lineinfile mit state: absent braucht line oder regexp")
+            }
+            _ => {}
+        }
+        let verb = match spec.state {
+            LineState::Present => "ensure",
+            LineState::Absent => "remove",
+        };
+        self.print_header(
+            step,
+            idx,
+            "lineinfile",
+            &format!("{} line in {}", verb, dest),
+        );
+
+        let path = Path::new(&dest);
+        let old = match std::fs::read_to_string(path) {
+            Ok(c) => Some(c),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("{} lesen", dest)),
+        };
+        let (edit, new) = match (spec.state, &old) {
+            (LineState::Present, None) if !spec.create => {
+                anyhow::bail!("{} existiert nicht (create: true legt die Datei an)", dest)
+            }
+            (LineState::Present, _) => lineinfile::ensure_present(
+                old.as_deref().unwrap_or_default(),
+                line.as_deref().unwrap_or_default(),
+                regexp.as_ref(),
+                &anchor,
+            ),
+            (LineState::Absent, None) => (Edit::Unchanged, None),
+            (LineState::Absent, Some(c)) => {
+                lineinfile::ensure_absent(c, line.as_deref(), regexp.as_ref())
+            }
+        };
+        let state = match edit {
+            Edit::Unchanged => "unverändert".to_string(),
+            Edit::Inserted => "Zeile eingefügt".to_string(),
+            Edit::Replaced => "Zeile ersetzt".to_string(),
+            Edit::Removed(n) => format!("{} Zeile(n) entfernt", n),
+        };
+        let Some(content) = new else {
+            println!("[lineinfile] {}: {}", dest, state);
+            return Ok(StepOutcome::default());
+        };
+        if !step.no_log && spec.diff.unwrap_or(self.opts.diff) {
+            self.print_diff(path, &content);
+        }
+        if self.opts.dry_run || self.opts.check {
+            let mode = if self.opts.dry_run {
+                "dry-run"
+            } else {
+                "check"
+            };
+            println!("[{}] {}: würde geändert ({})", mode, dest, state);
+            return Ok(StepOutcome {
+                changed: self.opts.check,
+                ..Default::default()
+            });
+        }
+
+        if spec.backup && old.is_some() {
+            let bak = self.backup_file(
+                path,
+                "lineinfile",
+                spec.backup_dir.as_ref(),
+                spec.backup_keep,
+            )?;
+            println!("[lineinfile] backup -> {}", bak.display());
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, content).with_context(|| format!("{} schreiben", dest))?;
+        println!("[lineinfile] {}: {}", dest, state);
+        Ok(StepOutcome {
+            changed: true,
+            ..Default::default()
+        })
+    }
+
+    /// `conf` mit `remote`: lokal gerendert, per ssh über ein Shell-Skript
+    /// (siehe `remote::RemoteWrite`) geschrieben, der Inhalt kommt über stdin.
+    async fn run_remote_conf(
+        &self,
+        step: &Step,
+        spec: &ConfSpec,
+        target: &SshTarget,
+        idx: usize,
+        content: Option<String>,
+        template_file: Option<&str>,
+    ) -> Result<StepOutcome> {
+        if spec.backup_keep.is_some() {
+            anyhow::bail!("backup_keep wird mit remote nicht unterstützt");
+        }
+        if spec.rollback_on_failure && !spec.backup {
+            anyhow::bail!("rollback_on_failure erfordert backup: true");
+        }
+        let conn = self.resolve_ssh(target)?;
+        let dest = self.render(&spec.dest, "conf.dest")?;
+        let mode = match &spec.mode {
+            Some(m) => {
+                Some(crate::mode::chmod_arg(m).with_context(|| format!("conf mode für {}", dest))?)
+            }
+            None => None,
+        };
+        let (umask, dir_mode) = conf_modes(spec)?;
+        let backup_dir = match (&spec.backup, &spec.backup_dir) {
+            (true, Some(d)) => Some(self.render(d, "conf.backup_dir")?),
+            (true, None) => Some(String::new()),
+            (false, _) => None,
+        };
+        let write = RemoteWrite {
+            dest: dest.clone(),
+            file_name: template_file
+                .and_then(|f| Path::new(f).file_name())
+                .map(|n| n.to_string_lossy().into_owned()),
+            mode,
+            dir_mode,
+            new_file_mode: umask.map(|u| 0o666 & !u),
+            backup_dir,
+            stamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            validate: spec
+                .validate
+                .as_ref()
+                .map(|v| self.render(v, "conf.validate"))
+                .transpose()?,
+            rollback: spec.rollback_on_failure,
+            check: self.opts.check,
+        };
+        let shown = format!("write {}@{}:{}", conn.user, conn.host, dest);
+        self.print_header(step, idx, "conf", &shown);
+        let content = match content {
+            Some(c) => c,
+            None => match self.conf_from_command(step, spec, idx).await? {
+                Some(c) => c,
+                None => return Ok(self.conf_deferred(&format!("{}:{}", conn.host, dest))),
+            },
+        };
+
+        if self.opts.dry_run {
+            println!("[dry-run] dest: {}:{}", conn.host, dest);
+            if let Some(m) = &write.mode {
+                println!("[dry-run] mode: {}", m);
+            }
+            if !step.no_log && !self.wants_diff(step, spec) {
+                println!("Content preview:\n{}", content);
+            }
+        }
+        if self.wants_diff(step, spec) {
+            let cat = format!(
+                "cat -- {} 2>/dev/null || true",
+                shell_escape::escape(dest.as_str().into())
+            );
+            let old = self.ssh_output(step, &conn, &cat, None).await?;
+            let diff = crate::diff::unified(&format!("{}:{}", conn.host, dest), &old, &content);
+            if diff.is_empty() {
+                println!("[diff] {}:{}: keine Änderungen", conn.host, dest);
+            } else {
+                print!("{}", diff);
+            }
+        }
+        if self.opts.dry_run {
+            return Ok(StepOutcome::default());
+        }
+
+        let out = match self
+            .ssh_output(step, &conn, &write.script(), Some(&content))
+            .await
+        {
+            Ok(out) => out,
+            Err(e) => {
+                // Backup- und Rollback-Meldungen des Skripts trotzdem zeigen
+                if let Some(pf) = e.downcast_ref::<ProcessFailed>() {
+                    for line in pf.stdout.lines().filter(|l| !l.starts_with(CHANGED_MARKER)) {
+                        println!("{}", line);
+                    }
+                }
+                return Err(e.context(format!("conf auf {}:{} schreiben", conn.host, dest)));
+            }
+        };
+        let mut changed = true;
+        for line in out.lines() {
+            match line.strip_prefix(CHANGED_MARKER) {
+                Some(flag) => changed = flag == "1",
+                None => println!("{}", line),
+            }
+        }
+        if self.opts.check {
+            let state = if changed {
+                "würde geändert"
+            } else {
+                "unverändert"
+            };
+            println!("[check] {}:{}: {}", conn.host, dest, state);
+        }
+        Ok(StepOutcome {
+            changed,
+            ..Default::default()
+        })
+    }
+
+    /// Führt eine Kommandozeile per ssh aus (mit `become` umschlossen) und liefert
+    /// stdout; `stdin` wird parallel zum Lesen übergeben, beides innerhalb des
+    /// Schritt-Timeouts. Scheitert das Kommando, steht stderr im Fehler.
+    async fn ssh_output(
+        &self,
+        step: &Step,
+        conn: &SshConn,
+        remote: &str,
+        stdin: Option<&str>,
+    ) -> Result<String> {
+        let become_ = self.resolve_become(step)?;
+        let remote = match &become_ {
+            Some(b) => b.wrap_remote(remote),
+            None => remote.to_string(),
+        };
+        let stdin = become_
+            .as_ref()
+            .and_then(Become::stdin_prefix)
+            .unwrap_or_default()
+            + stdin.unwrap_or_default();
+        let mut argv = conn.base_args();
+        argv.push(remote);
+        let mut cmd = Command::new(&argv[0]);
+        if let Some(pw) = &conn.password {
+            cmd.env("SSHPASS", pw);
+        }
+        let mut child = cmd
+            .args(&argv[1..])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| spawn_error(e, &argv[0], ssh_hint(&argv[0])))?;
+        // gleichzeitig schreiben und lesen: liest die Gegenseite nicht weiter,
+        // hängt sonst das Schreiben an der vollen Pipe, auch am Timeout vorbei
+        let writer = child.stdin.take().map(|mut pipe| {
+            tokio::spawn(async move {
+                use tokio::io::AsyncWriteExt;
+                pipe.write_all(stdin.as_bytes()).await
+            })
+        });
+        let run = async {
+            let output = child.wait_with_output().await?;
+            let written = match writer {
+                Some(w) => w.await?,
+                None => Ok(()),
+            };
+            anyhow::Ok((output, written))
+        };
+        let (output, written) = match self.timeout(step) {
+            Some(t) => tokio::time::timeout(t, run)
+                .await
+                .map_err(|_| StepTimeout(t.as_secs()))??,
+            None => run.await?,
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        if !output.status.success() {
+            let msg = become_
+                .as_ref()
+                .and_then(|b| b.explain_failure(&conn.host, &stderr))
+                .unwrap_or_else(|| stderr.trim().to_string());
+            let err = anyhow::Error::from(ProcessFailed::new(output.status, stdout, stderr));
+            return Err(if msg.is_empty() {
+                err
+            } else {
+                err.context(msg)
+            });
+        }
+        written.context("stdin an ssh übergeben")?;
+        Ok(stdout)
+    }
+
+    /// Führt `conf.from_command` über die Default-Shell im cwd und mit der
+    /// Umgebung des Schritts aus; stdout ist der Dateiinhalt, stderr wird
+    /// angezeigt. Ein Exit-Code ≠ 0 lässt den Schritt scheitern. Im Dry-Run und
+    /// mit `--check` ohne `check: true` läuft das Kommando nicht (`None`).
+    async fn conf_from_command(
+        &self,
+        step: &Step,
+        spec: &ConfSpec,
+        idx: usize,
+    ) -> Result<Option<String>> {
+        let Some(raw) = &spec.from_command else {
+            return Ok(None);
+        };
+        let cmd = self.render(raw, "conf.from_command")?;
+        if self.opts.dry_run {
+            println!("[dry-run] from_command: {}", cmd);
+            return Ok(None);
+        }
+        if self.check_skips(step) {
+            return Ok(None);
+        }
+        let (prg, args) = shell_argv(None, &cmd)?;
+        let (_, env) = self.step_env(step, &Default::default())?;
+        println!(
+            "[conf] from_command: {}",
+            if step.no_log { "(no_log)" } else { &cmd }
+        );
+        let child = Command::new(&prg)
+            .args(&args)
+            .env_clear()
+            .envs(env)
+            .current_dir(self.resolve_cwd(None)?)
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let output = match self.timeout(step) {
+            Some(t) => tokio::time::timeout(t, child)
+                .await
+                .map_err(|_| StepTimeout(t.as_secs()))?,
+            None => child.await,
+        }
+        .map_err(|e| spawn_error(e, &prg, SHELL_HINT))?;
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        if !step.no_log {
+            let prefix = format!("{}[from_command]", self.line_prefix(step, idx, "conf"));
+            for line in stderr.lines() {
+                eprintln!("{}[err] {}", prefix, line);
+            }
+        }
+        if !output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+            return Err(
+                anyhow::Error::from(ProcessFailed::new(output.status, stdout, stderr))
+                    .context("conf.from_command"),
+            );
+        }
+        String::from_utf8(output.stdout)
+            .map(Some)
+            .context("conf.from_command: Ausgabe ist kein UTF-8")
+    }
+
+    /// Ergebnis, wenn `from_command` nicht laufen durfte: Dry-Run bzw. `--check`.
+    fn conf_deferred(&self, dest: &str) -> StepOutcome {
+        if self.opts.dry_run {
+            println!("[dry-run] dest: {}", dest);
+            return StepOutcome::default();
+        }
+        self.check_skipped()
+    }
+
+    /// Schritt-`diff` vor `--diff`; `no_log` unterdrückt den Diff immer.
+    fn wants_diff(&self, step: &Step, spec: &ConfSpec) -> bool {
+        !step.no_log && spec.diff.unwrap_or(self.opts.diff)
+    }
+
+    /// Diff zwischen der bestehenden Datei (fehlend = leer) und dem neuen Inhalt.
+    fn print_diff(&self, path: &Path, content: &str) {
+        let old = std::fs::read_to_string(path).unwrap_or_default();
+        let diff = crate::diff::unified(&path.display().to_string(), &old, content);
+        if diff.is_empty() {
+            println!("[diff] {}: keine Änderungen", path.display());
+        } else {
+            print!("{}", diff);
+        }
+    }
+
+    /// Rendert `dest`; ein Verzeichnis (oder `/` am Ende) bekommt wie bei `cp`
+    /// den Dateinamen von `template_file` angehängt.
+    fn conf_dest(&self, raw: &str, template_file: Option<&str>) -> Result<String> {
+        let dest = self.in_workdir(self.render_path(raw, "conf.dest")?);
+        if !dest.ends_with('/') && !Path::new(&dest).is_dir() {
+            return Ok(dest);
+        }
+        let name = template_file
+            .and_then(|f| Path::new(f).file_name())
+            .with_context(|| {
+                format!(
+                    "dest {} ist ein Verzeichnis, ohne template_file gibt es keinen Dateinamen",
+                    dest
+                )
+            })?;
+        Ok(Path::new(&dest).join(name).to_string_lossy().into_owned())
+    }
+
+    /// `template_file` relativ zu `--workdir`, sonst zum Verzeichnis des Playbooks.
+    fn template_path(&self, file: &str) -> std::path::PathBuf {
+        let base = self
+            .opts
+            .workdir
+            .as_ref()
+            .or(self.opts.playbook_dir.as_ref());
+        match base {
+            Some(b) => Path::new(b).join(file),
+            None => file.into(),
+        }
+    }
+
+    /// Verbindungsparameter: Schritt → `defaults.ssh` → eingebauter Default,
+    /// alle Felder gegen den Kontext gerendert.
+    fn resolve_ssh(&self, spec: &SshTarget) -> Result<SshConn> {
+        self.resolve_ssh_in(spec, &spec.host, &self.defaults.ssh)
+    }
+
+    /// ssh-Binary: `--ssh-program` → `program` aus `d` → `ssh`.
+    fn ssh_program(&self, d: &SshDefaults) -> Result<String> {
+        match self.opts.ssh_program.as_ref().or(d.program.as_ref()) {
+            Some(p) => crate::paths::expand(p).context("ssh.program"),
+            None => Ok("ssh".into()),
+        }
+    }
+
+    /// Sucht vor dem ersten Schritt die Programme, ohne die Schritte sicher
+    /// scheitern: ssh (und `sshpass`) für `ssh`, `conf.remote` und `delegate_to`
+    /// sowie die Shell bzw. den Interpreter lokaler shell-Schritte. Templated
+    /// Angaben und `exec.cmd` (kann ein früherer Schritt erzeugen) prüft erst
+    /// der Start. Liefert eine Meldung je fehlendem Programm.
+    pub fn missing_programs<'a>(&self, steps: impl IntoIterator<Item = &'a Step>) -> Vec<String> {
+        let mut need = BTreeMap::new();
+        for step in steps {
+            let target = match (&step.ssh, &step.conf) {
+                (Some(s), _) => Some(&s.target),
+                (None, Some(c)) => c.remote.as_ref(),
+                _ => None,
+            };
+            if target.is_some() || step.delegate_to.is_some() {
+                let group = target.and_then(|t| self.inventory.get(&t.host));
+                let d = match group {
+                    Some(g) => g.ssh.or(&self.defaults.ssh),
+                    None => self.defaults.ssh.clone(),
+                };
+                if let Ok(prg) = self.ssh_program(&d) {
+                    need.insert(prg, SSH_HINT);
+                }
+                if target
+                    .and_then(|t| t.auth.as_ref())
+                    .is_some_and(|a| a.kind == "password")
+                {
+                    need.insert("sshpass".into(), SSHPASS_HINT);
+                }
+            }
+            if let (Some(sh), None) = (&step.shell, &step.delegate_to) {
+                let line = match &sh.script {
+                    Some(_) => sh.executable.as_deref().unwrap_or(DEFAULT_INTERPRETER),
+                    None => sh.shell.as_deref().unwrap_or(DEFAULT_SHELL),
+                };
+                if let Some(prg) = line.split_whitespace().next() {
+                    need.insert(prg.to_string(), SHELL_HINT);
+                }
+            }
+            if step.download.is_some() {
+                need.insert("curl".into(), CURL_HINT);
+            }
+        }
+        need.into_iter()
+            .filter(|(prg, _)| !prg.contains("{{") && crate::paths::which(prg).is_none())
+            .map(|(prg, hint)| not_found(&prg, hint))
+            .collect()
+    }
+
+    /// `--preflight`: probiert `ssh ... true` gegen jedes Ziel aus `ssh`,
+    /// `conf.remote` und `delegate_to` (Gruppen je Host), gleiche Verbindungen
+    /// nur einmal, alle gleichzeitig. Liefert je Ziel die Bezeichnung und bei
+    /// Misserfolg den Grund. Ziele, die sich vor dem Lauf nicht rendern lassen
+    /// (z. B. mit `{{ item }}`), werden mit Hinweis übergangen.
+    pub async fn preflight<'a>(
+        &self,
+        steps: impl IntoIterator<Item = &'a Step>,
+    ) -> Vec<(String, Option<String>)> {
+        let mut conns = BTreeMap::new();
+        let mut add = |conn: Result<SshConn>, what: &str| match conn {
+            Ok(c) => {
+                conns.entry(c.base_args()).or_insert(c);
+            }
+            Err(e) => eprintln!("[preflight] {} übergangen: {:#}", what, e),
+        };
+        for step in steps {
+            if let Some(spec) = &step.ssh {
+                match self.inventory.get(&spec.target.host) {
+                    Some(group) => {
+                        for member in &group.hosts {
+                            add(self.member_conn(&spec.target, group, member), "ssh.host");
+                        }
+                    }
+                    None => add(self.resolve_ssh(&spec.target), "ssh.host"),
+                }
+            }
+            if let Some(target) = step.conf.as_ref().and_then(|c| c.remote.as_ref()) {
+                add(self.resolve_ssh(target), "conf.remote");
+            }
+            if let Some(host) = &step.delegate_to {
+                let target = SshTarget {
+                    host: host.clone(),
+                    user: None,
+                    port: None,
+                    proxy_jump: None,
+                    auth: None,
+                    check_host: None,
+                    agent_forward: false,
+                };
+                add(self.resolve_ssh(&target), "delegate_to");
+            }
+        }
+        let probes = conns.into_values().map(|conn| async move {
+            let label = match &conn.port {
+                Some(port) => format!("{}@{}:{}", conn.user, conn.host, port),
+                None => format!("{}@{}", conn.user, conn.host),
+            };
+            (label, probe(&conn).await.err().map(|e| format!("{:#}", e)))
+        });
+        futures_util::future::join_all(probes).await
+    }
+
+    /// Wie `resolve_ssh`, aber mit `host` statt `spec.host` und `d` als Defaults.
+    fn resolve_ssh_in(&self, spec: &SshTarget, host: &str, d: &SshDefaults) -> Result<SshConn> {
+        let render = |v: Option<&String>, field: &str| -> Result<Option<String>> {
+            v.map(|s| self.render(s, field)).transpose()
+        };
+        let auth = spec.auth.as_ref();
+        // Key/Passwort: für openssh via ssh-Optionen; Passwort interaktiv wird vermieden
+        if let Some(a) = auth
+            && !matches!(a.kind.as_str(), "key" | "password" | "agent")
+        {
+            anyhow::bail!("unbekannte ssh auth.kind '{}' (key|password|agent)", a.kind);
+        }
+        if auth.is_some_and(|a| a.kind == "agent") && std::env::var_os("SSH_AUTH_SOCK").is_none() {
+            eprintln!("[ssh] auth.kind agent, aber SSH_AUTH_SOCK ist nicht gesetzt");
+        }
+        // bei `agent` wählt ein optionaler key_path die Identität im Agent aus
+        let render_key = |v: Option<&String>, field: &str| -> Result<Option<String>> {
+            v.map(|s| self.render_path(s, field)).transpose()
+        };
+        let key_path = match auth {
+            Some(a) if a.kind == "key" || a.kind == "agent" => render_key(
+                a.key_path.as_ref().or(d.key_path.as_ref()),
+                "ssh.auth.key_path",
+            )?,
+            Some(_) => None,
+            None => render_key(d.key_path.as_ref(), "defaults.ssh.key_path")?,
+        };
+        let password = match auth {
+            Some(a) if a.kind == "password" => render(a.password.as_ref(), "ssh.auth.password")?,
+            _ => None,
+        };
+        Ok(SshConn {
+            program: self.ssh_program(d)?,
+            host: self.render(host, "ssh.host")?,
+            user: render(spec.user.as_ref().or(d.user.as_ref()), "ssh.user")?
+                .unwrap_or_else(|| "root".into()),
+            port: render(spec.port.as_ref().or(d.port.as_ref()), "ssh.port")?,
+            key_path,
+            password,
+            proxy_jump: render(
+                spec.proxy_jump.as_ref().or(d.proxy_jump.as_ref()),
+                "ssh.proxy_jump",
+            )?,
+            check_host: spec.check_host.clone(),
+            agent_forward: spec.agent_forward,
+            connect_timeout: d.connect_timeout,
+        })
+    }
+
+    /// Führt das `validate`-Kommando mit `%s` = Pfad der temporären Datei aus,
+    /// bei `no_log` ohne es anzuzeigen.
+    async fn validate_conf(&self, step: &Step, idx: usize, tpl: &str, tmp: &Path) -> Result<()> {
+        let tmp_str = tmp.to_string_lossy();
+        let cmd = self
+            .render(tpl, "conf.validate")?
+            .replace("%s", &shell_escape::escape(tmp_str));
+        // wie der Header: bei no_log kann das Kommando Secrets enthalten
+        println!(
+            "[conf] validate: {}",
+            if step.no_log { "(no_log)" } else { &cmd }
+        );
+        let (prg, args) = shell_argv(None, &cmd)?;
+        let (_, env) = self.step_env(step, &Default::default())?;
+        let mut child = Command::new(&prg)
+            .args(&args)
+            .env_clear()
+            .envs(env)
+            .current_dir(self.resolve_cwd(None)?)
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| spawn_error(e, &prg, SHELL_HINT))?;
+        self.stream_child(
+            &mut child,
+            step,
+            format!("{}[validate]", self.line_prefix(step, idx, "conf")),
+            idx,
+            OutputFiles::default(),
+            false,
+        )
+        .await?;
+        Ok(())
+    }
+
+    fn backup_if_needed(
+        &self,
+        path: &Path,
+        spec: &ConfSpec,
+        changed: bool,
+    ) -> Result<Option<std::path::PathBuf>> {
+        if spec.backup && changed && path.exists() {
+            let bak = self.backup_file(path, "conf", spec.backup_dir.as_ref(), spec.backup_keep)?;
+            println!("[conf] backup -> {}", bak.display());
+            return Ok(Some(bak));
+        }
+        Ok(None)
+    }
+
+    /// Legt `<name>.<RFC3339>.bak` in `backup_dir` (default: neben `dest`) an
+    /// und behält höchstens `backup_keep` Backups.
+    fn backup_file(
+        &self,
+        path: &Path,
+        kind: &str,
+        backup_dir: Option<&String>,
+        backup_keep: Option<usize>,
+    ) -> Result<std::path::PathBuf> {
+        let dir = match backup_dir {
+            Some(d) => std::path::PathBuf::from(
+                self.in_workdir(self.render(d, &format!("{}.backup_dir", kind))?),
+            ),
+            None => path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| ".".into()),
+        };
+        std::fs::create_dir_all(&dir).context("backup_dir anlegen")?;
+        let name = path
+            .file_name()
+            .context("dest hat keinen Dateinamen")?
+            .to_string_lossy()
+            .into_owned();
+        let stamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let bak = dir.join(format!("{}.{}.bak", name, stamp));
+        std::fs::copy(path, &bak).context("backup copy")?;
+
+        if let Some(keep) = backup_keep {
+            let prefix = format!("{}.", name);
+            let mut old = std::fs::read_dir(&dir)?
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|n| n.starts_with(&prefix) && n.ends_with(".bak"))
+                .collect::<Vec<_>>();
+            // Zeitstempel sind UTC, lexikografisch = chronologisch
+            old.sort();
+            let excess = old.len().saturating_sub(keep);
+            for n in &old[..excess] {
+                std::fs::remove_file(dir.join(n)).context("altes Backup löschen")?;
+                println!("[{}] backup entfernt: {}", kind, n);
+            }
+        }
+        Ok(bak)
+    }
+
+    async fn run_ssh(&self, step: &Step, spec: &SshSpec, idx: usize) -> Result<StepOutcome> {
+        // Variante A: openssh crate, nutzt lokales ssh
+        let conn = match self.inventory.get(&spec.target.host) {
+            Some(group) => self.resolve_member(&spec.target, group)?,
+            None => self.resolve_ssh(&spec.target)?,
+        };
+        let (command, script) = match (&spec.command, &spec.script) {
+            (Some(c), None) => (self.render_quoted(c, "ssh.command")?, None),
+            (None, Some(s)) => {
+                let script = self.render(s, "ssh.script")?;
+                let words = spec
+                    .executable
+                    .as_deref()
+                    .unwrap_or(REMOTE_INTERPRETER)
+                    .split_whitespace()
+                    .map(|w| shell_escape::escape(w.into()).into_owned())
+                    .collect::<Vec<_>>();
+                if words.is_empty() {
+                    anyhow::bail!("ssh: leere executable-Angabe");
+                }
+                (format!("{} \"$t\"", words.join(" ")), Some(script))
+            }
+            _ => anyhow::bail!("ssh braucht genau eines von command oder script"),
+        };
+        // remote gibt es keine geerbte Umgebung, `env` zeigt nur frühere Einträge
+        let (env, _) = self.render_env(&spec.env, Env::new())?;
+        let cwd = match &spec.cwd {
+            Some(dir) => Some(self.render(dir, "ssh.cwd")?),
+            None => None,
+        };
+        let call = RemoteCall {
+            conn,
+            command,
+            env,
+            cwd,
+            env_mode: spec.env_mode,
+            output: None,
+            script,
+        };
+        self.run_remote(step, idx, "ssh", call, false).await
+    }
+
+    /// Verbindung zum aktuellen Host (`inventory_host`) einer Gruppe:
+    /// Schritt → Host-Eintrag → Gruppe → `defaults.ssh`.
+    fn resolve_member(&self, spec: &SshTarget, group: &HostGroup) -> Result<SshConn> {
+        let name = self
+            .with_ctx(|c| {
+                c.get("inventory_host")
+                    .and_then(Value::as_str)
+                    .map(String::from)
+            })
+            .with_context(|| format!("ssh.host: Gruppe '{}' ohne inventory_host", spec.host))?;
+        let member = group
+            .hosts
+            .iter()
+            .find(|h| h.name() == name)
+            .with_context(|| format!("ssh.host: '{}' fehlt in Gruppe '{}'", name, spec.host))?;
+        self.member_conn(spec, group, member)
+    }
+
+    /// Verbindung zu einem Host einer Gruppe: Schritt → Host-Eintrag → Gruppe → `defaults.ssh`.
+    fn member_conn(
+        &self,
+        spec: &SshTarget,
+        group: &HostGroup,
+        member: &InventoryHost,
+    ) -> Result<SshConn> {
+        let d = match member {
+            InventoryHost::Entry { ssh, .. } => ssh.or(&group.ssh),
+            InventoryHost::Name(_) => group.ssh.clone(),
+        };
+        self.resolve_ssh_in(spec, member.name(), &d.or(&self.defaults.ssh))
+    }
+
+    /// Baut für `delegate_to` die Verbindung zum delegierten Host mit den
+    /// Parametern aus `defaults.ssh`. Übertragen werden nur deklarierte
+    /// Variablen, `cwd` gilt remote.
+    fn delegate<'a>(
+        &self,
+        step: &Step,
+        host: &str,
+        command: String,
+        env: &EnvList,
+        cwd: Option<&String>,
+        output: &'a OutputSpec,
+    ) -> Result<RemoteCall<'a>> {
+        let target = SshTarget {
+            host: host.to_string(),
+            user: None,
+            port: None,
+            proxy_jump: None,
+            auth: None,
+            check_host: None,
+            agent_forward: false,
+        };
+        let conn = self.resolve_ssh(&target).context("delegate_to")?;
+        let (env, _) = self.render_env(step.env.iter().chain(env), Env::new())?;
+        let cwd = match cwd {
+            Some(dir) => Some(self.render(dir, "cwd")?),
+            None => None,
+        };
+        Ok(RemoteCall {
+            conn,
+            command,
+            env,
+            cwd,
+            env_mode: SshEnvMode::Inline,
+            output: Some(output),
+            script: None,
+        })
+    }
+
+    /// Führt `call.command` per ssh aus. Bei `delegated` zeigt der Header den
+    /// delegierten Host und das Remote-Kommando statt der ssh-Argumente.
+    async fn run_remote(
+        &self,
+        step: &Step,
+        idx: usize,
+        kind: &str,
+        call: RemoteCall<'_>,
+        delegated: bool,
+    ) -> Result<StepOutcome> {
+        let RemoteCall {
+            conn,
+            command,
+            env,
+            cwd: remote_cwd,
+            env_mode,
+            output,
+            script,
+        } = call;
+        if let Some(k) = env.keys().find(|k| !is_env_name(k)) {
+            anyhow::bail!("{}.env: ungültiger Variablenname '{}'", kind, k);
+        }
+
+        let mut ssh_cmd = conn.base_args();
+        // SendEnv/SetEnv sind ssh-Optionen und gehören vor `user@host`
+        let dest = ssh_cmd.pop().context("leerer ssh-Befehl")?;
+        let mut shown_cmd = ssh_cmd.clone();
+        let mut keys = env.keys().collect::<Vec<_>>();
+        keys.sort();
+        for k in keys {
+            let (opt, shown) = match env_mode {
+                SshEnvMode::SendEnv => (format!("SendEnv={}", k), format!("SendEnv={}", k)),
+                SshEnvMode::SetEnv => {
+                    // ssh zerlegt den Wert an Leerraum, nur `"` schützt ihn
+                    let quote = |v: &str| {
+                        if v.contains(char::is_whitespace) {
+                            format!("\"{}\"", v)
+                        } else {
+                            v.to_string()
+                        }
+                    };
+                    if env[k].contains('"') {
+                        anyhow::bail!(
+                            "{}.env.{}: `\"` ist mit env_mode: set_env nicht möglich",
+                            kind,
+                            k
+                        );
+                    }
+                    (
+                        format!("SetEnv={}={}", k, quote(&env[k])),
+                        format!("SetEnv={}={}", k, quote(mask_value(k, &env[k]))),
+                    )
+                }
+                SshEnvMode::Inline | SshEnvMode::Env => continue,
+            };
+            ssh_cmd.extend(["-o".to_string(), opt]);
+            shown_cmd.extend(["-o".to_string(), shown]);
+        }
+        ssh_cmd.push(dest.clone());
+        shown_cmd.push(dest.clone());
+        let cd = match &remote_cwd {
+            Some(dir) => format!("cd {} && ", shell_escape::escape(dir.into())),
+            None => "".to_string(),
+        };
+        // ENV inline export; für die Anzeige mit maskierten Secrets.
+        // become umschließt Variablen und Kommando, damit sudo sie nicht verwirft.
+        let become_ = self.resolve_become(step)?;
+        let remote = |masked: bool| {
+            let inner = match env_mode {
+                SshEnvMode::Inline => format!("{}{}", env_assignments(&env, masked), command),
+                // der Interpreter-Aufruf braucht kein `sh -c`, `$t` bleibt sichtbar
+                SshEnvMode::Env if script.is_some() && !env.is_empty() => {
+                    format!("env {}{}", env_assignments(&env, masked), command)
+                }
+                SshEnvMode::Env if !env.is_empty() => format!(
+                    "env {}sh -c {}",
+                    env_assignments(&env, masked),
+                    shell_escape::escape(command.as_str().into())
+                ),
+                _ => command.clone(),
+            };
+            // die Temp-Datei legt schon der Zielbenutzer an, sonst könnte er sie nicht lesen
+            let inner = match &script {
+                Some(_) => format!("{}{}", SCRIPT_UPLOAD, inner),
+                None => inner,
+            };
+            match &become_ {
+                Some(b) => format!("{}{}", cd, b.wrap_remote(&inner)),
+                None => format!("{}{}", cd, inner),
+            }
+        };
+        ssh_cmd.push(remote(false));
+        shown_cmd.push(remote(true));
+
+        let line = if delegated {
+            format!("[delegate_to {}] {}", dest, remote(true))
+        } else {
+            shown_cmd.join(" ")
+        };
+        self.print_header(step, idx, kind, &line);
+
+        if self.dry_run(step) {
+            self.print_preview(step, &shown_cmd, remote_cwd.as_deref().unwrap_or("~"), &env);
+            if let (Some(script), false) = (&script, step.no_log) {
+                println!("[dry-run] script:\n{}", script);
+            }
+            return Ok(StepOutcome::default());
+        }
+        if self.check_skips(step) {
+            return Ok(self.check_skipped());
+        }
+
+        // argv mit maskierten Werten
+        let (prg, args) = shown_cmd.split_first().context("leerer ssh-Befehl")?;
+        self.trace_spawn(step, prg, args, remote_cwd.as_deref().unwrap_or("~"), &env);
+        // Ausgabedateien bleiben lokal
+        let files = match output {
+            Some(o) => self.open_outputs(o, self.opts.workdir.as_deref().unwrap_or("."))?,
+            None => OutputFiles::default(),
+        };
+        let mut cmd = Command::new(&ssh_cmd[0]);
+        if let Some(pw) = &conn.password {
+            cmd.env("SSHPASS", pw);
+        }
+        if env_mode == SshEnvMode::SendEnv {
+            cmd.envs(&env);
+        }
+        // Passwort für sudo vor dem Skript, siehe `Become::stdin_prefix`
+        let stdin = match (become_.as_ref().and_then(Become::stdin_prefix), script) {
+            (None, None) => None,
+            (prefix, script) => Some(prefix.unwrap_or_default() + &script.unwrap_or_default()),
+        };
+        if stdin.is_some() {
+            cmd.stdin(std::process::Stdio::piped());
+        }
+        let mut child = cmd
+            .args(&ssh_cmd[1..])
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| spawn_error(e, &ssh_cmd[0], ssh_hint(&ssh_cmd[0])))?;
+        // parallel zum Lesen schreiben, sonst blockieren volle Pipes; das Schließen beendet `cat`
+        feed_stdin(&mut child, stdin);
+
+        let res = self
+            .stream_child(
+                &mut child,
+                step,
+                self.line_prefix(step, idx, kind),
+                idx,
+                files,
+                true,
+            )
+            .await;
+        match (res, &become_) {
+            (Err(e), Some(b)) => {
+                let hint = e
+                    .downcast_ref::<ProcessFailed>()
+                    .and_then(|pf| b.explain_failure(&conn.host, &pf.stderr));
+                Err(match hint {
+                    Some(hint) => e.context(hint),
+                    None => e,
+                })
+            }
+            (res, _) => res,
+        }
+    }
+
+    /// `meta: end_run` beendet den Lauf nach diesem Schritt, auch im Dry-Run,
+    /// damit die Vorschau dem echten Lauf entspricht.
+    fn run_meta(&self, step: &Step, meta: Meta, idx: usize) -> Result<StepOutcome> {
+        match meta {
+            Meta::EndRun => {
+                self.print_header(step, idx, "meta", "end_run");
+                self.ended.store(true, Ordering::SeqCst);
+            }
+        }
+        Ok(StepOutcome::default())
+    }
+
+    fn run_assert(&self, step: &Step, spec: &AssertSpec, idx: usize) -> Result<StepOutcome> {
+        self.print_header(step, idx, "assert", &spec.that.join(" && "));
+        // Assertions sind lesend und werden auch im Dry-Run ausgewertet
+        for expr in &spec.that {
+            if !self
+                .renderer
+                .eval_bool(expr, &self.ctx_with([]))
+                .context("assert.that")?
+            {
+                let msg = match &spec.msg {
+                    Some(m) => self.render(m, "assert.msg")?,
+                    None => format!("Assertion fehlgeschlagen: {}", expr),
+                };
+                anyhow::bail!(msg);
+            }
+        }
+        println!("[assert] ok");
+        Ok(StepOutcome::default())
+    }
+
+    /// Schritt-`cwd` vor Dokument-`cwd`, sonst das aktuelle Verzeichnis.
+    fn resolve_cwd(&self, step_cwd: Option<&String>) -> Result<String> {
+        let Some(raw) = step_cwd.or(self.cwd.as_ref()) else {
+            return Ok(self.opts.workdir.clone().unwrap_or_else(|| ".".into()));
+        };
+        let dir = self.in_workdir(self.render_path(raw, "cwd")?);
+        // im Dry-Run kann ein vorheriger Schritt das Verzeichnis noch nicht angelegt haben
+        if !self.opts.dry_run && !Path::new(&dir).is_dir() {
+            anyhow::bail!("Arbeitsverzeichnis {} existiert nicht", dir);
+        }
+        Ok(dir)
+    }
+
+    /// Relative Pfade gelten ab `--workdir`, absolute bleiben unverändert.
+    fn in_workdir(&self, path: String) -> String {
+        match &self.opts.workdir {
+            Some(base) if Path::new(&path).is_relative() => {
+                Path::new(base).join(path).to_string_lossy().into_owned()
+            }
+            _ => path,
+        }
+    }
+
+    /// Mitschnitt für `--output-dir`: `NNN-<name>.out`/`.err`, in einer Schleife
+    /// mit dem Element im Namen. Beim ersten Öffnen im Lauf werden die Dateien
+    /// geleert, weitere Prozesse des Schritts (Hooks, Wiederholungen) hängen an.
+    /// `no_log`-Schritte werden nicht mitgeschnitten.
+    fn audit_files(&self, step: &Step, idx: usize) -> Result<OutputFiles> {
+        let Some(dir) = self.opts.output_dir.as_deref().filter(|_| !step.no_log) else {
+            return Ok(OutputFiles::default());
+        };
+        std::fs::create_dir_all(dir).with_context(|| format!("--output-dir {} anlegen", dir))?;
+        let mut stem = format!(
+            "{:03}-{}",
+            idx + 1,
+            crate::paths::file_stem(step.name.as_deref().unwrap_or("step"))
+        );
+        if let Ok(label) = LOOP_ITEM.try_with(String::clone) {
+            stem = format!("{}-{}", stem, crate::paths::file_stem(&label));
+        }
+        let open = |ext: &str| -> Result<std::fs::File> {
+            let path = Path::new(dir).join(format!("{}.{}", stem, ext));
+            let fresh = self
+                .audit_opened
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(path.clone());
+            let mut opts = std::fs::OpenOptions::new();
+            match fresh {
+                true => opts.write(true).truncate(true),
+                false => opts.append(true),
+            };
+            opts.create(true)
+                .open(&path)
+                .with_context(|| format!("{} öffnen", path.display()))
+        };
+        Ok(OutputFiles {
+            stdout: Some(open("out")?),
+            stderr: Some(open("err")?),
+        })
+    }
+
+    /// Explizit deklarierte (Schritt, dann Block) und vollständige Umgebung des
+    /// Kindprozesses: geerbt (ohne `clean_env`), ohne `unset_env`, plus deklarierte.
+    fn step_env(&self, step: &Step, local_env: &EnvList) -> Result<(Env, Env)> {
+        let mut inherited = if step.clean_env {
+            Env::new()
+        } else {
+            std::env::vars().collect::<Env>()
+        };
+        for k in &step.unset_env {
+            inherited.remove(k);
+        }
+        self.render_env(step.env.iter().chain(local_env), inherited)
+    }
+
+    /// Rendert `env`-Einträge in Deklarationsreihenfolge. Jeder Wert sieht unter
+    /// `env` die bis dahin zusammengeführte Umgebung (`base` plus frühere Einträge),
+    /// `ENV` bleibt die Umgebung von `rust-runner`. Einträge mit falsy `when`
+    /// werden übersprungen, mit `process-first` auch solche, die `base` schon setzt.
+    fn render_env<'a>(
+        &self,
+        entries: impl IntoIterator<Item = &'a (String, EnvValue)>,
+        base: Env,
+    ) -> Result<(Env, Env)> {
+        let inherited = match self.opts.env_precedence {
+            EnvPrecedence::ProcessFirst => base.keys().cloned().collect(),
+            EnvPrecedence::ExplicitFirst => BTreeSet::new(),
+        };
+        let (mut declared, mut merged) = (Env::new(), base);
+        for (k, raw) in entries {
+            if inherited.contains(k) {
+                if self.opts.verbose >= 1 {
+                    println!("[env] {}: geerbter Wert hat Vorrang (process-first)", k);
+                }
+                continue;
+            }
+            let ctx = self.ctx_with([("env", serde_yaml::to_value(&merged)?)]);
+            if let Some(cond) = &raw.when
+                && !self
+                    .renderer
+                    .eval_bool(cond, &ctx)
+                    .with_context(|| format!("env.{}.when", k))?
+            {
+                continue;
+            }
+            self.trace_tpl(&raw.value, &format!("env.{}", k));
+            let val = self
+                .renderer
+                .render_str(&raw.value, &ctx)
+                .with_context(|| format!("env.{}", k))?;
+            merged.insert(k.clone(), val.clone());
+            declared.insert(k.clone(), val);
+        }
+        Ok((declared, merged))
+    }
+
+    /// Name für Schrittkopf und Präfix. Ein `name` mit `{{ }}` wird im Kontext
+    /// des Durchlaufs gerendert (also mit `item`/`inventory_host`) und ersetzt
+    /// dann das angehängte Label; bei einem Renderfehler bleibt er roh.
+    /// `steps.<name>`, `depends_on` und `--start-at` nutzen immer den rohen Namen.
+    fn display_name(&self, step: &Step, kind: &str) -> (String, bool) {
+        match step.name.as_deref() {
+            Some(n) if n.contains("{{") || n.contains("{%") => {
+                let rendered = self.with_ctx(|ctx| self.renderer.render_str(n, ctx));
+                (rendered.unwrap_or_else(|_| n.to_string()), true)
+            }
+            name => (name.unwrap_or(kind).to_string(), false),
+        }
+    }
+
+    fn print_header(&self, step: &Step, idx: usize, kind: &str, rendered: &str) {
+        let (mut name, templated) = self.display_name(step, kind);
+        if let (Ok(label), false) = (LOOP_ITEM.try_with(String::clone), templated) {
+            name = format!("{} [{}]", name, label);
+        }
+        let shown = if step.no_log { "***" } else { rendered };
+        let mut commands = self.commands.lock().unwrap_or_else(|e| e.into_inner());
+        let list = commands.entry(idx).or_default();
+        // Wiederholungen desselben Kommandos nur einmal
+        if list.last().is_none_or(|c| c != shown) {
+            list.push(shown.to_string());
+        }
+        drop(commands);
+        self.observer.on_step_start(&StepStart {
+            idx,
+            name: &name,
+            kind,
+            command: (!step.no_log).then_some(rendered),
+        });
+    }
+
+    /// Schritt-`timeout` vor `defaults.timeout`; `async` begrenzt zusätzlich.
+    fn timeout(&self, step: &Step) -> Option<std::time::Duration> {
+        let secs = match (step.timeout.or(self.defaults.timeout), step.r#async) {
+            (Some(t), Some(a)) => Some(t.min(a)),
+            (t, a) => t.or(a),
+        };
+        secs.map(std::time::Duration::from_secs)
+    }
+
+    /// `[3:deploy]` bzw. mit `--plain-prefix` nur `[shell]`.
+    fn line_prefix(&self, step: &Step, idx: usize, kind: &str) -> String {
+        let item = LOOP_ITEM
+            .try_with(|l| format!(":{}", l))
+            .unwrap_or_default();
+        if self.opts.plain_prefix {
+            return format!("[{}{}]", kind, item);
+        }
+        match self.display_name(step, kind) {
+            (name, true) => format!("[{}:{}]", idx + 1, name),
+            (name, false) => format!("[{}:{}{}]", idx + 1, name, item),
+        }
+    }
+
+    /// Ausgabe vor dem Start eines Prozesses je nach `-v`-Stufe: ab 1 die
+    /// deklarierte Umgebung, ab 3 die rohe argv, das cwd und den Kontext.
+    fn trace_spawn(&self, step: &Step, prg: &str, args: &[String], cwd: &str, env: &Env) {
+        if self.opts.verbose == 0 || step.no_log {
+            return;
+        }
+        let mut keys = env.keys().collect::<Vec<_>>();
+        keys.sort();
+        for k in keys {
+            println!("[env] {}={}", k, mask_value(k, &env[k]));
+        }
+        if self.opts.verbose >= 3 {
+            let argv = std::iter::once(prg).chain(args.iter().map(String::as_str));
+            println!("[spawn] argv: {:?}", argv.collect::<Vec<_>>());
+            println!("[spawn] cwd: {}", cwd);
+            let dump = self.with_ctx(|ctx| serde_yaml::to_string(&mask_ctx(ctx.clone())));
+            match dump {
+                Ok(yaml) => yaml.lines().for_each(|l| println!("[ctx] {}", l)),
+                Err(e) => println!("[ctx] nicht darstellbar: {}", e),
+            }
+        }
+    }
+
+    fn print_preview(&self, step: &Step, argv: &[String], cwd: &str, env: &Env) {
+        if step.no_log {
+            println!("[dry-run] (no_log, Vorschau unterdrückt)");
+            return;
+        }
+        let argv = argv
+            .iter()
+            .map(|a| shell_escape::escape(a.into()).into_owned())
+            .collect::<Vec<_>>();
+        println!("[dry-run] argv: {}", argv.join(" "));
+        println!("[dry-run] cwd: {}", cwd);
+        let mut keys = env.keys().collect::<Vec<_>>();
+        keys.sort();
+        for k in keys {
+            println!("[dry-run] env: {}={}", k, mask_value(k, &env[k]));
+        }
+    }
+
+    /// Öffnet die Zieldateien für `stdout_file`/`stderr_file` und legt
+    /// fehlende Verzeichnisse an. Relative Pfade gelten ab `cwd`.
+    fn open_outputs(&self, spec: &OutputSpec, cwd: &str) -> Result<OutputFiles> {
+        let open = |v: Option<&String>, field: &str| -> Result<Option<std::fs::File>> {
+            let Some(raw) = v else { return Ok(None) };
+            let path = Path::new(cwd).join(self.render(raw, field)?);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(spec.append_output)
+                .truncate(!spec.append_output)
+                .open(&path)
+                .with_context(|| format!("{} öffnen: {}", field, path.display()))?;
+            println!("[{}] -> {}", field, path.display());
+            Ok(Some(file))
+        };
+        Ok(OutputFiles {
+            stdout: open(spec.stdout_file.as_ref(), "stdout_file")?,
+            stderr: open(spec.stderr_file.as_ref(), "stderr_file")?,
+        })
+    }
+
+    /// Streamt die Ausgabe von `child` und wartet auf das Ende. Mit `action`
+    /// (die eigentliche shell/exec/ssh-Aktion) gelten `success_codes` und
+    /// `fail_on_stderr` des Schritts, sonst zählt nur Exit-Code 0.
+    async fn stream_child(
+        &self,
+        child: &mut tokio::process::Child,
+        step: &Step,
+        prefix: String,
+        idx: usize,
+        mut files: OutputFiles,
+        action: bool,
+    ) -> Result<StepOutcome> {
+        let mut audit = self.audit_files(step, idx)?;
+        let (combine, timeout) = (step.combine_output, self.timeout(step));
+        let (quiet, raw) = (step.no_log, step.raw_output);
+        let limit = match step.capture_limit.or(self.defaults.capture_limit) {
+            Some(0) => usize::MAX,
+            Some(n) => n,
+            None => DEFAULT_CAPTURE_LIMIT,
+        };
+        // beide Reader schreiben in einen Kanal; ein Printer gibt in Ankunftsreihenfolge
+        // aus und sammelt die Ausgabe. Nicht verbundene Pipes werden übersprungen.
+        let (tx, mut rx) = mpsc::unbounded_channel::<(&'static str, Chunk)>();
+        let out_task = child
+            .stdout
+            .take()
+            .map(|s| spawn_reader(s, "out", raw, tx.clone()));
+        let err_task = child
+            .stderr
+            .take()
+            .map(|s| spawn_reader(s, "err", raw, tx.clone()));
+        drop(tx);
+
+        let observer = self.observer.clone();
+        let print_task = tokio::spawn(async move {
+            // Bytes erst am Ende dekodieren, raw-Chunks können UTF-8-Zeichen teilen
+            let (mut stdout, mut stderr) = (Capture::new(limit), Capture::new(limit));
+            let mut unterminated = None; // Stream, dessen letzte Ausgabe ohne `\n` endete
+            let (started, mut last_line, mut waited) = (Instant::now(), Instant::now(), false);
+            let mut tick = tokio::time::interval(std::time::Duration::from_millis(250));
+            loop {
+                let (src, chunk) = tokio::select! {
+                    msg = rx.recv() => match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    },
+                    // ohne Zeilengrenzen würde die Statuszeile z. B. einen Prompt überschreiben
+                    _ = tick.tick(), if !raw => {
+                        // stille Prozesse melden, damit sie nicht wie hängend wirken
+                        if last_line.elapsed() >= IDLE_AFTER {
+                            observer.on_step_wait(idx, &prefix, Some(started.elapsed()));
+                            waited = true;
+                        }
+                        continue;
+                    }
+                };
+                last_line = Instant::now();
+                let line = match chunk {
+                    Chunk::Line(line) => line,
+                    Chunk::Raw(bytes) => {
+                        if let Some(f) = audit.get(src) {
+                            let _ = f.write_all(&bytes);
+                        }
+                        let (file, buf) = if src == "err" {
+                            (files.stderr.as_mut(), &mut stderr)
+                        } else {
+                            (files.stdout.as_mut(), &mut stdout)
+                        };
+                        buf.push(&bytes);
+                        if let Some(f) = file {
+                            let _ = f.write_all(&bytes);
+                        } else if !quiet {
+                            let to_stderr = src == "err" && !combine;
+                            observer.on_step_raw(idx, to_stderr, &bytes);
+                            unterminated = (!bytes.ends_with(b"\n")).then_some(to_stderr);
+                        }
+                        continue;
+                    }
+                };
+                let (file, buf) = if src == "err" {
+                    (files.stderr.as_mut(), &mut stderr)
+                } else {
+                    (files.stdout.as_mut(), &mut stdout)
+                };
+                buf.push(line.as_bytes());
+                buf.push(b"\n");
+                if let Some(f) = audit.get(src) {
+                    let _ = writeln!(f, "{}", line);
+                }
+                if let Some(f) = file {
+                    // umgeleitete Streams werden nur gesammelt, nicht angezeigt
+                    let _ = writeln!(f, "{}", line);
+                } else if quiet {
+                    // no_log: nur sammeln, nichts ausgeben
+                } else {
+                    observer.on_step_output(&OutputLine {
+                        idx,
+                        prefix: &prefix,
+                        src,
+                        line: &line,
+                        // ohne combine_output bleibt stderr auf stderr
+                        to_stderr: src == "err" && !combine,
+                    });
+                }
+            }
+            if waited {
+                observer.on_step_wait(idx, &prefix, None);
+            }
+            // folgende Ausgaben beginnen in einer neuen Zeile
+            if let Some(to_stderr) = unterminated {
+                observer.on_step_raw(idx, to_stderr, b"\n");
+            }
+            let (stdout, stderr) = (stdout.finish(), stderr.finish());
+            for (name, (_, dropped)) in [("stdout", &stdout), ("stderr", &stderr)] {
+                if *dropped > 0 {
+                    println!(
+                        "{}[capture] {} auf die letzten {} Bytes gekürzt ({} verworfen, capture_limit)",
+                        prefix, name, limit, dropped
+                    );
+                }
+            }
+            (stdout.0, stderr.0)
+        });
+
+        let status = wait_child(child, timeout).await?;
+        join_reader(out_task).await;
+        join_reader(err_task).await;
+        let (stdout, stderr) = print_task.await?;
+        // success_codes/fail_on_stderr gelten nur für die Aktion, nicht für Hooks o. ä.
+        let codes = if action { &step.success_codes[..] } else { &[] };
+        let ok = match status.code() {
+            Some(rc) if !codes.is_empty() => codes.contains(&rc),
+            _ => status.success(),
+        };
+        let stderr_failed = ok && action && step.fail_on_stderr && !stderr.is_empty();
+        if ok && !stderr_failed {
+            return Ok(StepOutcome {
+                rc: status.code().unwrap_or(0),
+                changed: true,
+                stdout,
+                stderr,
+                ..Default::default()
+            });
+        }
+        let reason = if stderr_failed {
+            Some(format!("fail_on_stderr: {} Bytes auf stderr", stderr.len()))
+        } else {
+            (!codes.is_empty()).then(|| format!("Exit-Code nicht in success_codes {:?}", codes))
+        };
+        let err = anyhow::Error::from(ProcessFailed::new(status, stdout, stderr));
+        Err(match reason {
+            Some(reason) => err.context(reason),
+            None => err,
+        })
+    }
+}
+
+/// Worüber `run_loop` verteilt.
+#[derive(Clone, Copy)]
+enum Fan {
+    Loop,  // `loop`, je Element `item`
+    Hosts, // Host-Gruppe in `ssh.host`, je Host `inventory_host`
+}
+
+impl Fan {
+    fn var(self) -> &'static str {
+        match self {
+            Fan::Loop => "item",
+            Fan::Hosts => "inventory_host",
+        }
+    }
+
+    fn tag(self) -> &'static str {
+        match self {
+            Fan::Loop => "loop",
+            Fan::Hosts => "hosts",
+        }
+    }
+
+    fn noun(self) -> &'static str {
+        match self {
+            Fan::Loop => "Durchläufen",
+            Fan::Hosts => "Hosts",
+        }
+    }
+}
+
+/// Ein per ssh auszuführendes Kommando, für `ssh` und `delegate_to`.
+struct RemoteCall<'a> {
+    conn: SshConn,
+    command: String, // gerendert, ohne cd/env
+    env: Env,
+    cwd: Option<String>,
+    env_mode: SshEnvMode,
+    output: Option<&'a OutputSpec>, // relativ zu --workdir
+    script: Option<String>,         // Inhalt für stdin, `command` startet ihn über `$t`
+}
+
+/// Geöffnete Umleitungsziele eines Prozesses; `None` heißt Konsole.
+#[derive(Default)]
+struct OutputFiles {
+    stdout: Option<std::fs::File>,
+    stderr: Option<std::fs::File>,
+}
+
+impl OutputFiles {
+    /// Ziel für `src` ("out"/"err"), falls geöffnet.
+    fn get(&mut self, src: &str) -> Option<&mut std::fs::File> {
+        match src {
+            "err" => self.stderr.as_mut(),
+            _ => self.stdout.as_mut(),
+        }
+    }
+}
+
+/// Ergebnis eines ausgeführten (oder übersprungenen) Schritts.
+#[derive(Debug, Clone, Default)]
+pub struct StepOutcome {
+    pub rc: i32,
+    pub changed: bool,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration: std::time::Duration,
+    pub skipped: bool,
+    pub skip_reason: Option<String>, // z. B. "creates: /opt/app existiert"
+    pub parsed: Option<Value>,       // stdout mit `parse: json`
+    pub signal: Option<i32>, // nur bei `until` nach einem durch ein Signal beendeten Versuch
+}
+
+/// Fehler eines Kindprozesses mit Exit-Code bzw. Signal und gesammelter Ausgabe.
+#[derive(Debug, thiserror::Error)]
+#[error("Prozess {}", exit_detail(.status))]
+pub struct ProcessFailed {
+    pub status: std::process::ExitStatus,
+    pub rc: Option<i32>,     // None, wenn ein Signal den Prozess beendet hat
+    pub signal: Option<i32>, // nur Unix
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ProcessFailed {
+    pub fn new(status: std::process::ExitStatus, stdout: String, stderr: String) -> Self {
+        Self {
+            status,
+            rc: status.code(),
+            signal: exit_signal(&status),
+            stdout,
+            stderr,
+        }
+    }
+}
+
+/// Signal, das den Prozess beendet hat.
+#[cfg(unix)]
+fn exit_signal(status: &std::process::ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(status)
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: &std::process::ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Name eines Signals wie `SIGKILL`, unbekannte als `Signal 42`.
+pub fn signal_name(sig: i32) -> String {
+    #[cfg(unix)]
+    let known = [
+        (libc::SIGHUP, "SIGHUP"),
+        (libc::SIGINT, "SIGINT"),
+        (libc::SIGQUIT, "SIGQUIT"),
+        (libc::SIGILL, "SIGILL"),
+        (libc::SIGABRT, "SIGABRT"),
+        (libc::SIGBUS, "SIGBUS"),
+        (libc::SIGFPE, "SIGFPE"),
+        (libc::SIGKILL, "SIGKILL"),
+        (libc::SIGUSR1, "SIGUSR1"),
+        (libc::SIGSEGV, "SIGSEGV"),
+        (libc::SIGUSR2, "SIGUSR2"),
+        (libc::SIGPIPE, "SIGPIPE"),
+        (libc::SIGALRM, "SIGALRM"),
+        (libc::SIGTERM, "SIGTERM"),
+        (libc::SIGXCPU, "SIGXCPU"),
+        (libc::SIGXFSZ, "SIGXFSZ"),
+    ]
+    .into_iter()
+    .find(|(n, _)| *n == sig)
+    .map(|(_, name)| name);
+    #[cfg(not(unix))]
+    let known: Option<&str> = None;
+    known.map_or_else(|| format!("Signal {}", sig), String::from)
+}
+
+/// "endete mit Exit-Code 2" bzw. "durch SIGKILL (Signal 9) beendet"; bei
+/// SIGKILL mit Hinweis auf den OOM-Killer, der sonst keine Spur hinterlässt.
+fn exit_detail(status: &std::process::ExitStatus) -> String {
+    if let Some(rc) = status.code() {
+        return format!("endete mit Exit-Code {}", rc);
+    }
+    let Some(sig) = exit_signal(status) else {
+        return format!("endete mit Status {}", status);
+    };
+    let mut out = format!("durch {} (Signal {}) beendet", signal_name(sig), sig);
+    #[cfg(unix)]
+    {
+        if std::os::unix::process::ExitStatusExt::core_dumped(status) {
+            out += ", Core-Dump geschrieben";
+        }
+        if sig == libc::SIGKILL {
+            out += " (z. B. OOM-Killer oder kill -9)";
+        }
+    }
+    out
+}
+
+/// Der ganze Lauf hat `--timeout` überschritten; laufende Prozesse wurden beendet.
+#[derive(Debug, thiserror::Error)]
+#[error("Lauf hat das globale Timeout (--timeout) überschritten")]
+pub struct RunTimeout;
+
+/// Zeitüberschreitung eines Schritts; der Prozess wurde beendet.
+#[derive(Debug, thiserror::Error)]
+#[error("Timeout nach {0}s")]
+pub struct StepTimeout(pub u64);
+
+/// Schreibt `input` nebenläufig in stdin des Kinds und schließt es danach,
+/// damit große Eingaben nicht gegen eine volle stdout-Pipe blockieren. Liest
+/// das Kind stdin nicht zu Ende, wird der Rest verworfen.
+fn feed_stdin(child: &mut tokio::process::Child, input: Option<String>) {
+    if let (Some(input), Some(mut pipe)) = (input, child.stdin.take()) {
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            let _ = pipe.write_all(input.as_bytes()).await;
+        });
+    }
+}
+
+/// Höchstens so lange wartet `--preflight` auf einen Host.
+const PREFLIGHT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// `ssh ... true` ohne Rückfragen; ein Fehler enthält die letzte Zeile von stderr.
+async fn probe(conn: &SshConn) -> Result<()> {
+    let mut args = conn.base_args();
+    let dest = args.pop().context("leerer ssh-Befehl")?;
+    if conn.connect_timeout.is_none() {
+        args.extend(["-o", "ConnectTimeout=10"].map(String::from));
+    }
+    // mit Passwort fragt sshpass, BatchMode würde die Anmeldung verhindern
+    if conn.password.is_none() {
+        args.extend(["-o", "BatchMode=yes"].map(String::from));
+    }
+    args.extend([dest, "true".into()]);
+    let mut cmd = Command::new(&args[0]);
+    if let Some(pw) = &conn.password {
+        cmd.env("SSHPASS", pw);
+    }
+    let out = cmd
+        .args(&args[1..])
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let out = tokio::time::timeout(PREFLIGHT_TIMEOUT, out)
+        .await
+        .map_err(|_| anyhow::anyhow!("keine Antwort nach {}s", PREFLIGHT_TIMEOUT.as_secs()))?
+        .map_err(|e| spawn_error(e, &args[0], SSH_HINT))?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let last = stderr.lines().rev().find(|l| !l.trim().is_empty());
+        anyhow::bail!("{}", last.unwrap_or(&out.status.to_string()).trim());
+    }
+    Ok(())
+}
+
+async fn wait_child(
+    child: &mut tokio::process::Child,
+    timeout: Option<std::time::Duration>,
+) -> Result<std::process::ExitStatus> {
+    let Some(t) = timeout else {
+        return Ok(child.wait().await?);
+    };
+    match tokio::time::timeout(t, child.wait()).await {
+        Ok(status) => Ok(status?),
+        Err(_) => {
+            let _ = child.kill().await;
+            Err(StepTimeout(t.as_secs()).into())
+        }
+    }
+}
+
+/// Ohne `retry_on_rc`/`retry_on_stderr` wird bei jedem Fehler wiederholt,
+/// sonst nur, wenn Exit-Code oder stderr passen.
+fn should_retry(step: &Step, stderr_re: Option<&regex::Regex>, err: &anyhow::Error) -> bool {
+    if step.retry_on_rc.is_empty() && stderr_re.is_none() {
+        return true;
+    }
+    let Some(pf) = err.downcast_ref::<ProcessFailed>() else {
+        return false;
+    };
+    pf.rc.is_some_and(|rc| step.retry_on_rc.contains(&rc))
+        || stderr_re.is_some_and(|re| re.is_match(&pf.stderr))
+}
+
+/// Gesammelte Ausgabe eines Streams, begrenzt auf die letzten `limit` Bytes.
+struct Capture {
+    buf: Vec<u8>,
+    limit: usize,
+    dropped: usize, // verworfene Bytes vom Anfang
+}
+
+impl Capture {
+    fn new(limit: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            limit,
+            dropped: 0,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+        // erst ab der doppelten Grenze kürzen, damit nicht jede Zeile den Puffer verschiebt
+        if self.buf.len() > self.limit.saturating_mul(2) {
+            self.trim();
+        }
+    }
+
+    fn trim(&mut self) {
+        let mut cut = self.buf.len().saturating_sub(self.limit);
+        // möglichst an einem Zeilenanfang, sonst nicht mitten in einem UTF-8-Zeichen beginnen
+        match self.buf[cut..].iter().position(|&b| b == b'\n') {
+            Some(nl) if cut + nl + 1 < self.buf.len() => cut += nl + 1,
+            _ => {
+                while cut < self.buf.len() && (self.buf[cut] & 0xC0) == 0x80 {
+                    cut += 1;
+                }
+            }
+        }
+        self.buf.drain(..cut);
+        self.dropped += cut;
+    }
+
+    /// Text und Zahl der insgesamt verworfenen Bytes.
+    fn finish(mut self) -> (String, usize) {
+        if self.buf.len() > self.limit {
+            self.trim();
+        }
+        (
+            String::from_utf8_lossy(&self.buf).into_owned(),
+            self.dropped,
+        )
+    }
+}
+
+/// Eine Zeile (ohne Umbruch) oder bei `raw_output` ein Block, wie er ankam.
+enum Chunk {
+    Line(String),
+    Raw(Vec<u8>),
+}
+
+fn spawn_reader<R>(
+    mut reader: R,
+    src: &'static str,
+    raw: bool,
+    tx: mpsc::UnboundedSender<(&'static str, Chunk)>,
+) -> tokio::task::JoinHandle<()>
+where
+    R: tokio::io::AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        if raw {
+            let mut buf = vec![0u8; 8192];
+            while let Ok(n @ 1..) = reader.read(&mut buf).await {
+                let _ = tx.send((src, Chunk::Raw(buf[..n].to_vec())));
+            }
+            return;
+        }
+        // zeilenweise als Bytes: ungültiges UTF-8 (latin1, Binärdaten) wird durch
+        // U+FFFD ersetzt, statt den Leser zu beenden und die Pipe volllaufen zu lassen
+        let mut reader = BufReader::new(reader);
+        let mut buf = Vec::new();
+        while let Ok(1..) = reader.read_until(b'\n', &mut buf).await {
+            if buf.ends_with(b"\n") {
+                buf.pop();
+                if buf.ends_with(b"\r") {
+                    buf.pop();
+                }
+            }
+            let line = String::from_utf8_lossy(&buf).into_owned();
+            let _ = tx.send((src, Chunk::Line(line)));
+            buf.clear();
+        }
+    })
+}
+
+/// Rendert alle Strings mit Template-Syntax in `v` an Ort und Stelle.
+fn render_leaves(renderer: &Renderer, v: &mut Value, ctx: &Value) -> Result<()> {
+    match v {
+        Value::String(s) if s.contains("{{") || s.contains("{%") => {
+            *s = renderer.render_str(&*s, ctx)?;
+        }
+        Value::Sequence(items) => {
+            for item in items {
+                render_leaves(renderer, item, ctx)?;
+            }
+        }
+        Value::Mapping(m) => {
+            for (_, item) in m.iter_mut() {
+                render_leaves(renderer, item, ctx)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+const SSH_HINT: &str = "Pfad über defaults.ssh.program oder --ssh-program angeben";
+const SSHPASS_HINT: &str = "auth.kind: password braucht sshpass";
+const SHELL_HINT: &str = "andere Shell über shell.shell bzw. shell.executable angeben";
+const EXEC_HINT: &str = "exec.cmd prüfen oder einen absoluten Pfad angeben";
+const CURL_HINT: &str = "download-Schritte laden über curl, bitte installieren";
+
+fn ssh_hint(prg: &str) -> &'static str {
+    if prg == "sshpass" {
+        SSHPASS_HINT
+    } else {
+        SSH_HINT
+    }
+}
+
+fn not_found(prg: &str, hint: &str) -> String {
+    format!(
+        "Programm '{}' nicht gefunden (auch nicht in PATH); {}",
+        prg, hint
+    )
+}
+
+/// Fehler beim Start von `prg`; fehlt das Programm, mit `hint` statt der OS-Meldung.
+fn spawn_error(e: std::io::Error, prg: &str, hint: &str) -> anyhow::Error {
+    if e.kind() == std::io::ErrorKind::NotFound {
+        anyhow::anyhow!(not_found(prg, hint))
+    } else {
+        anyhow::Error::from(e).context(format!("{} starten", prg))
+    }
+}
+
+/// argv als eine Zeile für die Remote-Shell, jedes Wort einzeln gequotet.
+fn join_argv(prg: &str, args: &[String]) -> String {
+    std::iter::once(prg)
+        .chain(args.iter().map(String::as_str))
+        .map(|a| shell_escape::escape(a.into()).into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Zerlegt die Shell-Angabe (`bash -c`) und hängt das Kommando an.
+pub fn shell_argv(shell: Option<&str>, cmd: &str) -> Result<(String, Vec<String>)> {
+    let mut parts = shell
+        .unwrap_or(DEFAULT_SHELL)
+        .split_whitespace()
+        .map(|s| s.to_string());
+    let prg = parts.next().context("leere shell-Angabe")?;
+    let mut args = parts.collect::<Vec<_>>();
+    args.push(cmd.to_string());
+    Ok((prg, args))
+}
+
+/// Stellt nach fehlgeschlagener Validierung den vorherigen Zustand her.
+fn rollback(path: &Path, bak: Option<&Path>, existed: bool) -> Result<()> {
+    match bak {
+        Some(b) => {
+            std::fs::copy(b, path).context("rollback copy")?;
+            println!("[conf] rollback: {} <- {}", path.display(), b.display());
+        }
+        None if !existed => {
+            std::fs::remove_file(path).context("rollback remove")?;
+            println!("[conf] rollback: {} entfernt", path.display());
+        }
+        // unverändert, daher kein Backup und nichts zurückzusetzen
+        None => {}
+    }
+    Ok(())
+}
+
+/// `umask` und Modus für neue Elternverzeichnisse eines conf-Schritts:
+/// `dir_mode`, sonst `0777` ohne die umask-Bits, sonst `0755`.
+fn conf_modes(spec: &ConfSpec) -> Result<(Option<u32>, u32)> {
+    let umask = match &spec.umask {
+        Some(u) => Some(crate::mode::parse_umask(u).context("conf umask")?),
+        None => None,
+    };
+    let dir_mode = match &spec.dir_mode {
+        Some(m) => parse_mode(m, 0o755).context("conf dir_mode")?,
+        None => umask.map_or(0o755, |u| 0o777 & !u),
+    };
+    Ok((umask, dir_mode))
+}
+
+/// Legt fehlende Verzeichnisse bis einschließlich `dir` an und setzt auf
+/// jedem neu angelegten `mode`, unabhängig von der umask des Prozesses.
+/// Bestehende Verzeichnisse bleiben unverändert.
+fn create_parents(dir: &Path, mode: u32) -> Result<()> {
+    let missing = dir
+        .ancestors()
+        .take_while(|d| !d.as_os_str().is_empty() && !d.exists())
+        .collect::<Vec<_>>();
+    for d in missing.into_iter().rev() {
+        match std::fs::create_dir(d) {
+            Ok(()) => {}
+            // parallel von einem anderen Schritt angelegt
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("{} anlegen", d.display())),
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(d, std::fs::Permissions::from_mode(mode))
+                .with_context(|| format!("{} chmod", d.display()))?;
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+    }
+    Ok(())
+}
+
+/// Temporäre Datei im Zielverzeichnis, damit `rename` atomar bleibt.
+fn tmp_path(path: &Path) -> std::path::PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.rr-tmp-{}", name, std::process::id()))
+}
+
+/// Heuristik für Variablen, deren Wert nicht angezeigt werden soll.
+fn is_secret_key(key: &str) -> bool {
+    let k = key.to_ascii_uppercase();
+    ["PASS", "SECRET", "TOKEN", "KEY"]
+        .iter()
+        .any(|s| k.contains(s))
+}
+
+fn mask_value<'a>(key: &str, value: &'a str) -> &'a str {
+    if is_secret_key(key) { "***" } else { value }
+}
+
+/// Maskiert Werte unter Schlüsseln wie `*_TOKEN` für die Kontextausgabe.
+fn mask_ctx(v: Value) -> Value {
+    match v {
+        Value::Mapping(m) => Value::Mapping(
+            m.into_iter()
+                .map(|(k, v)| match k.as_str() {
+                    Some(key) if is_secret_key(key) && !v.is_mapping() => (k, "***".into()),
+                    _ => (k, mask_ctx(v)),
+                })
+                .collect(),
+        ),
+        Value::Sequence(s) => Value::Sequence(s.into_iter().map(mask_ctx).collect()),
+        v => v,
+    }
+}
+
+/// Kurzbezeichnung eines `loop`-Elements: Skalare selbst, Maps ihr `name`,
+/// sonst die Nummer.
+fn item_label(item: &Value, i: usize) -> String {
+    let label = match item {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Mapping(m) => m.get("name").and_then(Value::as_str).map(str::to_string),
+        _ => None,
+    };
+    label.unwrap_or_else(|| format!("#{}", i + 1))
+}
+
+/// Gültiger Name für eine Shell-Variable: `[A-Za-z_][A-Za-z0-9_]*`.
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `KEY=val ` Präfix für Remote-Befehle, sortiert nach Schlüssel.
+fn env_assignments(env: &Env, masked: bool) -> String {
+    let mut keys = env.keys().collect::<Vec<_>>();
+    keys.sort();
+    keys.iter()
+        .map(|k| {
+            let v = if masked {
+                mask_value(k, &env[*k])
+            } else {
+                &env[*k]
+            };
+            format!("{}={} ", k, shell_escape::escape(v.into()))
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn current_mode(path: &Path) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|md| md.permissions().mode() & 0o7777)
+        .unwrap_or(0o644)
+}
+
+#[cfg(not(unix))]
+fn current_mode(_path: &Path) -> u32 {
+    0o644
+}
+
+async fn join_reader(task: Option<tokio::task::JoinHandle<()>>) {
+    if let Some(t) = task {
+        let _ = t.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Executor mit den globals aus `globals` (YAML), sonst Defaults.
+    fn executor(globals: &str) -> Executor {
+        let globals = serde_yaml::from_str(globals).unwrap();
+        Executor::new(globals, None, Defaults::default(), Options::default()).unwrap()
+    }
+
+    /// Leeres Verzeichnis je Test unter dem System-Temp.
+    fn scratch(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rr-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn exec_args_line_and_file() {
+        let dir = scratch("args-file");
+        std::fs::write(dir.join("args.txt"), "--name\n\na b  \n'quoted'\n").unwrap();
+        let spec: ExecSpec = serde_yaml::from_str(
+            "{cmd: x, args: \"-v '{{ who }} there' \\\"$HOME\\\"\", args_file: '{{ file }}'}",
+        )
+        .unwrap();
+        let exec = executor("{who: hello, file: args.txt}");
+        let args = exec.exec_args(&spec, &dir.to_string_lossy()).unwrap();
+        // Zeilen der Datei bleiben unverändert, leere zählen nicht
+        assert_eq!(
+            args,
+            ["-v", "hello there", "$HOME", "--name", "a b  ", "'quoted'"]
+        );
+    }
+
+    fn step(yaml: &str) -> Step {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn become_defaults_to_sudo_root() {
+        let exec = executor("{who: deploy}");
+        let b = exec
+            .resolve_become(&step("{become: true, shell: {command: id}}"))
+            .unwrap()
+            .unwrap();
+        assert_eq!((b.method, b.user.as_str()), (Method::Sudo, "root"));
+        let s = step(
+            "{become: true, become_user: '{{ who }}', become_method: doas, shell: {command: id}}",
+        );
+        let (prg, args) = exec
+            .apply_become(&s, "id".into(), vec!["-u".into()])
+            .unwrap();
+        assert_eq!(
+            (prg.as_str(), args),
+            (
+                "doas",
+                vec![
+                    "-u".to_string(),
+                    "deploy".into(),
+                    "--".into(),
+                    "id".into(),
+                    "-u".into()
+                ]
+            )
+        );
+        let s = step("{become: true, become_method: su, become_password: x, shell: {command: id}}");
+        assert!(exec.resolve_become(&s).is_err());
+    }
+
+    /// Zeilen, die `spawn_reader` aus `input` liest.
+    async fn read_lines(input: &[u8]) -> Vec<String> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        spawn_reader(std::io::Cursor::new(input.to_vec()), "out", false, tx)
+            .await
+            .unwrap();
+        let mut lines = Vec::new();
+        while let Ok((_, chunk)) = rx.try_recv() {
+            match chunk {
+                Chunk::Line(l) => lines.push(l),
+                Chunk::Raw(_) => panic!("Raw ohne raw_output"),
+            }
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn reader_replaces_invalid_utf8_per_line() {
+        let lines =
+            read_lines(b"ok\n\xff\xfe bad\nweiter \xe4\xf6\xfc\r\n\xc3\xa4 gut\nletzte").await;
+        assert_eq!(
+            lines,
+            [
+                "ok",
+                "\u{FFFD}\u{FFFD} bad",
+                "weiter \u{FFFD}\u{FFFD}\u{FFFD}",
+                "ä gut",
+                "letzte"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn capture_limit_holds_with_invalid_utf8() {
+        let input = b"\xff\xfe kaputt\n".repeat(100);
+        let lines = read_lines(&input).await;
+        assert_eq!(lines.len(), 100);
+        let mut cap = Capture::new(64);
+        for line in lines.iter().chain([&"ende".to_string()]) {
+            cap.push(line.as_bytes());
+            cap.push(b"\n");
+        }
+        let (text, dropped) = cap.finish();
+        assert!(text.len() <= 64, "{} Bytes", text.len());
+        assert!(dropped > 0);
+        // gekürzt wird an Zeilengrenzen, jede verbliebene Zeile ist vollständig
+        assert!(text.ends_with("ende\n"));
+        for line in text.lines().filter(|l| *l != "ende") {
+            assert_eq!(line, "\u{FFFD}\u{FFFD} kaputt");
+        }
+    }
+
+    /// Modus eines Pfads ohne Dateityp-Bits.
+    #[cfg(unix)]
+    fn mode_of(path: &Path) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::metadata(path).unwrap().permissions().mode() & 0o7777
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn conf_parent_modes() {
+        let dir = scratch("conf-modes");
+        let exec = executor("{}");
+        // ohne Angaben 0755, sonst dir_mode vor 0777 ohne umask-Bits
+        for (name, extra, want_dir, want_file) in [
+            ("plain", "", 0o755, None),
+            ("dir-mode", ", dir_mode: '0750'", 0o750, None),
+            ("umask", ", umask: '027'", 0o750, Some(0o640)),
+            (
+                "both",
+                ", dir_mode: '0711', umask: '077'",
+                0o711,
+                Some(0o600),
+            ),
+        ] {
+            let dest = dir.join(name).join("sub/app.conf");
+            let s = step(&format!(
+                "{{conf: {{dest: '{}', template: x{}}}}}",
+                dest.display(),
+                extra
+            ));
+            exec.run_conf(&s, s.conf.as_ref().unwrap(), 0)
+                .await
+                .unwrap();
+            for d in [dest.parent().unwrap(), &dir.join(name)] {
+                assert_eq!(mode_of(d), want_dir, "{}: {}", name, d.display());
+            }
+            if let Some(m) = want_file {
+                assert_eq!(mode_of(&dest), m, "{}", name);
+            }
+        }
+        // bestehende Verzeichnisse behalten ihren Modus
+        create_parents(&dir.join("plain/sub/neu"), 0o700).unwrap();
+        assert_eq!(mode_of(&dir.join("plain/sub")), 0o755);
+        assert_eq!(mode_of(&dir.join("plain/sub/neu")), 0o700);
+        // symbolisch relativ zu 0755
+        let spec: ConfSpec = serde_yaml::from_str("{dest: x, dir_mode: 'g-w,o='}").unwrap();
+        assert_eq!(conf_modes(&spec).unwrap(), (None, 0o750));
+        let spec: ConfSpec = serde_yaml::from_str("{dest: x, umask: '9'}").unwrap();
+        assert!(conf_modes(&spec).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn conf_dest_file_or_directory() {
+        let dir = scratch("conf-dest");
+        std::fs::create_dir(dir.join("etc")).unwrap();
+        let base = dir.display().to_string();
+        let exec = executor(&format!("{{base: '{}'}}", base));
+        let tpl = Some("templates/app.conf.j2");
+        // Datei bleibt, wie sie ist, auch wenn es sie noch nicht gibt
+        assert_eq!(
+            exec.conf_dest("{{ base }}/app.conf", tpl).unwrap(),
+            format!("{}/app.conf", base)
+        );
+        // bestehendes Verzeichnis und `/` am Ende: Name von template_file
+        for (raw, want) in [("{{ base }}/etc", "etc"), ("{{ base }}/neu/", "neu")] {
+            assert_eq!(
+                exec.conf_dest(raw, tpl).unwrap(),
+                format!("{}/{}/app.conf.j2", base, want)
+            );
+        }
+        // ohne template_file gibt es keinen Dateinamen
+        for raw in ["{{ base }}/etc", "{{ base }}/neu/"] {
+            let err = exec.conf_dest(raw, None).unwrap_err();
+            assert!(err.to_string().contains("ohne template_file"), "{}", err);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn ssh_argv_from_templated_target() {
+        let globals = "{u: deploy, p: 2222, jump: bastion, key: /keys/id_ed25519, h: web1}";
+        let defaults = serde_yaml::from_str(
+            "{ssh: {user: '{{ u }}-d', port: 22022, key_path: '/keys/{{ h }}', proxy_jump: 'j-{{ h }}'}}",
+        )
+        .unwrap();
+        let exec = Executor::new(
+            serde_yaml::from_str(globals).unwrap(),
+            None,
+            defaults,
+            Options::default(),
+        )
+        .unwrap();
+        let argv = |yaml: &str| {
+            let spec: SshTarget = serde_yaml::from_str(yaml).unwrap();
+            exec.resolve_ssh(&spec).unwrap().base_args()
+        };
+        let insecure = [
+            "-o",
+            "StrictHostKeyChecking=no",
+            "-o",
+            "UserKnownHostsFile=/dev/null",
+        ];
+        let want = |head: &[&str], rest: &[&str]| {
+            head.iter()
+                .chain(&insecure)
+                .chain(rest)
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+        // alles am Schritt, gegen die globals gerendert, port als Zahl
+        assert_eq!(
+            argv(
+                "{host: '{{ h }}.example', user: '{{ u }}', port: 2200, proxy_jump: '{{ jump }}', \
+                 auth: {kind: key, key_path: '{{ key }}'}}"
+            ),
+            want(
+                &["ssh"],
+                &[
+                    "-p",
+                    "2200",
+                    "-J",
+                    "bastion",
+                    "-i",
+                    "/keys/id_ed25519",
+                    "-o",
+                    "IdentitiesOnly=yes",
+                    "deploy@web1.example"
+                ]
+            )
+        );
+        // ohne Angaben am Schritt greifen die gerenderten defaults.ssh
+        assert_eq!(
+            argv("{host: web2}"),
+            want(
+                &["ssh"],
+                &[
+                    "-p",
+                    "22022",
+                    "-J",
+                    "j-web1",
+                    "-i",
+                    "/keys/web1",
+                    "-o",
+                    "IdentitiesOnly=yes",
+                    "deploy-d@web2"
+                ]
+            )
+        );
+        // Schritt schlägt defaults feldweise; Passwort nur über SSHPASS
+        assert_eq!(
+            argv(
+                "{host: web3, port: '{{ p }}', check_host: 'yes', \
+                 auth: {kind: password, password: geheim}}"
+            ),
+            [
+                "sshpass",
+                "-e",
+                "ssh",
+                "-p",
+                "2222",
+                "-J",
+                "j-web1",
+                "deploy-d@web3"
+            ]
+        );
+        // ohne defaults: root, kein Port, kein Key
+        let exec = executor(globals);
+        let spec: SshTarget = serde_yaml::from_str("{host: '{{ h }}'}").unwrap();
+        assert_eq!(
+            exec.resolve_ssh(&spec).unwrap().base_args(),
+            want(&["ssh"], &["root@web1"])
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn default_shell_is_bash() {
+        let (prg, args) = shell_argv(None, "echo 'a b'").unwrap();
+        assert_eq!(
+            (prg.as_str(), args),
+            ("bash", vec!["-c".into(), "echo 'a b'".to_string()])
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn default_shell_is_powershell() {
+        let (prg, args) = shell_argv(None, "Write-Output 'a b'").unwrap();
+        assert_eq!(
+            (prg.as_str(), args),
+            (
+                "powershell",
+                vec!["-Command".into(), "Write-Output 'a b'".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn shell_override_is_split_on_whitespace() {
+        // Kommando bleibt ein einziges Argument, die shell-Angabe wird zerlegt
+        let (prg, args) = shell_argv(Some("  sh  -e -c "), "x && y").unwrap();
+        assert_eq!(
+            (prg.as_str(), args),
+            ("sh", vec!["-e".into(), "-c".into(), "x && y".to_string()])
+        );
+        let (prg, args) = shell_argv(Some("cmd.exe /C"), "dir").unwrap();
+        assert_eq!(
+            (prg.as_str(), args),
+            ("cmd.exe", vec!["/C".into(), "dir".to_string()])
+        );
+        assert!(shell_argv(Some("   "), "x").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn validate_sees_step_env() {
+        let dir = scratch("validate-env");
+        let exec = executor("{}");
+        let dest = dir.join("app.conf");
+        let check = r#"test "$RR_V" = ok && test -z "$HOME" && test -f %s"#;
+        let conf = format!(
+            "conf: {{dest: '{}', template: x, validate: '{}'}}",
+            dest.display(),
+            check
+        );
+        let s = step(&format!(
+            "{{env: {{RR_V: ok}}, unset_env: [HOME], {}}}",
+            conf
+        ));
+        exec.run_conf(&s, s.conf.as_ref().unwrap(), 0)
+            .await
+            .unwrap();
+        assert!(dest.is_file());
+        std::fs::remove_file(&dest).unwrap();
+        let s = step(&format!("{{{}}}", conf));
+        assert!(
+            exec.run_conf(&s, s.conf.as_ref().unwrap(), 0)
+                .await
+                .is_err()
+        );
+        assert!(!dest.exists());
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
@@ -0,0 +1,37 @@
+// src/facts.rs
+use serde_yaml::{Mapping, Value};
+
+/// Einmalig beim Start gesammelte Fakten über die lokale Maschine (`facts.*`).
+pub fn gather() -> Value {
+    let mut m = Mapping::new();
+    m.insert("os".into(), std::env::consts::OS.into());
+    m.insert("family".into(), std::env::consts::FAMILY.into());
+    m.insert("arch".into(), std::env::consts::ARCH.into());
+    m.insert("hostname".into(), hostname().into());
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    m.insert("num_cpus".into(), (cpus as u64).into());
+    m.insert("user".into(), env_any(&["USER", "USERNAME"]).into());
+    m.insert("home".into(), env_any(&["HOME", "USERPROFILE"]).into());
+    m.insert(
+        "cwd".into(),
+        std::env::current_dir()
+            .map_or_else(|_| String::new(), |p| p.to_string_lossy().into_owned())
+            .into(),
+    );
+    Value::Mapping(m)
+}
+
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|p| std::fs::read_to_string(p).ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| env_any(&["HOSTNAME", "COMPUTERNAME"]))
+}
+
+fn env_any(keys: &[&str]) -> String {
+    keys.iter()
+        .find_map(|k| std::env::var(k).ok())
+        .unwrap_or_default()
+}
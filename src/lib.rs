@@ -0,0 +1,326 @@
+// src/lib.rs
+//! Bibliotheks-API von rust-runner: Playbooks parsen und ausführen, ohne die CLI.
+//!
+//! ```no_run
+//! # async fn demo() -> anyhow::Result<()> {
+//! let doc = rust_runner::parse_document(&std::fs::read_to_string("deploy.yaml")?)?;
+//! let scratch = rust_runner::scratch::ScratchDir::create(false)?;
+//! let opts = rust_runner::RunOptions {
+//!     options: rust_runner::Options {
+//!         tmpdir: scratch.path().to_string_lossy().into_owned(),
+//!         ..Default::default()
+//!     },
+//!     ..Default::default()
+//! };
+//! let report = rust_runner::run_document(doc, opts).await?;
+//! println!("{} Schritte, Exit-Code {}", report.records.len(), report.exit_code());
+//! # Ok(())
+//! # }
+//! ```
+pub mod breaker;
+mod checksum;
+pub mod config;
+mod diff;
+pub mod error;
+pub mod executor;
+mod facts;
+mod lineinfile;
+pub mod lint;
+pub mod lock;
+mod mode;
+pub mod notify;
+pub mod observer;
+mod output;
+pub mod paths;
+mod privilege;
+pub mod profile;
+pub mod prompt;
+mod remote;
+pub mod report;
+pub mod retry;
+pub mod scheduler;
+pub mod schema;
+pub mod scratch;
+mod ssh;
+pub mod state;
+mod template;
+pub mod watch;
+mod words;
+
+pub use error::ErrorKind;
+pub use executor::{Executor, Options, StepOutcome};
+pub use report::StepRecord;
+pub use schema::{Document, parse_document};
+
+use schema::OnError;
+
+use anyhow::{Context, Result};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Einstellungen für `run_document`.
+#[derive(Default)]
+pub struct RunOptions {
+    pub options: Options,
+    pub range: Option<RangeInclusive<usize>>, // Indizes ab 0, None: alle Schritte
+    pub only: Option<Vec<usize>>, // zusätzlich nur diese Indizes, z. B. aus der Retry-Datei
+    pub jobs: Option<usize>,      // parallel über depends_on, None: nacheinander
+    pub observer: Option<Arc<dyn observer::Observer>>, // None: Konsolenausgabe
+    pub deadline: Option<tokio::time::Instant>, // danach wird der Lauf abgebrochen
+    pub state: Option<Arc<state::State>>, // --incremental, gespeichert wird vom Aufrufer
+    pub retry_budget: Option<Arc<retry::Budget>>, // --max-retries-global, über alle Phasen
+    pub breaker: Option<Arc<breaker::Breaker>>, // --max-failures, über alle Phasen; rescue-Schritte zählen nicht
+}
+
+/// Ergebnis eines Laufs in Dokumentreihenfolge.
+pub struct RunReport {
+    pub records: Vec<StepRecord>,
+    pub failure: Option<ErrorKind>, // Art des ersten Fehlers
+    pub failures: Vec<notify::Failure>,
+}
+
+impl RunReport {
+    pub fn success(&self) -> bool {
+        self.failure.is_none()
+    }
+
+    /// Exit-Code wie bei der CLI: 0 oder der Code der ersten Fehlerart.
+    pub fn exit_code(&self) -> u8 {
+        self.failure.map_or(0, ErrorKind::exit_code)
+    }
+}
+
+/// Führt die Schritte eines Dokuments aus; `prompts` müssen vorher aufgelöst
+/// sein (siehe `prompt::resolve_prompts`). Fehler einzelner Schritte landen im
+/// Report, `Err` nur bei Fehlern vor dem ersten Schritt (z. B. `depends_on`).
+/// Benachrichtigungen aus `on_failure` werden außer im Dry-Run verschickt.
+pub async fn run_document(mut doc: Document, opts: RunOptions) -> Result<RunReport> {
+    let range = opts.range.unwrap_or(0..=doc.steps.len().saturating_sub(1));
+    let deps = scheduler::dependencies(&doc.steps)?;
+    let notifiers = notify::from_config(&doc.on_failure);
+    let dry_run = opts.options.dry_run;
+    let preflight = opts.options.preflight;
+    let on_error = doc.on_error.unwrap_or(if doc.rescue.is_empty() {
+        OnError::Abort
+    } else {
+        OnError::Rescue
+    });
+    let rescue = std::mem::take(&mut doc.rescue);
+    let steps = Arc::new(std::mem::take(&mut doc.steps));
+    let mut exec = Executor::new(doc.globals, doc.cwd, doc.defaults, opts.options)?;
+    exec.set_inventory(std::mem::take(&mut doc.inventory));
+    if let Some(src) = &doc.template_macros {
+        exec.set_template_macros(src)?;
+    }
+    // fehlende ssh-/Shell-Programme vor dem ersten Schritt melden, im Dry-Run nur warnen
+    let missing = exec.missing_programs(steps.iter().chain(&rescue));
+    if !missing.is_empty() && !dry_run {
+        anyhow::bail!(missing.join("\n"));
+    }
+    for m in &missing {
+        eprintln!("[preflight] {}", m);
+    }
+    if let Some(mode) = preflight {
+        let mut down = 0;
+        for (host, err) in exec.preflight(steps.iter().chain(&rescue)).await {
+            match err {
+                None => println!("[preflight] {}: erreichbar", host),
+                Some(e) => {
+                    down += 1;
+                    eprintln!("[preflight] {}: nicht erreichbar: {}", host, e);
+                }
+            }
+        }
+        if down > 0 && mode == executor::Preflight::Abort {
+            anyhow::bail!("{} Host(s) nicht erreichbar (--preflight)", down);
+        }
+    }
+    if let Some(state) = opts.state {
+        exec.set_state(state, doc.phase.clone());
+    }
+    if let Some(budget) = opts.retry_budget {
+        exec.set_retry_budget(budget);
+    }
+    if let Some(breaker) = &opts.breaker {
+        exec.set_breaker(breaker.clone());
+    }
+    if let Some(observer) = opts.observer {
+        exec.set_observer(observer);
+    }
+    let exec = Arc::new(exec);
+
+    let in_range =
+        |i: usize| range.contains(&i) && opts.only.as_ref().is_none_or(|only| only.contains(&i));
+    let mut records = Vec::new();
+    let mut failure = None;
+    let mut failures = Vec::new();
+    // rescue-Schritte zählen hinter den Hauptschritten weiter
+    let name_of = |i: usize| match i.checked_sub(steps.len()) {
+        None => scheduler::step_name(&steps, i),
+        Some(j) => format!(
+            "[rescue] {}",
+            rescue[j]
+                .name
+                .as_deref()
+                .unwrap_or(&format!("step {}", i + 1))
+        ),
+    };
+    let mut done = |i: usize, elapsed: Duration, res: Result<StepOutcome>| {
+        let name = name_of(i);
+        let record = match res {
+            Ok(outcome) => StepRecord {
+                name,
+                duration: outcome.duration,
+                usage: exec.usage(i),
+                commands: exec.commands(i),
+                result: Ok(outcome),
+            },
+            Err(e) => {
+                eprintln!("Fehler in Schritt {}: {:?}", i + 1, e);
+                // nur Hauptschritte zählen, sonst löst ein rescue-Handler allein aus
+                if let Some(b) = opts.breaker.as_ref().filter(|_| i < steps.len())
+                    && b.fail()
+                {
+                    eprintln!(
+                        "[max-failures] {} Schritt(e) fehlgeschlagen, es starten keine weiteren",
+                        b.max()
+                    );
+                }
+                failure.get_or_insert(ErrorKind::of(&e));
+                failures.push(notify::Failure::new(i + 1, name.clone(), &e));
+                StepRecord {
+                    name,
+                    duration: elapsed,
+                    usage: exec.usage(i),
+                    commands: exec.commands(i),
+                    result: Err(report::StepFailure::from_error(&e)),
+                }
+            }
+        };
+        let ok = record.result.is_ok();
+        records.push((i, record));
+        ok
+    };
+    let skip_out_of_range = |i: usize, done: &mut dyn FnMut(_, _, _) -> bool| {
+        println!(
+            "[skip] {} (außerhalb des Bereichs)",
+            scheduler::step_name(&steps, i)
+        );
+        let skipped = StepOutcome {
+            skipped: true,
+            skip_reason: Some("außerhalb des Bereichs".into()),
+            ..Default::default()
+        };
+        done(i, Duration::ZERO, Ok(skipped));
+    };
+
+    // merkt sich Fehler der Hauptschritte für `rescue`
+    let mut failed = false;
+    let mut track = |i: usize, elapsed: Duration, res: Result<StepOutcome>| {
+        let ok = done(i, elapsed, res);
+        failed |= !ok;
+        ok
+    };
+    if opts.jobs == Some(0) {
+        anyhow::bail!("--jobs muss mindestens 1 sein");
+    }
+    let run_all = async {
+        match opts.jobs {
+            Some(jobs) => {
+                for i in (0..steps.len()).filter(|&i| !in_range(i)) {
+                    skip_out_of_range(i, &mut track);
+                }
+                scheduler::run(
+                    exec.clone(),
+                    steps.clone(),
+                    &deps,
+                    jobs,
+                    on_error,
+                    in_range,
+                    &mut track,
+                )
+                .await
+            }
+            None => {
+                for i in 0..steps.len() {
+                    if !in_range(i) {
+                        skip_out_of_range(i, &mut track);
+                        continue;
+                    }
+                    if let Some(reason) = exec.stop_reason() {
+                        println!("[skip] {} ({})", scheduler::step_name(&steps, i), reason);
+                        let skipped = StepOutcome {
+                            skipped: true,
+                            skip_reason: Some(reason.into()),
+                            ..Default::default()
+                        };
+                        track(i, Duration::ZERO, Ok(skipped));
+                        continue;
+                    }
+                    let started = Instant::now();
+                    let res = exec.run_step(&steps[i], i).await;
+                    if !track(i, started.elapsed(), res) && on_error != OnError::Continue {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+        }
+    };
+    // beim Abbruch werden die laufenden Schritte verworfen, kill_on_drop beendet ihre Prozesse
+    let timed_out = match opts.deadline {
+        Some(deadline) => match tokio::time::timeout_at(deadline, run_all).await {
+            Ok(res) => res.map(|()| false)?,
+            Err(_) => true,
+        },
+        None => run_all.await.map(|()| false)?,
+    };
+    if timed_out {
+        eprintln!("\n[timeout] Lauf hat das globale Timeout überschritten, Abbruch");
+        for i in exec.running() {
+            track(i, Duration::ZERO, Err(executor::RunTimeout.into()));
+        }
+    }
+    if failed && on_error == OnError::Rescue && !timed_out {
+        println!("\n[rescue] {} Schritt(e) nach Fehler", rescue.len());
+        for (j, step) in rescue.iter().enumerate() {
+            let i = steps.len() + j;
+            let started = Instant::now();
+            let res = exec.run_step(step, i).await;
+            if !done(i, started.elapsed(), res) || exec.ended() {
+                break;
+            }
+        }
+    }
+    if timed_out {
+        // auch wenn das Timeout zwischen zwei Schritten ablief
+        failure.get_or_insert(ErrorKind::Timeout);
+    }
+    // Ausführungsreihenfolge kann bei --jobs abweichen, Reports folgen dem Dokument
+    records.sort_by_key(|(i, _)| *i);
+    let records = records.into_iter().map(|(_, r)| r).collect::<Vec<_>>();
+
+    if !dry_run {
+        notify::notify_all(&notifiers, &failures);
+    }
+    Ok(RunReport {
+        records,
+        failure,
+        failures,
+    })
+}
+
+/// Index eines Schritts über seine Nummer (ab 1) oder seinen Namen.
+pub fn resolve_step(steps: &[schema::Step], sel: &str) -> Result<usize> {
+    if let Ok(n) = sel.parse::<usize>() {
+        if n == 0 || n > steps.len() {
+            anyhow::bail!("Schritt {} existiert nicht (1-{})", n, steps.len());
+        }
+        return Ok(n - 1);
+    }
+    steps
+        .iter()
+        .position(|s| s.name.as_deref() == Some(sel))
+        .with_context(|| format!("kein Schritt mit Namen '{}'", sel))
+}
@@ -0,0 +1,83 @@
+// src/lineinfile.rs
+use regex::Regex;
+
+/// Wo eine fehlende Zeile eingefügt wird.
+pub enum Anchor {
+    Eof,
+    Bof,
+    After(Regex),  // nach dem letzten Treffer, ohne Treffer am Ende
+    Before(Regex), // vor dem ersten Treffer, ohne Treffer am Ende
+}
+
+/// Was `ensure_present`/`ensure_absent` getan haben.
+#[derive(Debug, PartialEq)]
+pub enum Edit {
+    Unchanged,
+    Inserted,
+    Replaced,
+    Removed(usize),
+}
+
+/// Stellt sicher, dass `line` vorkommt. Mit `regexp` wird der letzte Treffer
+/// ersetzt; ohne Treffer (oder ohne `regexp`, wenn die Zeile fehlt) wird am
+/// Anker eingefügt. Liefert den neuen Inhalt, wenn sich etwas ändert.
+pub fn ensure_present(
+    content: &str,
+    line: &str,
+    regexp: Option<&Regex>,
+    anchor: &Anchor,
+) -> (Edit, Option<String>) {
+    let mut lines = content.lines().collect::<Vec<_>>();
+    if let Some(re) = regexp
+        && let Some(i) = lines.iter().rposition(|l| re.is_match(l))
+    {
+        if lines[i] == line {
+            return (Edit::Unchanged, None);
+        }
+        lines[i] = line;
+        return (Edit::Replaced, Some(join(&lines)));
+    }
+    if lines.contains(&line) {
+        return (Edit::Unchanged, None);
+    }
+    let at = match anchor {
+        Anchor::Eof => lines.len(),
+        Anchor::Bof => 0,
+        Anchor::After(re) => lines
+            .iter()
+            .rposition(|l| re.is_match(l))
+            .map_or(lines.len(), |i| i + 1),
+        Anchor::Before(re) => lines
+            .iter()
+            .position(|l| re.is_match(l))
+            .unwrap_or(lines.len()),
+    };
+    lines.insert(at, line);
+    (Edit::Inserted, Some(join(&lines)))
+}
+
+/// Entfernt alle Zeilen, die `regexp` treffen bzw. genau `line` sind.
+pub fn ensure_absent(
+    content: &str,
+    line: Option<&str>,
+    regexp: Option<&Regex>,
+) -> (Edit, Option<String>) {
+    let lines = content.lines().collect::<Vec<_>>();
+    let keep = lines
+        .iter()
+        .filter(|l| match regexp {
+            Some(re) => !re.is_match(l),
+            None => Some(**l) != line,
+        })
+        .copied()
+        .collect::<Vec<_>>();
+    match lines.len() - keep.len() {
+        0 => (Edit::Unchanged, None),
+        n => (Edit::Removed(n), Some(join(&keep))),
+    }
+}
+
+/// Zeilen mit abschließendem Zeilenumbruch; eine leere Liste ergibt "".
+fn join(lines: &[&str]) -> String {
+    lines.iter().map(|l| format!("{}\n", l)).collect()
+}
@@ -0,0 +1,174 @@
+// src/lint.rs
+use crate::schema::{Document, LineState, SshTarget, Step};
+
+/// Ein Befund der Playbook-Prüfung mit Ort, z. B. `Schritt 3 (deploy)`.
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub location: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+/// Semantische Prüfung nach dem Parsen: sammelt alle Befunde, statt beim
+/// ersten abzubrechen. Templated Werte werden nicht geprüft, sie stehen erst
+/// zur Laufzeit fest.
+pub fn check(doc: &Document) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let lists = [("Schritt", &doc.steps), ("rescue-Schritt", &doc.rescue)];
+    for (label, steps) in lists {
+        for (i, step) in steps.iter().enumerate() {
+            let location = match &step.name {
+                Some(name) => format!("{} {} ({})", label, i + 1, name),
+                None => format!("{} {}", label, i + 1),
+            };
+            issues.extend(check_step(step).into_iter().map(|message| Issue {
+                location: location.clone(),
+                message,
+            }));
+        }
+    }
+    if let Some(src) = &doc.template_macros
+        && let Err(e) = tera::Tera::default().add_raw_template("macros", src)
+    {
+        issues.push(Issue {
+            location: "template_macros".into(),
+            message: format!("{:#}", anyhow::Error::from(e)),
+        });
+    }
+    issues
+}
+
+fn check_step(step: &Step) -> Vec<String> {
+    // genau ein Aktionsblock wird schon beim Parsen erzwungen
+    let mut out = Vec::new();
+    if let Some(shell) = &step.shell {
+        match (&shell.command, &shell.script) {
+            (Some(c), None) if c.trim().is_empty() => out.push("shell.command ist leer".into()),
+            (Some(_), None) | (None, Some(_)) => {}
+            _ => out.push("shell braucht genau eines von command oder script".into()),
+        }
+    }
+    if let Some(exec) = &step.exec
+        && exec.cmd.trim().is_empty()
+    {
+        out.push("exec.cmd ist leer".into());
+    }
+    if let Some(ssh) = &step.ssh {
+        match (&ssh.command, &ssh.script) {
+            (Some(c), None) if c.trim().is_empty() => out.push("ssh.command ist leer".into()),
+            (Some(_), None) | (None, Some(_)) => {}
+            _ => out.push("ssh braucht genau eines von command oder script".into()),
+        }
+        check_target(&ssh.target, "ssh", &mut out);
+    }
+    if let Some(conf) = &step.conf {
+        let sources = [&conf.template, &conf.template_file, &conf.from_command];
+        if sources.iter().filter(|s| s.is_some()).count() != 1 {
+            out.push(
+                "conf braucht genau eines von template, template_file oder from_command".into(),
+            );
+        }
+        if let Some(mode) = conf.mode.as_deref().filter(|m| !templated(m))
+            && let Err(e) = crate::mode::parse_mode(mode, 0)
+        {
+            out.push(format!("conf.mode: {}", e));
+        }
+        if let Some(mode) = conf.dir_mode.as_deref()
+            && let Err(e) = crate::mode::parse_mode(mode, 0o755)
+        {
+            out.push(format!("conf.dir_mode: {}", e));
+        }
+        if let Some(umask) = conf.umask.as_deref()
+            && let Err(e) = crate::mode::parse_umask(umask)
+        {
+            out.push(format!("conf.umask: {}", e));
+        }
+        if let Some(remote) = &conf.remote {
+            check_target(remote, "conf.remote", &mut out);
+        }
+    }
+    if let Some(spec) = &step.lineinfile {
+        match spec.state {
+            LineState::Present if spec.line.is_none() => {
+                out.push("lineinfile mit state: present braucht line".into())
+            }
+            LineState::Absent if spec.line.is_none() && spec.regexp.is_none() => {
+                out.push("lineinfile mit state: absent braucht line oder regexp".into())
+            }
+            _ => {}
+        }
+        if spec.insertafter.is_some() && spec.insertbefore.is_some() {
+            out.push("insertafter und insertbefore schließen sich aus".into());
+        }
+    }
+    if let Some(sum) = step.download.as_ref().and_then(|d| d.checksum.as_deref())
+        && !templated(sum)
+        && let Err(e) = crate::checksum::Checksum::parse(sum)
+    {
+        out.push(format!("download.checksum: {}", e));
+    }
+    if let Err(e) = crate::privilege::Method::parse(step.become_method.as_deref()) {
+        out.push(e.to_string());
+    }
+    if let Some(delay) = &step.retry_delay {
+        if !templated(delay) && delay.trim().parse::<f64>().map_or(true, |d| d < 0.0) {
+            out.push(format!("retry_delay '{}' ist keine Zahl >= 0", delay));
+        }
+        if step.retry_backoff.is_some() {
+            out.push("retry_delay und retry_backoff schließen sich aus".into());
+        }
+    }
+    if step.become_password.is_some() {
+        let remote = step.ssh.is_some()
+            || step.delegate_to.is_some()
+            || step.conf.as_ref().is_some_and(|c| c.remote.is_some());
+        if !remote {
+            out.push("become_password gilt nur für ssh, conf.remote und delegate_to".into());
+        }
+        if !matches!(step.become_method.as_deref(), None | Some("sudo")) {
+            out.push("become_password gibt es nur mit become_method: sudo".into());
+        }
+    }
+    if step.dry_run_safe && step.shell.is_none() && step.exec.is_none() && step.ssh.is_none() {
+        out.push("dry_run_safe gilt nur für shell, exec und ssh".into());
+    }
+    let stdin_from = [
+        step.shell.as_ref().and_then(|s| s.stdin_from.as_ref()),
+        step.exec.as_ref().and_then(|e| e.stdin_from.as_ref()),
+    ];
+    if step.delegate_to.is_some() && stdin_from.iter().any(Option::is_some) {
+        out.push("stdin_from geht nicht mit delegate_to".into());
+    }
+    if step.delegate_to.is_some() && step.shell.is_none() && step.exec.is_none() {
+        out.push("delegate_to gilt nur für shell und exec".into());
+    }
+    out
+}
+
+fn check_target(target: &SshTarget, field: &str, out: &mut Vec<String>) {
+    if let Some(v) = target.check_host.as_deref()
+        && !matches!(v, "yes" | "no" | "fingerprint")
+    {
+        out.push(format!(
+            "{}.check_host '{}' unbekannt (yes|no|fingerprint)",
+            field, v
+        ));
+    }
+    if let Some(auth) = &target.auth
+        && !matches!(auth.kind.as_str(), "key" | "password" | "agent")
+    {
+        out.push(format!(
+            "{}.auth.kind '{}' unbekannt (key|password|agent)",
+            field, auth.kind
+        ));
+    }
+}
+
+fn templated(s: &str) -> bool {
+    s.contains("{{") || s.contains("{%")
+}
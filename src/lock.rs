@@ -0,0 +1,27 @@
+// src/lock.rs
+use anyhow::{Context, Result};
+use std::fs::{File, TryLockError};
+
+/// Exklusiver Advisory-Lock auf `path` (flock bzw. LockFileEx).
+/// Der Lock hält, solange die zurückgegebene Datei lebt; das Betriebssystem
+/// gibt ihn auch bei Panic, Ctrl-C oder `exit` frei.
+pub fn acquire(path: &str, wait: bool) -> Result<File> {
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Lock-Datei {} öffnen", path))?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) if wait => {
+            eprintln!("[lock] {} ist belegt, warte ...", path);
+            file.lock().context("Lock anfordern")?;
+        }
+        Err(TryLockError::WouldBlock) => {
+            anyhow::bail!("{} ist von einem anderen Lauf belegt", path)
+        }
+        Err(TryLockError::Error(e)) => return Err(e).context("Lock anfordern"),
+    }
+    Ok(file)
+}
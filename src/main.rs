@@ -1,39 +1,927 @@
 // src/main.rs
-mod executor;
-mod schema;
-mod template;
-
 use anyhow::{Context, Result};
 use clap::Parser;
+use rust_runner::{
+    breaker, config, error, executor, lint, lock, paths, profile, prompt, report, retry, schema,
+    scratch, state, watch,
+};
 use schema::Document;
+use serde_yaml::Value;
 
-#[derive(Parser, Debug)]
-#[command(name = "rust-runner", version, about = "YAML-gesteuerte Ausführung")]
+#[derive(Parser, Debug, Clone)]
+#[command(
+    name = "rust-runner",
+    version,
+    about = "YAML-gesteuerte Ausführung",
+    subcommand_negates_reqs = true
+)]
 struct Cli {
-    /// Pfad zur YAML-Datei
-    file: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Pfad zur YAML-Datei, `-` liest von stdin
+    #[arg(required = true)]
+    file: Option<String>,
     /// Dry-Run (nichts ausführen)
     #[arg(long)]
     dry_run: bool,
-    /// Verbose Logging
+    /// Check-Modus: Änderungen ermitteln, aber nicht anwenden
+    #[arg(long, conflicts_with = "dry_run")]
+    check: bool,
+    /// Unified-Diff für alle conf-Schritte (auch mit --dry-run/--check)
+    #[arg(long)]
+    diff: bool,
+    /// Mehr Ausgaben: -v Umgebung und Ergebnis, -vv Template-Quellen und
+    /// Laufzeiten, -vvv rohe argv und Kontext
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Keine Header und keine Ausgabe erfolgreicher Schritte, nur Fehler und eine Abschlusszeile
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// ISO-8601-Zeitstempel vor jeder Ausgabezeile
+    #[arg(long)]
+    timestamps: bool,
+    /// Altes Präfixformat `[shell][out]` ohne Schrittnummer
+    #[arg(long)]
+    plain_prefix: bool,
+    /// Undefinierte Variablen auch in Bedingungen als Fehler behandeln
+    #[arg(long, global = true)]
+    strict_vars: bool,
+    /// Basisverzeichnis für relative Pfade (cwd, conf dest, ...)
+    #[arg(long, global = true, value_name = "PATH")]
+    workdir: Option<String>,
+    /// Verzeichnis mit Tera-Templates für include/extends (überschreibt template_dir)
+    #[arg(long, global = true, value_name = "PATH")]
+    template_dir: Option<String>,
+    /// Exklusiver Lock gegen parallele Läufe
+    #[arg(long, value_name = "PATH")]
+    lock_file: Option<String>,
+    /// Auf einen belegten Lock warten statt abzubrechen
+    #[arg(long, requires = "lock_file")]
+    lock_wait: bool,
+    /// Ab diesem Schritt ausführen (Nummer ab 1 oder Name)
+    #[arg(long, value_name = "STEP")]
+    start_at: Option<String>,
+    /// Bis einschließlich diesem Schritt ausführen (Nummer ab 1 oder Name)
+    #[arg(long, value_name = "STEP")]
+    step_until: Option<String>,
+    /// Unabhängige Schritte parallel ausführen (höchstens N gleichzeitig, beachtet depends_on)
+    #[arg(long, value_name = "N")]
+    jobs: Option<usize>,
+    /// Nur die Schritte ausführen, die im letzten Lauf fehlgeschlagen sind (<playbook>.retry)
+    #[arg(long, conflicts_with_all = ["start_at", "step_until"])]
+    retry_failed: bool,
+    /// Obergrenze für den ganzen Lauf in Sekunden, danach Abbruch mit Exit-Code 124
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<u64>,
+    /// Höchstens N Wiederholungen (`retry`) über den ganzen Lauf, danach schlägt der Schritt fehl
+    #[arg(long, value_name = "N")]
+    max_retries_global: Option<u32>,
+    /// Nach N fehlgeschlagenen Schritten (über alle Phasen) keine weiteren starten
+    #[arg(long, value_name = "N")]
+    max_failures: Option<usize>,
+    /// JUnit-XML-Report nach dem Lauf schreiben
+    #[arg(long, value_name = "PATH")]
+    junit: Option<String>,
+    /// Ausgabe jedes Schritts zusätzlich in NNN-<name>.out/.err in diesem Verzeichnis
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<String>,
+    /// Scratch-Verzeichnis ({{ run.tmpdir }}) nach dem Lauf nicht löschen
+    #[arg(long)]
+    keep_tmp: bool,
+    /// Fortschrittsbalken statt Schritt-Headern (Feature `progress`)
+    #[cfg(feature = "progress")]
+    #[arg(long, conflicts_with = "quiet")]
+    progress: bool,
+    /// Aufgelöstes Playbook (Merges, use:, Defaults, Bereich) als YAML ausgeben und beenden
+    #[arg(long)]
+    explain: bool,
+    /// Playbook nur prüfen, alle Befunde ausgeben und beenden
+    #[arg(long, conflicts_with = "explain")]
+    validate: bool,
+    /// Bei Änderungen an Playbook oder Templates neu ausführen, laufende Durchgänge abbrechen
+    #[arg(long, conflicts_with_all = ["validate", "explain"])]
+    watch: bool,
+    /// Befunde der Playbook-Prüfung als Fehler statt als Warnung behandeln
+    #[arg(long)]
+    strict: bool,
+    /// Keine Rückfragen; fehlende prompts ohne Default sind ein Fehler
+    #[arg(long, global = true)]
+    non_interactive: bool,
+    /// Schritte überspringen, deren Definition seit dem letzten Erfolg unverändert ist
     #[arg(long)]
-    verbose: bool,
+    incremental: bool,
+    /// Mit --incremental alle Schritte ausführen und den Stand neu aufzeichnen
+    #[arg(long, requires = "incremental")]
+    force: bool,
+    /// State-Datei für --incremental, default: <playbook>.state
+    #[arg(long, value_name = "PATH", requires = "incremental")]
+    state_file: Option<String>,
+    /// Laufzeit, CPU-Zeit und Peak-RSS je Schritt messen, am Ende die langsamsten ausgeben
+    #[arg(long)]
+    profile: bool,
+    /// Bei gleichnamigen Variablen: explicit-first (env gewinnt, default) oder process-first
+    #[arg(long, value_name = "ORDER", value_parser = executor::EnvPrecedence::parse)]
+    env_precedence: Option<executor::EnvPrecedence>,
+    /// Am Ende eine Tabelle aller Schritte; compact (default) oder wide mit Kommandos
+    #[arg(
+        long,
+        value_name = "STYLE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "compact",
+        value_parser = report::SummaryStyle::parse
+    )]
+    summary: Option<report::SummaryStyle>,
+    /// Vor dem ersten Schritt alle ssh-Hosts per `ssh ... true` prüfen; abort (default) oder warn
+    #[arg(
+        long,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "abort",
+        value_parser = executor::Preflight::parse
+    )]
+    preflight: Option<executor::Preflight>,
+    /// ssh-Binary statt `ssh` aus PATH, vor defaults.ssh.program
+    #[arg(long, value_name = "PATH")]
+    ssh_program: Option<String>,
+    /// Host-Gruppen aus dieser Datei, ergänzt bzw. ersetzt `inventory:` im Playbook
+    #[arg(long, value_name = "PATH")]
+    inventory: Option<String>,
+    /// Voreinstellungen aus dieser Datei statt .rust-runner.yaml bzw. ~/.config/rust-runner/config.yaml
+    #[arg(long, global = true, value_name = "PATH", conflicts_with = "no_config")]
+    config: Option<String>,
+    /// Keine Konfigurationsdatei laden
+    #[arg(long, global = true)]
+    no_config: bool,
+}
+
+/// Befehle neben dem Ausführen eines Playbooks; sie führen keine Schritte aus
+/// und nehmen keinen Lock.
+#[derive(clap::Subcommand, Debug, Clone)]
+enum Command {
+    /// Alle Tags mit der Zahl ihrer Schritte ausgeben
+    ListTags {
+        /// Pfad zur YAML-Datei, `-` liest von stdin
+        file: String,
+    },
+    /// Template-Datei gegen globals und --vars-file rendern und ausgeben
+    Template {
+        /// Template-Datei, relativ zum Aufrufverzeichnis
+        tpl: String,
+        /// Playbook, dessen globals, prompts, template_dir und template_macros gelten
+        #[arg(long, value_name = "PATH")]
+        playbook: Option<String>,
+        /// Phase des Playbooks (Nummer ab 1 oder Name), default: die erste
+        #[arg(long, value_name = "PHASE", requires = "playbook")]
+        phase: Option<String>,
+        /// YAML-Datei mit Variablen, die tief über globals gemischt werden
+        #[arg(long, value_name = "PATH")]
+        vars_file: Option<String>,
+    },
+}
+
+impl Cli {
+    /// Playbook des Laufs; ohne Unterbefehl verlangt clap es.
+    fn file(&self) -> &str {
+        self.file.as_deref().unwrap_or("-")
+    }
+
+    /// Übernimmt Werte aus der Konfigurationsdatei, soweit die Kommandozeile
+    /// sie nicht setzt. Schalter lassen sich dort nur einschalten.
+    fn apply_config(&mut self, cfg: config::Config) {
+        // -q und -v schließen sich aus, die Kommandozeile entscheidet
+        if self.verbose == 0 && !self.quiet {
+            self.verbose = cfg.verbose.unwrap_or(0);
+            self.quiet = cfg.quiet && self.verbose == 0;
+        }
+        self.timestamps |= cfg.timestamps;
+        self.plain_prefix |= cfg.plain_prefix;
+        self.strict_vars |= cfg.strict_vars;
+        self.diff |= cfg.diff;
+        self.non_interactive |= cfg.non_interactive;
+        self.keep_tmp |= cfg.keep_tmp;
+        #[cfg(feature = "progress")]
+        {
+            self.progress |= cfg.progress;
+        }
+        self.jobs = self.jobs.or(cfg.jobs);
+        self.timeout = self.timeout.or(cfg.timeout);
+        self.max_retries_global = self.max_retries_global.or(cfg.max_retries_global);
+        self.max_failures = self.max_failures.or(cfg.max_failures);
+        self.workdir = self.workdir.take().or(cfg.workdir);
+        self.template_dir = self.template_dir.take().or(cfg.template_dir);
+        // lock_wait gehört zum Lock aus derselben Quelle
+        if self.lock_file.is_none() {
+            self.lock_file = cfg.lock_file;
+            self.lock_wait |= cfg.lock_wait;
+        }
+        self.junit = self.junit.take().or(cfg.junit);
+        self.inventory = self.inventory.take().or(cfg.inventory);
+        self.output_dir = self.output_dir.take().or(cfg.output_dir);
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+async fn main() -> std::process::ExitCode {
+    // bei Ctrl-C wird `run` verworfen, dabei räumen Lock und Scratch-Verzeichnis auf.
+    // `run` zuerst: der Handler greift erst nach dem synchronen Start (Lock, prompts)
+    let res = tokio::select! {
+        biased;
+        res = start(Cli::parse()) => res,
+        _ = tokio::signal::ctrl_c() => {
+            eprintln!("Abgebrochen (Ctrl-C)");
+            return std::process::ExitCode::from(130);
+        }
+    };
+    match res {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            // Fehler vor dem ersten Schritt: nur YAML-Fehler haben einen eigenen Code
+            match e.downcast_ref::<error::ErrorKind>() {
+                Some(kind) => kind.exit_code().into(),
+                None => std::process::ExitCode::FAILURE,
+            }
+        }
+    }
+}
+
+async fn start(cli: Cli) -> Result<std::process::ExitCode> {
+    match cli.command.clone() {
+        Some(Command::ListTags { file }) => {
+            let raw = read_playbook(&file)?;
+            let phases = schema::parse_phases(&raw).context(error::ErrorKind::Parse)?;
+            print!("{}", list_tags(&phases));
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Some(Command::Template {
+            tpl,
+            playbook,
+            phase,
+            vars_file,
+        }) => {
+            render_template(cli, &tpl, playbook.as_deref(), phase.as_deref(), vars_file)?;
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        None if cli.watch => watch(cli).await,
+        None => run(cli).await,
+    }
+}
+
+/// Voreinstellungen aus der Konfigurationsdatei übernehmen, außer mit `--no-config`.
+fn load_config(cli: &mut Cli) -> Result<()> {
+    if !cli.no_config
+        && let Some((path, cfg)) = config::load(cli.config.as_deref())?
+    {
+        if cli.verbose >= 1 || cfg.verbose.is_some_and(|v| v >= 1) {
+            println!("[config] {}", path.display());
+        }
+        cli.apply_config(cfg);
+    }
+    Ok(())
+}
+
+/// `--watch`: führt das Playbook aus und startet es nach jeder Änderung an
+/// seinen Eingaben neu. Ein noch laufender Durchgang wird dabei verworfen;
+/// `kill_on_drop` beendet seine Prozesse, Lock und Scratch-Verzeichnis räumen
+/// beim Drop auf. Beendet wird nur mit Ctrl-C.
+async fn watch(mut cli: Cli) -> Result<std::process::ExitCode> {
+    if cli.file() == "-" {
+        anyhow::bail!("--watch geht nicht mit stdin");
+    }
+    load_config(&mut cli)?;
+    cli.no_config = true;
+    let mut n = 0;
+    loop {
+        n += 1;
+        let (files, dirs) = watch_inputs(&cli);
+        let mut watcher = watch::Watcher::new(&files, &dirs)?;
+        let line = "=".repeat(20);
+        let now = chrono::Local::now().format("%H:%M:%S");
+        println!("\n{} [watch] Lauf {}, {} {}", line, n, now, line);
+        let changed = tokio::select! {
+            res = run(cli.clone()) => {
+                if let Err(e) = res {
+                    eprintln!("Error: {:?}", e);
+                }
+                println!("\n[watch] Lauf {} beendet, warte auf Änderungen (Ctrl-C beendet)", n);
+                watcher.changed().await?
+            }
+            changed = watcher.changed() => {
+                println!("\n[watch] Änderung, Lauf {} wird abgebrochen", n);
+                changed?
+            }
+        };
+        for path in changed {
+            println!("[watch] geändert: {}", path.display());
+        }
+    }
+}
+
+/// Was `--watch` beobachtet: Playbook, `--inventory`, `template_dir` sowie
+/// wörtlich (ohne Tera) angegebene `template_file`-, `include_file`- und
+/// `include_template`-Pfade. Ist das Playbook gerade ungültig, nur das
+/// Playbook selbst und das Inventory. Liefert Dateien und Verzeichnisse.
+fn watch_inputs(cli: &Cli) -> (Vec<std::path::PathBuf>, Vec<std::path::PathBuf>) {
+    use std::path::{Path, PathBuf};
+    let mut files = vec![PathBuf::from(cli.file())];
+    files.extend(cli.inventory.iter().map(PathBuf::from));
+    let mut dirs = Vec::new();
+    let Ok(raw) = std::fs::read_to_string(cli.file()) else {
+        return (files, dirs);
+    };
+    // wie template_file: relativ zu --workdir, sonst zum Playbook
+    let base = match &cli.workdir {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(cli.file())
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default(),
+    };
+    let include =
+        regex::Regex::new(r#"include_(?:file|template)\(\s*path\s*=\s*\\?"([^"\\{}]+)\\?"\s*\)"#)
+            .expect("gültige Regex");
+    files.extend(include.captures_iter(&raw).map(|c| base.join(&c[1])));
+    let Ok(phases) = schema::parse_phases(&raw) else {
+        return (files, dirs);
+    };
+    let literal = |s: &&String| !s.contains("{{") && !s.contains("{%");
+    for doc in &phases {
+        let confs = doc
+            .steps
+            .iter()
+            .chain(&doc.rescue)
+            .filter_map(|s| s.conf.as_ref());
+        for file in confs
+            .filter_map(|c| c.template_file.as_ref())
+            .filter(literal)
+        {
+            files.push(base.join(file));
+        }
+        match (&cli.template_dir, &doc.template_dir) {
+            (Some(dir), _) => dirs.push(PathBuf::from(dir)),
+            (None, Some(dir)) => dirs.extend(paths::expand(dir).ok().map(|d| base.join(d))),
+            (None, None) => {}
+        }
+    }
+    (files, dirs)
+}
+
+async fn run(mut cli: Cli) -> Result<std::process::ExitCode> {
+    load_config(&mut cli)?;
+    // bis zum Ende des Prozesses gehalten; --validate führt nichts aus
+    let _lock = match &cli.lock_file {
+        Some(path) if !cli.validate => Some(lock::acquire(path, cli.lock_wait)?),
+        _ => None,
+    };
+
+    let raw = read_playbook(cli.file())?;
+    let phases = schema::parse_phases(&raw).context(error::ErrorKind::Parse)?;
+    let multi = phases.len() > 1;
+    check_phases(&phases, multi, cli.strict || cli.validate)?;
+    if cli.validate {
+        println!("[validate] keine Befunde");
+        return Ok(std::process::ExitCode::SUCCESS);
+    }
+    let state = match (cli.incremental, &cli.state_file) {
+        (false, _) => None,
+        (true, Some(path)) => Some(path.clone()),
+        (true, None) if cli.file() == "-" => {
+            anyhow::bail!("--incremental braucht bei stdin eine --state-file")
+        }
+        (true, None) => Some(state::path_for(cli.file())),
+    };
+    let state = match state {
+        Some(path) => Some(std::sync::Arc::new(state::State::load(&path, cli.force)?)),
+        None => None,
+    };
+    let inventory = match &cli.inventory {
+        Some(path) => Some(schema::load_inventory(path).context(error::ErrorKind::Parse)?),
+        None => None,
+    };
+    if multi && (cli.start_at.is_some() || cli.step_until.is_some() || cli.retry_failed) {
+        anyhow::bail!("--start-at, --step-until und --retry-failed gehen nur mit einer Phase");
+    }
+
+    if let Some(dir) = &cli.workdir
+        && !std::path::Path::new(dir).is_dir()
+    {
+        anyhow::bail!("--workdir {} ist kein Verzeichnis", dir);
+    }
+    let playbook_dir = playbook_dir(cli.file());
+
+    // gilt über alle Phasen hinweg
+    let deadline = cli
+        .timeout
+        .map(|s| tokio::time::Instant::now() + std::time::Duration::from_secs(s));
+    let retry_budget = cli
+        .max_retries_global
+        .map(|n| std::sync::Arc::new(retry::Budget::new(n)));
+    if cli.max_failures == Some(0) {
+        anyhow::bail!("--max-failures muss mindestens 1 sein");
+    }
+    let breaker = cli
+        .max_failures
+        .map(|n| std::sync::Arc::new(breaker::Breaker::new(n)));
+    let total = phases.len();
+    let mut scratch = None;
+    let mut answered = serde_yaml::Mapping::new();
+    let (mut records, mut failures, mut failure) = (Vec::new(), Vec::new(), None);
+    for (n, mut doc) in phases.into_iter().enumerate() {
+        if multi {
+            let name = doc
+                .phase
+                .clone()
+                .unwrap_or_else(|| format!("Phase {}", n + 1));
+            if cli.explain {
+                println!("---\n# {}", name);
+            } else if !cli.quiet {
+                println!("\n###### [{}/{}] {} ######", n + 1, total, name);
+            }
+        }
+        // Gruppen aus --inventory ersetzen gleichnamige aus dem Playbook
+        if let Some(inv) = &inventory {
+            doc.inventory.extend(inv.clone());
+        }
+        // prompts aus einer gemeinsamen Basis nur einmal fragen
+        if let Value::Mapping(globals) = &mut doc.globals {
+            for p in &doc.prompts {
+                if let (Some(v), false) = (answered.get(&p.name), globals.contains_key(&p.name)) {
+                    globals.insert(p.name.clone().into(), v.clone());
+                }
+            }
+        }
+        // bei mehreren Phasen ein Unterverzeichnis je Phase, die Nummern beginnen neu
+        let output_dir = cli.output_dir.as_ref().map(|dir| match multi {
+            true => {
+                let name = doc.phase.as_deref().unwrap_or("phase");
+                let sub = format!("{:02}-{}", n + 1, paths::file_stem(name));
+                std::path::Path::new(dir)
+                    .join(sub)
+                    .to_string_lossy()
+                    .into_owned()
+            }
+            false => dir.clone(),
+        });
+        let phase = run_phase(
+            &cli,
+            doc,
+            &playbook_dir,
+            &mut scratch,
+            &mut answered,
+            deadline,
+            state.clone(),
+            retry_budget.clone(),
+            breaker.clone(),
+            output_dir,
+        )
+        .await?;
+        let Some((run, continue_on_failure)) = phase else {
+            continue;
+        };
+        let ok = run.success();
+        records.extend(run.records);
+        failures.extend(run.failures);
+        failure = failure.or(run.failure);
+        let expired = deadline.is_some_and(|d| tokio::time::Instant::now() >= d);
+        let tripped = breaker.as_ref().is_some_and(|b| b.tripped());
+        if !ok && (!continue_on_failure || expired || tripped) {
+            if n + 1 < total {
+                println!(
+                    "[phase] abgebrochen, {} Phase(n) übersprungen",
+                    total - n - 1
+                );
+            }
+            break;
+        }
+    }
+    if cli.explain {
+        return Ok(std::process::ExitCode::SUCCESS);
+    }
+
+    // Dry-Run und Check führen nichts aus, eine vorhandene Retry-Datei bleibt;
+    // Nummern mehrerer Phasen wären nicht eindeutig
+    let retry_file = (cli.file() != "-" && !multi).then(|| retry::path_for(cli.file()));
+    if let Some(path) = &retry_file
+        && !cli.dry_run
+        && !cli.check
+    {
+        retry::write(path, &failures)?;
+    }
+    if let Some(state) = &state
+        && !cli.dry_run
+        && !cli.check
+    {
+        state.save()?;
+    }
+
+    if let Some(style) = cli.summary {
+        print!("{}", report::summary(&records, style));
+    }
+    if cli.quiet {
+        println!("{}", quiet_summary(&records));
+    }
+    if let Some(b) = &breaker
+        && b.tripped()
+    {
+        print!("{}", breaker_summary(&records, b.max()));
+    }
+    if cli.profile {
+        print!("{}", profile::report(&records));
+    }
+    if let Some(path) = &cli.junit {
+        report::write_junit(path, cli.file(), &records)?;
+    }
+    Ok(failure.map_or(0, error::ErrorKind::exit_code).into())
+}
+
+/// Führt ein Dokument aus (bzw. gibt es mit `--explain` aus, dann `None`),
+/// dazu `continue_on_failure` der Phase.
+/// Das Scratch-Verzeichnis wird beim ersten Lauf angelegt und von allen
+/// Phasen geteilt; beantwortete prompts landen in `answered`. `deadline`
+/// stammt aus `--timeout` und gilt wie `retry_budget` und `breaker` für alle
+/// Phasen gemeinsam, `output_dir` ist bei mehreren Phasen schon das Unterverzeichnis
+/// der Phase.
+#[allow(clippy::too_many_arguments)]
+async fn run_phase(
+    cli: &Cli,
+    mut doc: Document,
+    playbook_dir: &Option<String>,
+    scratch: &mut Option<scratch::ScratchDir>,
+    answered: &mut serde_yaml::Mapping,
+    deadline: Option<tokio::time::Instant>,
+    state: Option<std::sync::Arc<state::State>>,
+    retry_budget: Option<std::sync::Arc<retry::Budget>>,
+    breaker: Option<std::sync::Arc<breaker::Breaker>>,
+    output_dir: Option<String>,
+) -> Result<Option<(rust_runner::RunReport, bool)>> {
+    // stdin ist beim Lesen von `-` schon verbraucht, Rückfragen sind dann nicht möglich
+    let interactive = !cli.non_interactive && cli.file() != "-";
+    prompt::resolve_prompts(&mut doc.globals, &doc.prompts, interactive)?;
+    for p in &doc.prompts {
+        if let Some(v) = doc.globals.get(p.name.as_str()) {
+            answered.insert(p.name.clone().into(), v.clone());
+        }
+    }
+
+    let template_dir = template_dir(cli, &doc, playbook_dir)?;
+    let first = match &cli.start_at {
+        Some(sel) => rust_runner::resolve_step(&doc.steps, sel).context("--start-at")?,
+        None => 0,
+    };
+    let last = match &cli.step_until {
+        Some(sel) => rust_runner::resolve_step(&doc.steps, sel).context("--step-until")?,
+        None => doc.steps.len().saturating_sub(1),
+    };
+    if first > last {
+        anyhow::bail!("--start-at liegt hinter --step-until");
+    }
+    let only = match cli.retry_failed {
+        true if cli.file() == "-" => {
+            anyhow::bail!("--retry-failed braucht eine Playbook-Datei, nicht stdin")
+        }
+        true => Some(retry::load(&retry::path_for(cli.file()), &doc.steps)?),
+        false => None,
+    };
+    if cli.explain {
+        // nur die Schritte aus --start-at/--step-until bzw. --retry-failed
+        let steps = std::mem::take(&mut doc.steps).into_iter().enumerate();
+        doc.steps = steps
+            .filter(|(i, _)| (first..=last).contains(i))
+            .filter(|(i, _)| only.as_ref().is_none_or(|o| o.contains(i)))
+            .map(|(_, s)| s)
+            .collect();
+        print!("{}", schema::explain(doc)?);
+        return Ok(None);
+    }
+    if scratch.is_none() {
+        *scratch = Some(scratch::ScratchDir::create(cli.keep_tmp)?);
+    }
+    let tmpdir = scratch
+        .as_ref()
+        .map(|s| s.path().to_string_lossy().into_owned());
+    let opts = executor::Options {
+        verbose: cli.verbose,
+        dry_run: cli.dry_run,
+        timestamps: cli.timestamps,
+        plain_prefix: cli.plain_prefix,
+        strict_vars: cli.strict_vars || doc.strict_vars || doc.strict,
+        check: cli.check,
+        diff: cli.diff,
+        tmpdir: tmpdir.unwrap_or_default(),
+        template_dir,
+        workdir: cli.workdir.clone(),
+        playbook_dir: playbook_dir.clone(),
+        ssh_program: cli.ssh_program.clone(),
+        profile: cli.profile,
+        output_dir,
+        env_precedence: cli.env_precedence.unwrap_or_default(),
+        preflight: cli.preflight,
+    };
+    let total = doc.steps.len();
+    let continue_on_failure = doc.continue_on_failure;
+    #[cfg(feature = "progress")]
+    let observer = cli.progress.then(|| {
+        let planned = only.as_ref().map_or(last - first + 1, Vec::len);
+        let bar = rust_runner::observer::ProgressObserver::new(planned);
+        std::sync::Arc::new(bar) as std::sync::Arc<dyn rust_runner::observer::Observer>
+    });
+    #[cfg(not(feature = "progress"))]
+    let observer = None;
+    let observer = match cli.quiet {
+        true => Some(
+            std::sync::Arc::new(rust_runner::observer::QuietObserver::default())
+                as std::sync::Arc<dyn rust_runner::observer::Observer>,
+        ),
+        false => observer,
+    };
+    let run = rust_runner::run_document(
+        doc,
+        rust_runner::RunOptions {
+            options: opts,
+            range: Some(first..=last),
+            only,
+            jobs: cli.jobs,
+            observer,
+            deadline,
+            state,
+            retry_budget,
+            breaker,
+        },
+    )
+    .await?;
+
+    if cli.jobs.is_some() {
+        for (i, r) in run.records.iter().enumerate() {
+            let state = match &r.result {
+                Ok(o) if o.skipped => match &o.skip_reason {
+                    Some(reason) => format!("übersprungen ({})", reason),
+                    None => "übersprungen".into(),
+                },
+                Ok(_) => "ok".into(),
+                Err(_) => "fehlgeschlagen".into(),
+            };
+            println!("[jobs] {:>3} {}: {}", i + 1, r.name, state);
+        }
+    }
+
+    if cli.check {
+        let would = run
+            .records
+            .iter()
+            .filter(|r| r.result.as_ref().is_ok_and(|o| o.changed))
+            .map(|r| r.name.as_str())
+            .collect::<Vec<_>>();
+        println!("[check] {} Schritt(e) würden ändern", would.len());
+        for name in would {
+            println!("[check]   {}", name);
+        }
+    }
+
+    if cli.start_at.is_some() || cli.step_until.is_some() {
+        println!(
+            "[range] Schritte {}-{} von {}, {} übersprungen",
+            first + 1,
+            last + 1,
+            total,
+            total - (last - first + 1)
+        );
+    }
+    Ok(Some((run, continue_on_failure)))
+}
 
-    let raw = std::fs::read_to_string(&cli.file).context("YAML lesen")?;
-    let doc: Document = serde_yaml::from_str(&raw).context("YAML parsen")?;
+/// Abschlusszeile für `--quiet`, z. B. `[quiet] 12 Schritt(e): 10 ok, 1 übersprungen, 1 fehlgeschlagen (3.21s)`.
+fn quiet_summary(records: &[report::StepRecord]) -> String {
+    let failed = records.iter().filter(|r| r.result.is_err()).count();
+    let skipped = records
+        .iter()
+        .filter(|r| r.result.as_ref().is_ok_and(|o| o.skipped))
+        .count();
+    let total = records
+        .iter()
+        .map(|r| r.duration)
+        .sum::<std::time::Duration>();
+    format!(
+        "[quiet] {} Schritt(e): {} ok, {} übersprungen, {} fehlgeschlagen ({:.2}s)",
+        records.len(),
+        records.len() - failed - skipped,
+        skipped,
+        failed,
+        total.as_secs_f64()
+    )
+}
+
+/// Was bis zum Auslösen von `--max-failures` gelaufen ist.
+fn breaker_summary(records: &[report::StepRecord], max: usize) -> String {
+    let names = |pick: &dyn Fn(&report::StepRecord) -> bool| {
+        let names = records.iter().filter(|r| pick(r)).map(|r| r.name.as_str());
+        names.collect::<Vec<_>>()
+    };
+    let ok = names(&|r| r.result.as_ref().is_ok_and(|o| !o.skipped));
+    let failed = names(&|r| r.result.is_err());
+    let stopped = names(&|r| {
+        r.result
+            .as_ref()
+            .is_ok_and(|o| o.skip_reason.as_deref() == Some("--max-failures erreicht"))
+    });
+    let list = |names: &[&str]| match names {
+        [] => String::new(),
+        names => format!(" ({})", names.join(", ")),
+    };
+    format!(
+        "[max-failures] ausgelöst nach {} fehlgeschlagenen Schritt(en)\n\
+         [max-failures] gelaufen: {} ok{}, {} fehlgeschlagen{}\n\
+         [max-failures] nicht gestartet: {}\n",
+        max,
+        ok.len(),
+        list(&ok),
+        failed.len(),
+        list(&failed),
+        stopped.len()
+    )
+}
+
+/// Gibt alle Befunde von `lint::check` aus; mit `strict` (bzw. `--validate`)
+/// oder `strict: true` im Dokument sind sie ein Parse-Fehler, sonst nur Warnungen.
+fn check_phases(phases: &[Document], multi: bool, strict: bool) -> Result<()> {
+    let mut count = 0;
+    for (n, doc) in phases.iter().enumerate() {
+        // `strict: true` im Dokument wirkt wie --strict, nur für diese Phase
+        let strict = strict || doc.strict;
+        for issue in lint::check(doc) {
+            if strict {
+                count += 1;
+            }
+            let tag = if strict { "[lint]" } else { "[lint] Warnung:" };
+            match multi {
+                true => eprintln!("{} Phase {}, {}", tag, n + 1, issue),
+                false => eprintln!("{} {}", tag, issue),
+            }
+        }
+    }
+    if count > 0 {
+        return Err(anyhow::anyhow!("{} Befund(e) im Playbook", count))
+            .context(error::ErrorKind::Parse);
+    }
+    Ok(())
+}
 
-    let exec = executor::Executor::new(doc.globals, cli.verbose, cli.dry_run);
+/// Verzeichnis des Playbooks, `None` bei stdin.
+fn playbook_dir(file: &str) -> Option<String> {
+    (file != "-").then(|| {
+        let dir = std::path::Path::new(file).parent();
+        dir.map_or_else(String::new, |d| d.to_string_lossy().into_owned())
+    })
+}
 
-    for (i, step) in doc.steps.iter().enumerate() {
-        if let Err(e) = exec.run_step(step, i).await {
-            eprintln!("Fehler in Schritt {}: {:?}", i + 1, e);
-            std::process::exit(1);
+/// `--template-dir` oder `template_dir` des Dokuments; dieses wie
+/// template_file relativ zu --workdir, sonst zum Playbook.
+fn template_dir(
+    cli: &Cli,
+    doc: &Document,
+    playbook_dir: &Option<String>,
+) -> Result<Option<String>> {
+    Ok(match (&cli.template_dir, &doc.template_dir) {
+        (Some(dir), _) => Some(dir.clone()),
+        (None, Some(dir)) => {
+            let dir = paths::expand(dir).context("template_dir")?;
+            let base = cli.workdir.as_ref().or(playbook_dir.as_ref());
+            Some(match base {
+                Some(b) => std::path::Path::new(b)
+                    .join(dir)
+                    .to_string_lossy()
+                    .into_owned(),
+                None => dir,
+            })
+        }
+        (None, None) => None,
+    })
+}
+
+/// `template`: rendert `tpl` (relativ zum Aufrufverzeichnis) wie ein
+/// Template-Feld eines Schritts und gibt das Ergebnis aus. Das Playbook ist
+/// optional und liefert nur globals (samt prompts), `template_dir` und
+/// `template_macros`, bei mehreren Phasen die von `phase`. Variablen aus
+/// `vars_file` werden tief über die globals gemischt.
+fn render_template(
+    mut cli: Cli,
+    tpl: &str,
+    playbook: Option<&str>,
+    phase: Option<&str>,
+    vars_file: Option<String>,
+) -> Result<()> {
+    load_config(&mut cli)?;
+    let mut doc = match playbook {
+        Some(file) => {
+            let raw = read_playbook(file)?;
+            let mut phases = schema::parse_phases(&raw).context(error::ErrorKind::Parse)?;
+            let n = match phase {
+                Some(sel) => pick_phase(&phases, sel)?,
+                None => 0,
+            };
+            let mut doc = phases.swap_remove(n);
+            // stdin ist beim Lesen von `-` schon verbraucht
+            let interactive = !cli.non_interactive && file != "-";
+            prompt::resolve_prompts(&mut doc.globals, &doc.prompts, interactive)?;
+            Some(doc)
+        }
+        None => None,
+    };
+    let mut globals = doc
+        .as_mut()
+        .map_or(Value::Null, |d| std::mem::take(&mut d.globals));
+    if let Some(path) = &vars_file {
+        let raw = std::fs::read_to_string(path).with_context(|| format!("{} lesen", path))?;
+        let vars: Value = serde_yaml::from_str(&raw)
+            .with_context(|| format!("{}: ungültiges YAML", path))
+            .context(error::ErrorKind::Parse)?;
+        match vars {
+            Value::Mapping(_) if globals.is_null() => globals = vars,
+            Value::Mapping(_) => schema::merge_value(&mut globals, vars),
+            Value::Null => {}
+            _ => anyhow::bail!("--vars-file {} muss ein Mapping enthalten", path),
         }
     }
+    let playbook_dir = playbook.and_then(playbook_dir);
+    let template_dir = match &doc {
+        Some(d) => template_dir(&cli, d, &playbook_dir)?,
+        None => cli.template_dir.clone(),
+    };
+    let strict = doc.as_ref().is_some_and(|d| d.strict_vars || d.strict);
+    let opts = executor::Options {
+        verbose: cli.verbose,
+        strict_vars: cli.strict_vars || strict,
+        template_dir,
+        workdir: cli.workdir.clone(),
+        playbook_dir,
+        ..Default::default()
+    };
+    let (cwd, defaults, macros) = match doc {
+        Some(d) => (d.cwd, d.defaults, d.template_macros),
+        None => Default::default(),
+    };
+    let mut exec = executor::Executor::new(globals, cwd, defaults, opts)?;
+    if let Some(src) = &macros {
+        exec.set_template_macros(src)?;
+    }
+    let out = exec
+        .render_file(std::path::Path::new(tpl))
+        .context(error::ErrorKind::Template)?;
+    print!("{}", out);
     Ok(())
 }
+
+/// Phase nach Nummer (ab 1) oder Name, wie `resolve_step` für Schritte.
+fn pick_phase(phases: &[Document], sel: &str) -> Result<usize> {
+    if let Ok(n) = sel.parse::<usize>() {
+        return match n {
+            1.. if n <= phases.len() => Ok(n - 1),
+            _ => anyhow::bail!("--phase {}: Playbook hat {} Phase(n)", n, phases.len()),
+        };
+    }
+    phases
+        .iter()
+        .position(|d| d.phase.as_deref() == Some(sel))
+        .with_context(|| format!("--phase: keine Phase '{}'", sel))
+}
+
+/// Tags aller Phasen (inklusive rescue) sortiert mit der Zahl ihrer Schritte,
+/// Schritte ohne Tag zuletzt als `(untagged)`.
+fn list_tags(phases: &[Document]) -> String {
+    let mut counts = std::collections::BTreeMap::<&str, usize>::new();
+    let mut untagged = 0;
+    for step in phases.iter().flat_map(|d| d.steps.iter().chain(&d.rescue)) {
+        if step.tags.is_empty() {
+            untagged += 1;
+        }
+        // doppelte Tags an einem Schritt zählen einmal
+        let tags = step.tags.iter().collect::<std::collections::BTreeSet<_>>();
+        for tag in tags {
+            *counts.entry(tag).or_default() += 1;
+        }
+    }
+    let width = counts.keys().map(|t| t.len()).max().unwrap_or(0).max(10);
+    let mut out = counts
+        .into_iter()
+        .map(|(tag, n)| format!("{:<width$}  {}\n", tag, n))
+        .collect::<String>();
+    if untagged > 0 {
+        out += &format!("{:<width$}  {}\n", "(untagged)", untagged);
+    }
+    out
+}
+
+/// Liest das Playbook aus der Datei oder bei `-` von stdin.
+fn read_playbook(file: &str) -> Result<String> {
+    if file != "-" {
+        return std::fs::read_to_string(file).context("YAML lesen");
+    }
+    let mut raw = String::new();
+    std::io::Read::read_to_string(&mut std::io::stdin(), &mut raw)
+        .context("YAML von stdin lesen")?;
+    if raw.trim().is_empty() {
+        anyhow::bail!("stdin ist leer, kein Playbook gelesen");
+    }
+    Ok(raw)
+}
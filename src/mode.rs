@@ -0,0 +1,191 @@
+// src/mode.rs
+use anyhow::Result;
+
+/// Parst einen Dateimodus: oktal (`0644`, `644`, `0o644`), im `ls`-Format
+/// (`rw-r--r--`) oder symbolisch (`u+x`, `go-w`, `u=rw,g=r,o=`).
+/// Symbolische Angaben wirken auf `current`. Gemeinsam für alle Schritte mit `mode`.
+pub fn parse_mode(s: &str, current: u32) -> Result<u32> {
+    let s = s.trim();
+    let octal = s.strip_prefix("0o").unwrap_or(s);
+    if !octal.is_empty() && octal.len() <= 4 && octal.chars().all(|c| c.is_digit(8)) {
+        return Ok(u32::from_str_radix(octal, 8)?);
+    }
+    if let Some(mode) = parse_ls(s) {
+        return Ok(mode);
+    }
+    let mut mode = current;
+    for clause in s.split(',') {
+        mode = apply_clause(clause, mode).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Ungültiger Modus '{}' (erwartet z. B. \"0644\", \"rw-r--r--\" oder \"u=rw,go=r\")",
+                s
+            )
+        })?;
+    }
+    Ok(mode)
+}
+
+fn apply_clause(clause: &str, mode: u32) -> Option<u32> {
+    let op_pos = clause.find(['+', '-', '='])?;
+    let (who, rest) = clause.split_at(op_pos);
+    let mut who_mask = 0;
+    for c in who.chars() {
+        who_mask |= match c {
+            'u' => 0o700,
+            'g' => 0o070,
+            'o' => 0o007,
+            'a' => 0o777,
+            _ => return None,
+        };
+    }
+    if who_mask == 0 {
+        who_mask = 0o777;
+    }
+    let (op, perms) = rest.split_at(1);
+    let mut perm_mask = 0;
+    for c in perms.chars() {
+        perm_mask |= match c {
+            'r' => 0o444,
+            'w' => 0o222,
+            'x' => 0o111,
+            _ => return None,
+        };
+    }
+    let bits = who_mask & perm_mask;
+    Some(match op {
+        "+" => mode | bits,
+        "-" => mode & !bits,
+        _ => (mode & !who_mask) | bits,
+    })
+}
+
+/// `ls`-Format mit genau neun Zeichen; `s`/`S` und `t`/`T` setzen
+/// setuid/setgid bzw. sticky mit bzw. ohne Ausführungsrecht.
+fn parse_ls(s: &str) -> Option<u32> {
+    let chars = s.chars().collect::<Vec<_>>();
+    if chars.len() != 9 {
+        return None;
+    }
+    let mut mode = 0;
+    for (i, c) in chars.into_iter().enumerate() {
+        let bit = 0o400 >> i;
+        // Sonderbit der jeweiligen Triade: setuid, setgid, sticky
+        let special = 0o4000 >> (i / 3);
+        mode |= match (i % 3, c) {
+            (_, '-') => 0,
+            (0, 'r') => bit,
+            (1, 'w') => bit,
+            (2, 'x') => bit,
+            (2, 's') if i < 8 => bit | special,
+            (2, 'S') if i < 8 => special,
+            (2, 't') if i == 8 => bit | special,
+            (2, 'T') if i == 8 => special,
+            _ => return None,
+        };
+    }
+    Some(mode)
+}
+
+/// Parst eine umask wie `022` oder `0027`; nur oktal, symbolisch ergibt keinen Sinn.
+pub fn parse_umask(s: &str) -> Result<u32> {
+    let s = s.trim();
+    let octal = s.strip_prefix("0o").unwrap_or(s);
+    if octal.is_empty() || octal.len() > 4 || !octal.chars().all(|c| c.is_digit(8)) {
+        anyhow::bail!("Ungültige umask '{}' (erwartet oktal, z. B. \"022\")", s);
+    }
+    Ok(u32::from_str_radix(octal, 8)? & 0o777)
+}
+
+/// Wandelt `mode` als YAML-Zahl in die Stringform. Zahlen gelten wie bei Ansible
+/// als Wert (`420` bzw. `0o644` = `0644`); `mode: 644` ist fast immer als oktal
+/// gemeint, daher sind Zahlen über `0o777` ein Fehler mit Hinweis.
+pub fn from_number(n: u64) -> Result<String> {
+    if n > 0o777 {
+        anyhow::bail!(
+            "mode {} als Zahl ist dezimal ({:o} oktal); oktal als String angeben, z. B. \"0{}\"",
+            n,
+            n,
+            n
+        );
+    }
+    Ok(format!("0o{:o}", n))
+}
+
+/// Argument für `chmod` auf einem entfernten Host: oktale und `ls`-Angaben
+/// werden oktal, symbolische Klauseln bleiben erhalten, da dort der aktuelle
+/// Modus gilt. Ungültige Angaben sind wie bei `parse_mode` ein Fehler.
+pub fn chmod_arg(s: &str) -> Result<String> {
+    let absolute = parse_mode(s, 0)?;
+    if parse_mode(s, 0o7777)? == absolute {
+        Ok(format!("{:04o}", absolute))
+    } else {
+        Ok(s.trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepted_forms() {
+        for (input, current, want) in [
+            ("0644", 0, 0o644),
+            ("644", 0, 0o644),
+            ("0o644", 0, 0o644),
+            ("4755", 0, 0o4755),
+            ("rw-r--r--", 0, 0o644),
+            ("rwsr-xr-t", 0, 0o5755),
+            ("u=rw,g=r,o=r", 0o777, 0o644),
+            ("u=rw,g=r,o=", 0o777, 0o640),
+            ("u+x", 0o644, 0o744),
+            ("go-w", 0o666, 0o644),
+            ("a+x", 0o644, 0o755),
+            ("+x", 0o600, 0o711),
+        ] {
+            assert_eq!(parse_mode(input, current).unwrap(), want, "{}", input);
+        }
+    }
+
+    #[test]
+    fn number_is_value() {
+        assert_eq!(from_number(420).unwrap(), "0o644");
+        assert_eq!(parse_mode(&from_number(420).unwrap(), 0).unwrap(), 0o644);
+        assert_eq!(from_number(0o755).unwrap(), "0o755");
+        assert!(from_number(644).is_err());
+    }
+
+    #[test]
+    fn rejected() {
+        for input in [
+            "999",
+            "rwx",
+            "u=q",
+            "17777",
+            "0o17777",
+            "",
+            "rw-r--r-x-",
+            "z+x",
+        ] {
+            assert!(parse_mode(input, 0o644).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn umask() {
+        assert_eq!(parse_umask("022").unwrap(), 0o022);
+        assert_eq!(parse_umask("0027").unwrap(), 0o027);
+        assert_eq!(parse_umask("0o077").unwrap(), 0o077);
+        for input in ["", "u=rwx", "999", "00000"] {
+            assert!(parse_umask(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn chmod_keeps_symbolic() {
+        assert_eq!(chmod_arg("644").unwrap(), "0644");
+        assert_eq!(chmod_arg("rw-r-----").unwrap(), "0640");
+        assert_eq!(chmod_arg("u+x").unwrap(), "u+x");
+        assert!(chmod_arg("u=q").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+// src/notify.rs
+use crate::schema::OnFailure;
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Anzahl stderr-Zeilen, die an Benachrichtigungen gehen.
+const STDERR_TAIL: usize = 20;
+
+/// Details eines fehlgeschlagenen Schritts für Benachrichtigungen.
+#[derive(Debug, serde::Serialize)]
+pub struct Failure {
+    pub index: usize, // ab 1
+    pub step: String,
+    pub rc: Option<i32>,
+    pub signal: Option<String>, // z. B. "SIGKILL", dann ist `rc` leer
+    pub error: String,
+    pub stderr: String, // letzte Zeilen
+}
+
+impl Failure {
+    pub fn new(index: usize, step: String, e: &anyhow::Error) -> Self {
+        let pf = e.downcast_ref::<crate::executor::ProcessFailed>();
+        let stderr = pf.map_or("", |p| p.stderr.as_str());
+        let lines = stderr.lines().collect::<Vec<_>>();
+        let tail = lines[lines.len().saturating_sub(STDERR_TAIL)..].join("\n");
+        Self {
+            index,
+            step,
+            rc: pf.and_then(|p| p.rc),
+            signal: pf.and_then(|p| p.signal).map(crate::executor::signal_name),
+            error: format!("{:#}", e),
+            stderr: tail,
+        }
+    }
+}
+
+pub trait Notifier {
+    fn notify(&self, failure: &Failure) -> Result<()>;
+}
+
+/// Führt ein Shell-Kommando mit `RR_STEP`, `RR_STEP_INDEX`, `RR_RC`,
+/// `RR_SIGNAL`, `RR_ERROR` und `RR_STDERR` in der Umgebung aus.
+pub struct CommandNotifier {
+    pub command: String,
+}
+
+impl Notifier for CommandNotifier {
+    fn notify(&self, f: &Failure) -> Result<()> {
+        let (prg, args) = crate::executor::shell_argv(None, &self.command)?;
+        let status = Command::new(prg)
+            .args(args)
+            .env("RR_STEP", &f.step)
+            .env("RR_STEP_INDEX", f.index.to_string())
+            .env("RR_RC", f.rc.map(|rc| rc.to_string()).unwrap_or_default())
+            .env("RR_SIGNAL", f.signal.as_deref().unwrap_or_default())
+            .env("RR_ERROR", &f.error)
+            .env("RR_STDERR", &f.stderr)
+            .status()
+            .context("on_failure command starten")?;
+        if !status.success() {
+            anyhow::bail!("on_failure command endete mit {}", status);
+        }
+        Ok(())
+    }
+}
+
+/// POSTet die Details als JSON über `curl` (HTTPS ohne eigenen TLS-Stack).
+pub struct HttpNotifier {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Notifier for HttpNotifier {
+    fn notify(&self, f: &Failure) -> Result<()> {
+        let mut cmd = Command::new("curl");
+        cmd.args(["-fsS", "-X", "POST", "-H", "Content-Type: application/json"]);
+        for (k, v) in &self.headers {
+            cmd.args(["-H", &format!("{}: {}", k, v)]);
+        }
+        let mut child = cmd
+            .args(["--data-binary", "@-", &self.url])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .context("curl für on_failure.http starten")?;
+        let body = serde_json::to_vec(f)?;
+        child.stdin.take().context("curl stdin")?.write_all(&body)?;
+        let status = child.wait()?;
+        if !status.success() {
+            anyhow::bail!(
+                "on_failure.http an {} fehlgeschlagen ({})",
+                self.url,
+                status
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Baut die konfigurierten Notifier. Werte werden nicht gerendert.
+pub fn from_config(cfg: &OnFailure) -> Vec<Box<dyn Notifier>> {
+    let mut out: Vec<Box<dyn Notifier>> = Vec::new();
+    if let Some(command) = &cfg.command {
+        out.push(Box::new(CommandNotifier {
+            command: command.clone(),
+        }));
+    }
+    if let Some(http) = &cfg.http {
+        let mut headers = http.headers.clone().into_iter().collect::<Vec<_>>();
+        headers.sort();
+        out.push(Box::new(HttpNotifier {
+            url: http.url.clone(),
+            headers,
+        }));
+    }
+    out
+}
+
+/// Benachrichtigt alle Notifier; deren Fehler werden nur gemeldet und ändern
+/// den Ausgang des Laufs nicht.
+pub fn notify_all(notifiers: &[Box<dyn Notifier>], failures: &[Failure]) {
+    for f in failures {
+        for n in notifiers {
+            if let Err(e) = n.notify(f) {
+                eprintln!("[on_failure] {:#}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,221 @@
+// src/observer.rs
+use crate::executor::StepOutcome;
+use crate::output::{self, Stream};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Beginn eines Schritts (bzw. eines erneuten Versuchs bei `retry`/`until`).
+pub struct StepStart<'a> {
+    pub idx: usize, // ab 0
+    pub name: &'a str,
+    pub kind: &'a str,            // "shell", "exec", "conf", ...
+    pub command: Option<&'a str>, // gerendert, `None` bei `no_log`
+}
+
+/// Eine Ausgabezeile eines Kindprozesses, die auf die Konsole gehört
+/// (umgeleitete Streams und `no_log` erzeugen keine Ereignisse).
+pub struct OutputLine<'a> {
+    pub idx: usize,
+    pub prefix: &'a str, // `[3:deploy]`, mit Hook-Markierung
+    pub src: &'a str,    // "out" | "err"
+    pub line: &'a str,
+    pub to_stderr: bool, // ohne combine_output landet stderr auf stderr
+}
+
+/// Lebenszyklus-Ereignisse eines Laufs, z. B. für eine Fortschrittsanzeige.
+/// Die Methoden werden aus mehreren Tasks gleichzeitig aufgerufen (`--jobs`).
+pub trait Observer: Send + Sync {
+    fn on_step_start(&self, step: &StepStart);
+    fn on_step_output(&self, line: &OutputLine);
+    /// Bytes eines Schritts mit `raw_output`, ohne Präfix und Zeilengrenzen.
+    fn on_step_raw(&self, _idx: usize, to_stderr: bool, bytes: &[u8]) {
+        output::raw(if to_stderr { Stream::Err } else { Stream::Out }, bytes);
+    }
+    /// Ende jedes Schritts, auch wenn er per `when` übersprungen wurde.
+    fn on_step_end(&self, idx: usize, name: &str, result: &Result<StepOutcome>);
+    /// Ein Prozess gibt seit `IDLE_AFTER` nichts aus: regelmäßig mit der
+    /// bisherigen Laufzeit, einmal mit `None`, sobald er beendet ist.
+    fn on_step_wait(&self, _idx: usize, _prefix: &str, _running: Option<Duration>) {}
+}
+
+/// Stille, ab der `on_step_wait` gemeldet wird.
+pub const IDLE_AFTER: Duration = Duration::from_secs(2);
+
+/// Die bisherige Konsolenausgabe: Header, präfixierte Zeilen und mit
+/// `-v` eine Ergebniszeile, ab `-vv` mit Laufzeit. Mit `spinner` zeigt eine Statuszeile auf
+/// stderr, dass ein stiller Prozess noch läuft.
+pub struct ConsoleObserver {
+    pub verbose: u8,
+    pub timestamps: bool,
+    pub spinner: bool, // nur auf einem Terminal, siehe `output::status_supported`
+}
+
+impl Observer for ConsoleObserver {
+    fn on_step_start(&self, step: &StepStart) {
+        println!("\n==[{}] {} ==", step.idx + 1, step.name);
+        // no_log zeigt nur den Namen
+        if let Some(cmd) = step.command {
+            println!("-> {}", cmd);
+        }
+    }
+
+    fn on_step_output(&self, l: &OutputLine) {
+        let text = fmt_line(l.prefix, l.src, l.line, self.timestamps);
+        output::line(
+            if l.to_stderr {
+                Stream::Err
+            } else {
+                Stream::Out
+            },
+            &text,
+        );
+    }
+
+    fn on_step_end(&self, _idx: usize, _name: &str, result: &Result<StepOutcome>) {
+        let Ok(o) = result else { return };
+        match self.verbose {
+            0 => {}
+            1 => println!("<- rc={} changed={} skipped={}", o.rc, o.changed, o.skipped),
+            _ => println!(
+                "<- rc={} changed={} skipped={} ({:.2?})",
+                o.rc, o.changed, o.skipped, o.duration
+            ),
+        }
+    }
+
+    fn on_step_wait(&self, idx: usize, prefix: &str, running: Option<Duration>) {
+        if !self.spinner {
+            return;
+        }
+        let Some(running) = running else {
+            output::clear_status(idx);
+            return;
+        };
+        let frames: &[char] = if output::no_color() {
+            &['-', '\\', '|', '/']
+        } else {
+            &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏']
+        };
+        let frame = frames[(running.as_millis() / 250) as usize % frames.len()];
+        output::status(
+            idx,
+            &format!("{} {} läuft seit {}s", frame, prefix, running.as_secs()),
+        );
+    }
+}
+
+/// `--quiet`: keine Header und keine Ausgabe erfolgreicher Schritte. Beides
+/// wird je Schritt gepuffert und nur ausgegeben, wenn er scheitert, dann auf
+/// stderr und mit allen Versuchen.
+#[derive(Default)]
+pub struct QuietObserver {
+    buffered: Mutex<BTreeMap<usize, Vec<u8>>>,
+}
+
+impl QuietObserver {
+    fn push(&self, idx: usize, bytes: &[u8]) {
+        let mut buffered = self.buffered.lock().unwrap_or_else(|e| e.into_inner());
+        buffered.entry(idx).or_default().extend_from_slice(bytes);
+    }
+}
+
+impl Observer for QuietObserver {
+    fn on_step_start(&self, step: &StepStart) {
+        let mut header = format!("==[{}] {} ==\n", step.idx + 1, step.name);
+        if let Some(cmd) = step.command {
+            header += &format!("-> {}\n", cmd);
+        }
+        self.push(step.idx, header.as_bytes());
+    }
+
+    fn on_step_output(&self, l: &OutputLine) {
+        let text = fmt_line(l.prefix, l.src, l.line, false) + "\n";
+        self.push(l.idx, text.as_bytes());
+    }
+
+    fn on_step_raw(&self, idx: usize, _to_stderr: bool, bytes: &[u8]) {
+        self.push(idx, bytes);
+    }
+
+    fn on_step_end(&self, idx: usize, _name: &str, result: &Result<StepOutcome>) {
+        let buffered = self
+            .buffered
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&idx);
+        if let (Some(bytes), Err(_)) = (buffered, result) {
+            output::raw(Stream::Err, &bytes);
+        }
+    }
+}
+
+fn fmt_line(prefix: &str, src: &str, line: &str, timestamps: bool) -> String {
+    if timestamps {
+        let now = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false);
+        format!("{} {}[{}] {}", now, prefix, src, line)
+    } else {
+        format!("{}[{}] {}", prefix, src, line)
+    }
+}
+
+/// Fortschrittsbalken über alle Schritte; Ausgabezeilen erscheinen oberhalb.
+#[cfg(feature = "progress")]
+pub struct ProgressObserver {
+    bar: indicatif::ProgressBar,
+}
+
+#[cfg(feature = "progress")]
+impl ProgressObserver {
+    pub fn new(total: usize) -> Self {
+        let bar = indicatif::ProgressBar::new(total as u64);
+        bar.set_style(
+            indicatif::ProgressStyle::with_template("{bar:30} {pos}/{len} {elapsed} {msg}")
+                .expect("gültiges Template"),
+        );
+        Self { bar }
+    }
+}
+
+#[cfg(feature = "progress")]
+impl Observer for ProgressObserver {
+    fn on_step_start(&self, step: &StepStart) {
+        self.bar
+            .set_message(format!("{} ({})", step.name, step.kind));
+    }
+
+    fn on_step_output(&self, l: &OutputLine) {
+        // `println` des Balkens schweigt ohne Terminal, `suspend` schreibt immer
+        let text = fmt_line(l.prefix, l.src, l.line, false);
+        let stream = if l.to_stderr {
+            Stream::Err
+        } else {
+            Stream::Out
+        };
+        self.bar.suspend(|| output::line(stream, &text));
+    }
+
+    fn on_step_raw(&self, _idx: usize, to_stderr: bool, bytes: &[u8]) {
+        let stream = if to_stderr { Stream::Err } else { Stream::Out };
+        self.bar.suspend(|| output::raw(stream, bytes));
+    }
+
+    fn on_step_end(&self, idx: usize, name: &str, result: &Result<StepOutcome>) {
+        let state = match result {
+            Ok(o) if o.skipped => match &o.skip_reason {
+                Some(reason) => format!("übersprungen ({})", reason),
+                None => "übersprungen".into(),
+            },
+            Ok(o) if o.changed => "geändert".into(),
+            Ok(_) => "ok".into(),
+            Err(_) => "fehlgeschlagen".into(),
+        };
+        let text = format!("[{}] {}: {}", idx + 1, name, state);
+        self.bar.suspend(|| output::line(Stream::Out, &text));
+        self.bar.inc(1);
+        if self.bar.position() >= self.bar.length().unwrap_or(0) {
+            self.bar.finish_and_clear();
+        }
+    }
+}
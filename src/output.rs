@@ -0,0 +1,90 @@
+// src/output.rs
+use std::io::Write;
+use std::sync::Mutex;
+
+/// Ein Lock für stdout und stderr gemeinsam: jede Zeile wird samt Zeilenumbruch
+/// mit einem einzigen `write_all` geschrieben, auch wenn beide Streams auf
+/// dasselbe Terminal zeigen oder mehrere Schritte parallel ausgeben.
+/// Geschützt wird zugleich die Statuszeile (Besitzer-Schritt, angezeigte Breite).
+static LOCK: Mutex<Option<(usize, usize)>> = Mutex::new(None);
+
+#[derive(Clone, Copy)]
+pub enum Stream {
+    Out,
+    Err,
+}
+
+/// Schreibt `line` atomar als ganze Zeile; Schreibfehler (z. B. geschlossene Pipe)
+/// werden wie bei `println!` nicht gemeldet, aber auch nicht zur Panic.
+pub fn line(stream: Stream, line: &str) {
+    let mut buf = Vec::with_capacity(line.len() + 1);
+    buf.extend_from_slice(line.as_bytes());
+    buf.push(b'\n');
+    raw(stream, &buf);
+}
+
+/// Schreibt Bytes unverändert, auch ohne Zeilenumbruch (`raw_output`).
+pub fn raw(stream: Stream, bytes: &[u8]) {
+    // ein Panic in einem anderen Schreiber soll die Ausgabe nicht blockieren
+    let mut status = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    // eine Zeile beginnt nie hinter der Statuszeile
+    if let Some((_, width)) = status.take() {
+        erase(width);
+    }
+    let _ = match stream {
+        Stream::Out => {
+            let mut out = std::io::stdout().lock();
+            out.write_all(bytes).and_then(|_| out.flush())
+        }
+        Stream::Err => {
+            let mut err = std::io::stderr().lock();
+            err.write_all(bytes).and_then(|_| err.flush())
+        }
+    };
+}
+
+/// `NO_COLOR` (nicht leer) schaltet Escape-Sequenzen und Unicode-Symbole ab.
+pub fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+}
+
+/// Statuszeile nur, wenn stdout und stderr ein Terminal sind; in Pipes und
+/// Logdateien würde sie die Ausgabe verunreinigen.
+pub fn status_supported() -> bool {
+    use std::io::IsTerminal;
+    std::io::stdout().is_terminal() && std::io::stderr().is_terminal()
+}
+
+/// Zeichnet die Statuszeile von Schritt `owner` auf stderr (ohne Zeilenumbruch),
+/// sie verschwindet mit der nächsten Zeile oder `clear_status`.
+pub fn status(owner: usize, text: &str) {
+    let mut status = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, width)) = status.take() {
+        erase(width);
+    }
+    let mut err = std::io::stderr().lock();
+    let _ = write!(err, "{}", text).and_then(|_| err.flush());
+    *status = Some((owner, text.chars().count()));
+}
+
+/// Entfernt die Statuszeile, sofern sie noch von `owner` stammt.
+pub fn clear_status(owner: usize) {
+    let mut status = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((o, width)) = *status
+        && o == owner
+    {
+        erase(width);
+        *status = None;
+    }
+}
+
+/// Löscht die aktuelle Terminalzeile; ohne Escape-Sequenzen per Leerzeichen.
+fn erase(width: usize) {
+    let mut err = std::io::stderr().lock();
+    let _ = if no_color() {
+        write!(err, "\r{}\r", " ".repeat(width))
+    } else {
+        write!(err, "\r\x1b[2K")
+    }
+    .and_then(|_| err.flush());
+}
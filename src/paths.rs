@@ -0,0 +1,144 @@
+// src/paths.rs
+use anyhow::{Context, Result};
+
+/// Expandiert `~` am Anfang sowie `${VAR}`/`$VAR` aus der Umgebung des
+/// Prozesses in einem (bereits gerenderten) Pfad. `$$` ergibt ein einzelnes `$`.
+pub fn expand(raw: &str) -> Result<String> {
+    expand_with(raw, |name| std::env::var(name).ok())
+}
+
+/// Wie `expand`, die Werte liefert `lookup` (auch `HOME`/`USERPROFILE` für `~`).
+pub fn expand_with(raw: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    if let Some(r) = rest.strip_prefix('~')
+        && (r.is_empty() || r.starts_with('/') || r.starts_with('\\'))
+    {
+        let home = lookup("HOME").or_else(|| lookup("USERPROFILE"));
+        out.push_str(&home.context("~ im Pfad, aber HOME ist nicht gesetzt")?);
+        rest = r;
+    }
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if let Some(r) = rest.strip_prefix("$$") {
+            out.push('$');
+            rest = r;
+        } else if let Some((name, len)) = var_ref(rest)? {
+            let val = lookup(name)
+                .with_context(|| format!("Umgebungsvariable {} im Pfad ist nicht gesetzt", name))?;
+            out.push_str(&val);
+            rest = &rest[len..];
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Name und Länge einer Referenz `${NAME}` oder `$NAME` am Anfang von `s`.
+fn var_ref(s: &str) -> Result<Option<(&str, usize)>> {
+    if let Some(r) = s.strip_prefix("${") {
+        let end = r.find('}').context("`${` ohne schließende `}` im Pfad")?;
+        return Ok(Some((&r[..end], end + 3)));
+    }
+    let r = &s[1..];
+    if !r.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return Ok(None);
+    }
+    let len = r
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(r.len());
+    Ok(Some((&r[..len], len + 1)))
+}
+
+/// Sucht ein Programm wie die Shell: mit Pfadtrenner direkt, sonst in `$PATH`
+/// (unter Windows auch mit `.exe`).
+pub fn which(prg: &str) -> Option<std::path::PathBuf> {
+    let candidates = |p: std::path::PathBuf| {
+        let exe = cfg!(windows).then(|| p.with_extension("exe"));
+        std::iter::once(p).chain(exe)
+    };
+    if prg.contains(['/', '\\']) {
+        return candidates(prg.into()).find(|p| p.is_file());
+    }
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .flat_map(|dir| candidates(dir.join(prg)))
+        .find(|p| p.is_file())
+}
+
+/// Macht aus einem Schritt- oder Phasennamen einen Dateinamen-Teil: alles
+/// außer Buchstaben, Ziffern, `-`, `_` und `.` wird zu `_`, führende Punkte
+/// entfallen. Leere Namen ergeben `step`.
+pub fn file_stem(name: &str) -> String {
+    let stem = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    match stem.trim_start_matches('.') {
+        "" => "step".into(),
+        s => s.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/tester".into()),
+            "SUB" => Some("conf".into()),
+            "DOLLAR" => Some("a$SUB".into()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expand_home_and_vars() {
+        for (raw, want) in [
+            ("~/.ssh/id_rsa", "/home/tester/.ssh/id_rsa"),
+            ("~", "/home/tester"),
+            ("${HOME}/x", "/home/tester/x"),
+            ("$HOME", "/home/tester"),
+            ("$HOME/a/$SUB.d", "/home/tester/a/conf.d"),
+            ("/etc/${SUB}/app", "/etc/conf/app"),
+            // ~ nur am Anfang und vor `/`
+            ("/srv/~/x", "/srv/~/x"),
+            ("a~b", "a~b"),
+            ("~user/x", "~user/x"),
+            // $$ und `$` ohne Namen bleiben ein `$`
+            ("/tmp/$$x", "/tmp/$x"),
+            ("/tmp/a$", "/tmp/a$"),
+            ("/tmp/$1", "/tmp/$1"),
+            // eingesetzte Werte werden nicht noch einmal expandiert
+            ("/x/$DOLLAR", "/x/a$SUB"),
+        ] {
+            assert_eq!(expand_with(raw, env).unwrap(), want, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn expand_errors() {
+        assert!(expand_with("$NOT_SET/x", env).is_err());
+        assert!(expand_with("${SUB", env).is_err());
+        assert!(expand_with("~/x", |_| None).is_err());
+    }
+
+    #[test]
+    fn stem() {
+        assert_eq!(file_stem("build/all"), "build_all");
+        assert_eq!(file_stem("..hidden"), "hidden");
+        assert_eq!(file_stem("  "), "step");
+    }
+}
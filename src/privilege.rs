@@ -0,0 +1,206 @@
+// src/privilege.rs
+use anyhow::Result;
+
+/// Rechteerhöhung eines Schritts (`become`), bereits gerendert.
+#[derive(Debug, Clone)]
+pub struct Become {
+    pub method: Method,
+    pub user: String,
+    pub password: Option<String>, // `become_password`, nur sudo über ssh
+}
+
+/// Liest remote die erste Zeile von stdin (das Passwort) und gibt sie an
+/// `sudo -S -v`; das eigentliche Kommando läuft danach mit `sudo -n` über den
+/// frischen Zeitstempel und bekommt den Rest von stdin. So landet das Passwort
+/// weder in der Kommandozeile noch bei NOPASSWD im Kommando.
+const SUDO_VALIDATE: &str = "IFS= read -r p && printf '%s\\n' \"$p\" | sudo -S -p '' -v && ";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    Sudo,
+    Su,
+    Doas,
+}
+
+impl Method {
+    pub fn parse(s: Option<&str>) -> Result<Self> {
+        Ok(match s.unwrap_or("sudo") {
+            "sudo" => Method::Sudo,
+            "su" => Method::Su,
+            "doas" => Method::Doas,
+            other => anyhow::bail!("unbekannte become_method '{}' (sudo|su|doas)", other),
+        })
+    }
+}
+
+impl Become {
+    /// Lokale Ausführung: Programm und Argumente mit dem Präfix der Methode.
+    /// `su` erwartet eine Kommandozeile, die Argumente werden dafür escaped.
+    pub fn wrap_argv(&self, argv: Vec<String>) -> Vec<String> {
+        let user = self.user.clone();
+        match self.method {
+            Method::Sudo => ["sudo".into(), "-u".into(), user, "--".into()]
+                .into_iter()
+                .chain(argv)
+                .collect(),
+            Method::Doas => ["doas".into(), "-u".into(), user, "--".into()]
+                .into_iter()
+                .chain(argv)
+                .collect(),
+            Method::Su => vec![
+                "su".into(),
+                "-".into(),
+                user,
+                "-c".into(),
+                join_escaped(&argv),
+            ],
+        }
+    }
+
+    /// Remote (ssh): die Kommandozeile läuft in einer Shell des Zielbenutzers.
+    /// sudo und doas fragen nie nach einem Passwort (`-n`), ohne Terminal
+    /// würden sie sonst scheitern oder hängen; mit `password` siehe `SUDO_VALIDATE`.
+    pub fn wrap_remote(&self, cmd: &str) -> String {
+        let user = shell_escape::escape(self.user.as_str().into());
+        let quoted = shell_escape::escape(cmd.into());
+        match self.method {
+            Method::Sudo => {
+                let validate = if self.password.is_some() {
+                    SUDO_VALIDATE
+                } else {
+                    ""
+                };
+                format!("{}sudo -n -u {} -- sh -c {}", validate, user, quoted)
+            }
+            Method::Doas => format!("doas -n -u {} sh -c {}", user, quoted),
+            Method::Su => format!("su - {} -c {}", user, quoted),
+        }
+    }
+
+    /// Was remote vor allem anderen auf stdin gehört: das Passwort für `SUDO_VALIDATE`.
+    pub fn stdin_prefix(&self) -> Option<String> {
+        self.password.as_ref().map(|pw| format!("{}\n", pw))
+    }
+
+    /// Erklärt, warum die Rechteerhöhung auf `host` gescheitert ist, anhand von
+    /// stderr; `None`, wenn der Fehler nicht von sudo/doas stammt.
+    pub fn explain_failure(&self, host: &str, stderr: &str) -> Option<String> {
+        let tool = match self.method {
+            Method::Sudo => "sudo",
+            Method::Doas => "doas",
+            Method::Su => return None,
+        };
+        if stderr.contains("incorrect password") || stderr.contains("Sorry, try again") {
+            Some(format!(
+                "become: become_password für {} auf {} ist falsch",
+                tool, host
+            ))
+        } else if stderr.contains("a password is required")
+            || stderr.contains("Authentication required")
+        {
+            Some(format!(
+                "become: {} verlangt auf {} ein Passwort; become_password setzen oder NOPASSWD einrichten",
+                tool, host
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+fn join_escaped(argv: &[String]) -> String {
+    argv.iter()
+        .map(|a| shell_escape::escape(a.as_str().into()).into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn become_(method: &str, user: &str, password: Option<&str>) -> Become {
+        Become {
+            method: Method::parse(Some(method)).unwrap(),
+            user: user.into(),
+            password: password.map(Into::into),
+        }
+    }
+
+    fn argv(b: &Become) -> Vec<String> {
+        b.wrap_argv(vec!["cat".into(), "/etc/my file".into()])
+    }
+
+    #[test]
+    fn method_default_and_unknown() {
+        assert_eq!(Method::parse(None).unwrap(), Method::Sudo);
+        assert!(Method::parse(Some("pbrun")).is_err());
+    }
+
+    #[test]
+    fn sudo() {
+        // ohne become_user setzt der Executor `root` ein
+        assert_eq!(
+            argv(&become_("sudo", "root", None)),
+            ["sudo", "-u", "root", "--", "cat", "/etc/my file"]
+        );
+        assert_eq!(
+            argv(&become_("sudo", "deploy", None)),
+            ["sudo", "-u", "deploy", "--", "cat", "/etc/my file"]
+        );
+        assert_eq!(
+            become_("sudo", "root", None).wrap_remote("id -u; echo $HOME"),
+            "sudo -n -u root -- sh -c 'id -u; echo $HOME'"
+        );
+        assert_eq!(
+            become_("sudo", "web admin", None).wrap_remote("id"),
+            "sudo -n -u 'web admin' -- sh -c id"
+        );
+    }
+
+    #[test]
+    fn sudo_with_password() {
+        let b = become_("sudo", "deploy", Some("s3cret"));
+        assert_eq!(
+            b.wrap_remote("id"),
+            format!("{}sudo -n -u deploy -- sh -c id", SUDO_VALIDATE)
+        );
+        assert!(SUDO_VALIDATE.contains("sudo -S -p '' -v"));
+        // das Passwort kommt über stdin, nie in die Kommandozeile
+        assert!(!b.wrap_remote("id").contains("s3cret"));
+        assert_eq!(b.stdin_prefix().as_deref(), Some("s3cret\n"));
+        assert_eq!(become_("sudo", "deploy", None).stdin_prefix(), None);
+    }
+
+    #[test]
+    fn su() {
+        assert_eq!(
+            argv(&become_("su", "root", None)),
+            ["su", "-", "root", "-c", "cat '/etc/my file'"]
+        );
+        assert_eq!(
+            argv(&become_("su", "deploy", None)),
+            ["su", "-", "deploy", "-c", "cat '/etc/my file'"]
+        );
+        assert_eq!(
+            become_("su", "deploy", None).wrap_remote("id -u; whoami"),
+            "su - deploy -c 'id -u; whoami'"
+        );
+    }
+
+    #[test]
+    fn doas() {
+        assert_eq!(
+            argv(&become_("doas", "root", None)),
+            ["doas", "-u", "root", "--", "cat", "/etc/my file"]
+        );
+        assert_eq!(
+            argv(&become_("doas", "deploy", None)),
+            ["doas", "-u", "deploy", "--", "cat", "/etc/my file"]
+        );
+        assert_eq!(
+            become_("doas", "deploy", None).wrap_remote("id -u"),
+            "doas -n -u deploy sh -c 'id -u'"
+        );
+    }
+}
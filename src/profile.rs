@@ -0,0 +1,91 @@
+// src/profile.rs
+use crate::report::StepRecord;
+use std::time::Duration;
+
+/// Verbrauch der Kindprozesse eines Schritts für `--profile`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Usage {
+    pub cpu: Duration,           // user + sys
+    pub max_rss_kb: Option<u64>, // nur, wenn ein Kind dieses Schritts den bisherigen Höchstwert übertraf
+}
+
+/// Stand über alle bisher beendeten Kindprozesse: CPU-Zeit und Peak-RSS in KiB.
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+    cpu: Duration,
+    max_rss_kb: u64,
+}
+
+/// `getrusage(RUSAGE_CHILDREN)`; ohne Unterstützung `None`, dann bleibt es
+/// bei der Laufzeit.
+#[cfg(unix)]
+pub fn snapshot() -> Option<Snapshot> {
+    let mut ru = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: getrusage füllt die Struktur vollständig, wenn es 0 liefert
+    let ru = unsafe {
+        if libc::getrusage(libc::RUSAGE_CHILDREN, ru.as_mut_ptr()) != 0 {
+            return None;
+        }
+        ru.assume_init()
+    };
+    let tv = |t: libc::timeval| {
+        Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+    };
+    // macOS meldet Bytes, Linux und die BSDs KiB
+    let rss = ru.ru_maxrss as u64;
+    let max_rss_kb = if cfg!(target_os = "macos") {
+        rss / 1024
+    } else {
+        rss
+    };
+    Some(Snapshot {
+        cpu: tv(ru.ru_utime) + tv(ru.ru_stime),
+        max_rss_kb,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn snapshot() -> Option<Snapshot> {
+    None
+}
+
+/// Verbrauch zwischen zwei Ständen. Laufen Schritte parallel (`--jobs`),
+/// enthält er auch deren Kinder.
+pub fn delta(before: Snapshot, after: Snapshot) -> Usage {
+    Usage {
+        cpu: after.cpu.saturating_sub(before.cpu),
+        max_rss_kb: (after.max_rss_kb > before.max_rss_kb).then_some(after.max_rss_kb),
+    }
+}
+
+/// Höchstens so viele Zeilen im Report.
+const TOP: usize = 10;
+
+/// Die langsamsten Schritte nach Laufzeit, mit CPU-Zeit und Peak-RSS, soweit bekannt.
+pub fn report(records: &[StepRecord]) -> String {
+    let mut sorted = records.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|r| std::cmp::Reverse(r.duration));
+    let total = records.iter().map(|r| r.duration).sum::<Duration>();
+    let mut out = format!(
+        "\n[profile] {} Schritt(e), zusammen {:.2}s; die langsamsten:\n",
+        records.len(),
+        total.as_secs_f64()
+    );
+    for r in sorted.into_iter().take(TOP) {
+        let cpu = r
+            .usage
+            .map_or("-".into(), |u| format!("{:.2}s", u.cpu.as_secs_f64()));
+        let rss = r
+            .usage
+            .and_then(|u| u.max_rss_kb)
+            .map_or("-".into(), |kb| format!("{:.1} MiB", kb as f64 / 1024.0));
+        out += &format!(
+            "[profile] {:>9.2}s  cpu {:>8}  rss {:>10}  {}\n",
+            r.duration.as_secs_f64(),
+            cpu,
+            rss,
+            r.name
+        );
+    }
+    out
+}
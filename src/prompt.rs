@@ -0,0 +1,65 @@
+// src/prompt.rs
+use crate::schema::Prompt;
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+use std::io::Write;
+
+/// Fragt alle `prompts` ab, die nicht bereits in den Globals gesetzt sind,
+/// und schreibt die Antworten in die Globals.
+pub fn resolve_prompts(globals: &mut Value, prompts: &[Prompt], interactive: bool) -> Result<()> {
+    if globals.is_null() {
+        *globals = Value::Mapping(Default::default());
+    }
+    let map = globals
+        .as_mapping_mut()
+        .context("globals muss ein Mapping sein")?;
+
+    let mut missing = Vec::new();
+    for p in prompts {
+        let key = Value::String(p.name.clone());
+        if map.contains_key(&key) {
+            continue;
+        }
+        let answer = if interactive {
+            Some(ask(p)?)
+        } else {
+            p.default.clone()
+        };
+        match answer {
+            Some(a) => {
+                map.insert(key, Value::String(a));
+            }
+            None => missing.push(p.name.as_str()),
+        }
+    }
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "Fehlende Eingaben im nicht-interaktiven Modus: {}",
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn ask(p: &Prompt) -> Result<String> {
+    let text = p.prompt.as_deref().unwrap_or(&p.name);
+    let label = match &p.default {
+        Some(d) if !p.secret => format!("{} [{}]: ", text, d),
+        _ => format!("{}: ", text),
+    };
+    let input = if p.secret {
+        rpassword::prompt_password(&label).context("Passwort lesen")?
+    } else {
+        print!("{}", label);
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        std::io::stdin()
+            .read_line(&mut line)
+            .context("Eingabe lesen")?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+    match (&p.default, input.is_empty()) {
+        (Some(d), true) => Ok(d.clone()),
+        _ => Ok(input),
+    }
+}
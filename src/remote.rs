@@ -0,0 +1,188 @@
+// src/remote.rs
+use shell_escape::escape;
+
+/// Parameter für das Schreiben einer Datei auf einem ssh-Ziel (`conf.remote`),
+/// alle Werte bereits gerendert.
+pub struct RemoteWrite {
+    pub dest: String,
+    pub file_name: Option<String>, // für `dest` als Verzeichnis, aus template_file
+    pub mode: Option<String>,      // chmod-Argument, siehe `mode::chmod_arg`
+    pub dir_mode: u32,             // neu angelegte Elternverzeichnisse, per umask
+    pub new_file_mode: Option<u32>, // neue Datei ohne `mode`, aus `umask`
+    pub backup_dir: Option<String>, // `Some` = backup, leer = neben dest
+    pub stamp: String,
+    pub validate: Option<String>,
+    pub rollback: bool,
+    pub check: bool, // nur vergleichen
+}
+
+/// Zeile, mit der das Skript meldet, ob sich die Datei ändert.
+pub const CHANGED_MARKER: &str = "rr-changed=";
+
+impl RemoteWrite {
+    /// POSIX-Shell-Skript, das den Inhalt von stdin nach `dest` schreibt:
+    /// temporäre Datei neben `dest`, Vergleich von Inhalt und Modus, optional
+    /// Backup und `validate` (mit `%s` vor, ohne nach dem Verschieben).
+    /// Unverändert bleibt die Datei unangetastet; mit `check` wird am Ziel
+    /// nichts angelegt oder geschrieben.
+    pub fn script(&self) -> String {
+        let mut s = String::from("set -e\n");
+        s += &format!("dest={}\n", escape(self.dest.as_str().into()));
+        if let Some(name) = &self.file_name {
+            s += &format!(
+                "if [ -d \"$dest\" ] || [ \"${{dest%/}}\" != \"$dest\" ]; then dest=\"${{dest%/}}/\"{}; fi\n",
+                escape(name.as_str().into())
+            );
+        }
+        if self.check {
+            // nichts am Ziel anlegen: fehlt das Verzeichnis, ändert sich die
+            // Datei sicher, sonst Vergleich über eine Datei in $TMPDIR
+            s += &format!(
+                "if [ ! -d \"$(dirname \"$dest\")\" ]; then cat > /dev/null; echo \"{}1\"; exit 0; fi\n",
+                CHANGED_MARKER
+            );
+            s += "tmp=\"$(mktemp \"${TMPDIR:-/tmp}/rr-check.XXXXXX\")\"\n";
+        } else {
+            s += &format!(
+                "(umask {:03o}; mkdir -p \"$(dirname \"$dest\")\")\n",
+                0o777 & !self.dir_mode
+            );
+            s += "tmp=\"$(dirname \"$dest\")/.$(basename \"$dest\").rr-tmp-$$\"\n";
+        }
+        s += "trap 'rm -f \"$tmp\"' EXIT\n";
+        s += "cat > \"$tmp\"\n";
+        // ohne mode bleibt der Modus einer bestehenden Datei erhalten
+        s += "if [ -e \"$dest\" ]; then chmod \"$(stat -c %a \"$dest\")\" \"$tmp\"; fi\n";
+        if let Some(m) = self.new_file_mode {
+            s += &format!("if [ ! -e \"$dest\" ]; then chmod {:04o} \"$tmp\"; fi\n", m);
+        }
+        if let Some(m) = &self.mode {
+            s += &format!("chmod {} \"$tmp\"\n", escape(m.as_str().into()));
+        }
+        s += "changed=1\n";
+        s += "if [ -f \"$dest\" ] && cmp -s \"$tmp\" \"$dest\" \\\n";
+        s += "  && [ \"$(stat -c %a \"$tmp\")\" = \"$(stat -c %a \"$dest\")\" ]; then changed=0; fi\n";
+        s += &format!("echo \"{}$changed\"\n", CHANGED_MARKER);
+        if self.check {
+            return s;
+        }
+        s += "[ \"$changed\" = 1 ] || exit 0\n";
+        let validate = self.validate.as_deref();
+        if let Some(v) = validate.filter(|v| v.contains("%s")) {
+            s += &format!(
+                "if ! {}; then echo 'validate fehlgeschlagen' >&2; exit 1; fi\n",
+                v.replace("%s", "\"$tmp\"")
+            );
+        }
+        s += "bak=\n";
+        if let Some(dir) = &self.backup_dir {
+            let dir = if dir.is_empty() {
+                "\"$(dirname \"$dest\")\"".to_string()
+            } else {
+                escape(dir.as_str().into()).into_owned()
+            };
+            s += &format!(
+                "if [ -e \"$dest\" ]; then mkdir -p {dir}; bak={dir}/\"$(basename \"$dest\")\".{stamp}.bak; cp -p \"$dest\" \"$bak\"; echo \"[conf] backup -> $bak\"; fi\n",
+                dir = dir,
+                stamp = escape(self.stamp.as_str().into())
+            );
+        }
+        s += "existed=0; if [ -e \"$dest\" ]; then existed=1; fi\n";
+        s += "mv -f \"$tmp\" \"$dest\"\n";
+        if let Some(v) = validate.filter(|v| !v.contains("%s")) {
+            s += &format!("if ! {}; then\n", v);
+            if self.rollback {
+                s += "  if [ -n \"$bak\" ]; then cp -p \"$bak\" \"$dest\"; echo \"[conf] rollback: $dest <- $bak\";\n";
+                s += "  elif [ \"$existed\" = 0 ]; then rm -f \"$dest\"; echo \"[conf] rollback: $dest entfernt\"; fi\n";
+            }
+            s += "  echo 'validate fehlgeschlagen' >&2; exit 1\nfi\n";
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dest: &str, check: bool) -> RemoteWrite {
+        RemoteWrite {
+            dest: dest.into(),
+            file_name: Some("app.conf".into()),
+            mode: Some("0640".into()),
+            dir_mode: 0o755,
+            new_file_mode: None,
+            backup_dir: Some(String::new()),
+            stamp: "t".into(),
+            validate: Some("nginx -t -c %s".into()),
+            rollback: false,
+            check,
+        }
+    }
+
+    #[test]
+    fn check_touches_nothing_next_to_dest() {
+        let script = write("/etc/app/app.conf", true).script();
+        for word in ["mkdir", "mv ", "cp ", ".rr-tmp-", "nginx"] {
+            assert!(!script.contains(word), "{} in:\n{}", word, script);
+        }
+        assert!(script.contains("mktemp \"${TMPDIR:-/tmp}/"));
+        assert!(
+            script
+                .trim_end()
+                .ends_with(&format!("echo \"{}$changed\"", CHANGED_MARKER))
+        );
+        let script = write("/etc/app/app.conf", false).script();
+        assert!(script.contains("mkdir -p") && script.contains(".rr-tmp-"));
+    }
+
+    /// Führt das Skript lokal mit `sh` aus (`stat -c` gibt es nur mit GNU).
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn check_script_runs_without_side_effects() {
+        use std::io::Write;
+        let dir = std::env::temp_dir().join(format!("rr-remote-{}", std::process::id()));
+        let tmpdir = dir.join("tmp");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("etc")).unwrap();
+        std::fs::create_dir_all(&tmpdir).unwrap();
+        let run = |dest: &std::path::Path| {
+            let mut child = std::process::Command::new("sh")
+                .args(["-c", &write(&dest.to_string_lossy(), true).script()])
+                .env("TMPDIR", &tmpdir)
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .spawn()
+                .unwrap();
+            child.stdin.take().unwrap().write_all(b"neu\n").unwrap();
+            let out = child.wait_with_output().unwrap();
+            assert!(out.status.success());
+            String::from_utf8(out.stdout).unwrap()
+        };
+        let listing = |d: &std::path::Path| {
+            let mut names = std::fs::read_dir(d)
+                .unwrap()
+                .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        // fehlendes Verzeichnis wird nicht angelegt
+        assert_eq!(run(&dir.join("neu/sub/app.conf")), "rr-changed=1\n");
+        assert!(!dir.join("neu").exists());
+
+        let dest = dir.join("etc/app.conf");
+        assert_eq!(run(&dest), "rr-changed=1\n");
+        std::fs::write(&dest, "neu\n").unwrap();
+        std::fs::set_permissions(&dest, std::os::unix::fs::PermissionsExt::from_mode(0o640))
+            .unwrap();
+        assert_eq!(run(&dest), "rr-changed=0\n");
+        std::fs::write(&dest, "alt\n").unwrap();
+        assert_eq!(run(&dest), "rr-changed=1\n");
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "alt\n");
+        assert_eq!(listing(&dir.join("etc")), ["app.conf"]);
+        assert!(listing(&tmpdir).is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
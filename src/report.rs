@@ -0,0 +1,249 @@
+// src/report.rs
+use crate::executor::{ProcessFailed, StepOutcome, signal_name};
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Ergebnis eines Schritts für Reports; `Err` enthält die Fehlermeldung.
+pub struct StepRecord {
+    pub name: String,
+    pub duration: Duration,
+    pub usage: Option<crate::profile::Usage>, // nur mit --profile
+    pub commands: Vec<String>,                // gerendert wie im Header, Secrets maskiert
+    pub result: std::result::Result<StepOutcome, StepFailure>,
+}
+
+pub struct StepFailure {
+    pub message: String,
+    pub rc: Option<i32>,        // Exit-Code des Prozesses, falls einer lief
+    pub signal: Option<String>, // z. B. "SIGKILL", wenn ein Signal ihn beendet hat
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl StepFailure {
+    pub fn from_error(e: &anyhow::Error) -> Self {
+        let pf = e.downcast_ref::<ProcessFailed>();
+        Self {
+            message: format!("{:#}", e),
+            rc: pf.and_then(|pf| pf.rc),
+            signal: pf.and_then(|pf| pf.signal).map(signal_name),
+            stdout: pf.map(|pf| pf.stdout.clone()).unwrap_or_default(),
+            stderr: pf.map(|pf| pf.stderr.clone()).unwrap_or_default(),
+        }
+    }
+
+    /// `rc 2` bzw. `SIGKILL`; `None` bei Fehlern ohne Prozess.
+    pub fn exit(&self) -> Option<String> {
+        match (&self.signal, self.rc) {
+            (Some(sig), _) => Some(sig.clone()),
+            (None, Some(rc)) => Some(format!("rc {}", rc)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Schreibt die Schritte als JUnit-XML (ein `<testcase>` pro Schritt, mit
+/// den ausgeführten Kommandos, `changed` bzw. Exit-Code oder Signal als
+/// `<properties>`).
+pub fn write_junit(path: &str, suite: &str, records: &[StepRecord]) -> Result<()> {
+    let failures = records.iter().filter(|r| r.result.is_err()).count();
+    let skipped = records
+        .iter()
+        .filter(|r| matches!(&r.result, Ok(o) if o.skipped))
+        .count();
+    let total: f64 = records.iter().map(|r| r.duration.as_secs_f64()).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        xml,
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+        escape(suite),
+        records.len(),
+        failures,
+        skipped,
+        total
+    )?;
+    for r in records {
+        write!(
+            xml,
+            "  <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\">",
+            escape(&r.name),
+            escape(suite),
+            r.duration.as_secs_f64()
+        )?;
+        let mut props = r
+            .commands
+            .iter()
+            .map(|c| ("command", c.clone()))
+            .collect::<Vec<_>>();
+        match &r.result {
+            Ok(o) if !o.skipped => props.push(("changed", o.changed.to_string())),
+            Ok(_) => {}
+            Err(f) => {
+                props.extend(f.rc.map(|rc| ("rc", rc.to_string())));
+                props.extend(f.signal.clone().map(|sig| ("signal", sig)));
+            }
+        }
+        if !props.is_empty() {
+            xml.push_str("<properties>");
+            for (name, value) in &props {
+                write!(
+                    xml,
+                    "<property name=\"{}\" value=\"{}\"/>",
+                    name,
+                    escape(value)
+                )?;
+            }
+            xml.push_str("</properties>");
+        }
+        let (stdout, stderr) = match &r.result {
+            Ok(o) => {
+                match (o.skipped, &o.skip_reason) {
+                    (true, Some(reason)) => {
+                        write!(xml, "<skipped message=\"{}\"/>", escape(reason))?
+                    }
+                    (true, None) => xml.push_str("<skipped/>"),
+                    _ => {}
+                }
+                (o.stdout.as_str(), o.stderr.as_str())
+            }
+            Err(f) => {
+                write!(
+                    xml,
+                    "<failure message=\"{}\">{}</failure>",
+                    escape(&f.message),
+                    escape(&f.message)
+                )?;
+                (f.stdout.as_str(), f.stderr.as_str())
+            }
+        };
+        if !stdout.is_empty() {
+            write!(xml, "<system-out>{}</system-out>", escape(stdout))?;
+        }
+        if !stderr.is_empty() {
+            write!(xml, "<system-err>{}</system-err>", escape(stderr))?;
+        }
+        xml.push_str("</testcase>\n");
+    }
+    xml.push_str("</testsuite>\n");
+    std::fs::write(path, xml).with_context(|| format!("JUnit-Report {} schreiben", path))
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // in XML 1.0 nicht erlaubte Steuerzeichen auslassen
+            c if (c as u32) < 0x20 && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Form der Abschlusstabelle (`--summary`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SummaryStyle {
+    Compact, // eine Zeile je Schritt
+    Wide,    // zusätzlich die ausgeführten Kommandos
+}
+
+impl SummaryStyle {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "compact" => Ok(Self::Compact),
+            "wide" => Ok(Self::Wide),
+            other => anyhow::bail!("unbekannte Form '{}' (compact|wide)", other),
+        }
+    }
+}
+
+/// Längere Schrittnamen werden gekürzt.
+const SUMMARY_NAME_WIDTH: usize = 40;
+
+/// Abschlusstabelle mit Nummer, Name, Status und Laufzeit je Schritt. Breiten
+/// zählen Zeichen ohne Farbcodes; gefärbt wird nur auf einem Terminal ohne
+/// `NO_COLOR`, und erst nach dem Auffüllen.
+pub fn summary(records: &[StepRecord], style: SummaryStyle) -> String {
+    use std::io::IsTerminal;
+    let plain = crate::output::no_color();
+    let color = !plain && std::io::stdout().is_terminal();
+    let rows = records
+        .iter()
+        .enumerate()
+        .map(|(i, r)| {
+            let (status, code) = match &r.result {
+                Ok(o) if o.skipped => ("übersprungen".to_string(), "33"),
+                Ok(o) if o.changed => ("geändert".to_string(), "36"),
+                Ok(_) => ("ok".to_string(), "32"),
+                Err(f) => match f.exit() {
+                    Some(exit) => (format!("fehlgeschlagen ({})", exit), "31"),
+                    None => ("fehlgeschlagen".to_string(), "31"),
+                },
+            };
+            let name = truncate(&r.name, SUMMARY_NAME_WIDTH, plain);
+            (i + 1, name, status, code, r)
+        })
+        .collect::<Vec<_>>();
+    let nr_w = records.len().to_string().len().max(1);
+    let name_w = rows
+        .iter()
+        .map(|(_, name, ..)| name.chars().count())
+        .chain(["Schritt".len()])
+        .max()
+        .unwrap_or(0);
+    let status_w = rows
+        .iter()
+        .map(|(_, _, status, ..)| status.chars().count())
+        .chain(["Status".len()])
+        .max()
+        .unwrap_or(0);
+    let pad = |s: &str, w: usize| format!("{}{}", s, " ".repeat(w - s.chars().count()));
+    let mut out = format!(
+        "\n[summary] {:>nr_w$}  {}  {}  {:>9}{}\n",
+        "#",
+        pad("Schritt", name_w),
+        pad("Status", status_w),
+        "Dauer",
+        if style == SummaryStyle::Wide {
+            "  Kommando"
+        } else {
+            ""
+        },
+    );
+    for (nr, name, status, code, r) in rows {
+        let status = match color {
+            true => format!("\x1b[{}m{}\x1b[0m", code, pad(&status, status_w)),
+            false => pad(&status, status_w),
+        };
+        let mut line = format!(
+            "[summary] {:>nr_w$}  {}  {}  {:>8.2}s",
+            nr,
+            pad(&name, name_w),
+            status,
+            r.duration.as_secs_f64()
+        );
+        if style == SummaryStyle::Wide && !r.commands.is_empty() {
+            line += &format!("  {}", r.commands.join("; "));
+        }
+        out += line.trim_end();
+        out.push('\n');
+    }
+    out
+}
+
+/// Kürzt auf `max` Zeichen mit Auslassungszeichen, mit `ascii` als `...`.
+fn truncate(s: &str, max: usize, ascii: bool) -> String {
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+    let ellipsis = if ascii { "..." } else { "…" };
+    let keep = max - ellipsis.chars().count();
+    s.chars().take(keep).collect::<String>() + ellipsis
+}
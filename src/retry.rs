@@ -0,0 +1,136 @@
+// src/retry.rs
+use crate::notify::Failure;
+use crate::scheduler::step_name;
+use crate::schema::{Backoff, Jitter, Step};
+use anyhow::{Context, Result};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+/// Retry-Datei neben dem Playbook: `deploy.yaml` → `deploy.yaml.retry`.
+pub fn path_for(playbook: &str) -> String {
+    format!("{}.retry", playbook)
+}
+
+/// Schreibt eine Zeile `<nummer>\t<name>` pro fehlgeschlagenem Schritt;
+/// ohne Fehler wird eine alte Datei entfernt.
+pub fn write(path: &str, failures: &[Failure]) -> Result<()> {
+    if failures.is_empty() {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("{} entfernen", path));
+            }
+            _ => return Ok(()),
+        }
+    }
+    let mut lines = failures
+        .iter()
+        .map(|f| (f.index, f.step.as_str()))
+        .collect::<Vec<_>>();
+    lines.sort();
+    lines.dedup();
+    let body = lines
+        .iter()
+        .map(|(i, name)| format!("{}\t{}\n", i, name))
+        .collect::<String>();
+    std::fs::write(path, body).with_context(|| format!("{} schreiben", path))?;
+    println!("[retry] {} Schritt(e) in {}", lines.len(), path);
+    Ok(())
+}
+
+/// Liest die Datei und liefert die Indizes (ab 0) der zu wiederholenden
+/// Schritte. Passt Nummer und Name nicht mehr zusammen, weil das Playbook
+/// geändert wurde, wird über den Namen gesucht und gewarnt.
+pub fn load(path: &str, steps: &[Step]) -> Result<Vec<usize>> {
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("{} lesen (kein fehlgeschlagener Lauf?)", path))?;
+    let mut out = Vec::new();
+    for line in raw.lines().filter(|l| !l.trim().is_empty()) {
+        let (nr, name) = line
+            .split_once('\t')
+            .with_context(|| format!("{}: ungültige Zeile '{}'", path, line))?;
+        let nr = nr
+            .parse::<usize>()
+            .with_context(|| format!("{}: ungültige Nummer '{}'", path, nr))?;
+        let idx = if (1..=steps.len()).contains(&nr) && step_name(steps, nr - 1) == name {
+            Some(nr - 1)
+        } else {
+            let found = (0..steps.len()).find(|&i| step_name(steps, i) == name);
+            match found {
+                Some(i) => eprintln!(
+                    "[retry] Playbook geändert: '{}' ist jetzt Schritt {} statt {}",
+                    name,
+                    i + 1,
+                    nr
+                ),
+                None => eprintln!(
+                    "[retry] Playbook geändert: Schritt {} '{}' nicht gefunden, ausgelassen",
+                    nr, name
+                ),
+            }
+            found
+        };
+        out.extend(idx);
+    }
+    out.sort();
+    out.dedup();
+    if out.is_empty() {
+        anyhow::bail!("{}: keine Schritte zum Wiederholen", path);
+    }
+    Ok(out)
+}
+
+/// Obergrenze für Wiederholungen über den ganzen Lauf (`--max-retries-global`),
+/// von allen Phasen und parallelen Schritten geteilt.
+#[derive(Debug)]
+pub struct Budget {
+    max: u32,
+    used: AtomicU32,
+}
+
+impl Budget {
+    pub fn new(max: u32) -> Self {
+        Self {
+            max,
+            used: AtomicU32::new(0),
+        }
+    }
+
+    /// Verbraucht eine Wiederholung; `false`, wenn keine mehr übrig ist.
+    pub fn take(&self) -> bool {
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < self.max).then_some(n + 1)
+            })
+            .is_ok()
+    }
+
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+}
+
+/// Wartezeit vor der Wiederholung nach Versuch `attempt` (ab 1).
+pub fn backoff_delay(spec: &Backoff, attempt: u32) -> Duration {
+    let delay = spec.delay.unwrap_or(1.0).max(0.0);
+    let factor = spec.factor.unwrap_or(2.0).max(1.0);
+    let max = spec.max.unwrap_or(30.0).max(0.0);
+    let d = (delay * factor.powi(attempt.saturating_sub(1) as i32)).min(max);
+    let secs = match spec.jitter {
+        Jitter::Full => d * random_fraction(),
+        Jitter::Equal => d / 2.0 + d / 2.0 * random_fraction(),
+        Jitter::None => d,
+    };
+    Duration::from_secs_f64(secs)
+}
+
+/// Zufallszahl in [0, 1) ohne eigene Abhängigkeit: `RandomState` bekommt bei
+/// jedem Aufruf neue Schlüssel, gemischt mit der Uhrzeit.
+fn random_fraction() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+    let mut h = std::collections::hash_map::RandomState::new().build_hasher();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    h.write_u128(now.as_nanos());
+    (h.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
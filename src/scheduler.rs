@@ -0,0 +1,149 @@
+// src/scheduler.rs
+use crate::executor::{Executor, StepOutcome};
+use crate::schema::{OnError, Step};
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+/// Löst `depends_on` in Indizes auf. Unbekannte Namen und Zyklen sind Fehler.
+pub fn dependencies(steps: &[Step]) -> Result<Vec<Vec<usize>>> {
+    let deps = steps
+        .iter()
+        .map(|s| {
+            s.depends_on
+                .iter()
+                .map(|d| {
+                    steps
+                        .iter()
+                        .position(|o| o.name.as_deref() == Some(d.as_str()))
+                        .with_context(|| format!("depends_on: kein Schritt mit Namen '{}'", d))
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Kahn: bleibt ein Schritt übrig, hängt er in einem Zyklus
+    let mut open = deps.iter().map(Vec::len).collect::<Vec<_>>();
+    let mut ready = (0..steps.len())
+        .filter(|&i| open[i] == 0)
+        .collect::<Vec<_>>();
+    let mut seen = 0;
+    while let Some(i) = ready.pop() {
+        seen += 1;
+        for (j, d) in deps.iter().enumerate() {
+            for _ in d.iter().filter(|&&x| x == i) {
+                open[j] -= 1;
+                if open[j] == 0 {
+                    ready.push(j);
+                }
+            }
+        }
+    }
+    if seen < steps.len() {
+        anyhow::bail!("depends_on enthält einen Zyklus");
+    }
+    Ok(deps)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Pending,
+    Running,
+    Done,
+    Failed, // auch übersprungen wegen fehlgeschlagener Abhängigkeit
+}
+
+/// Führt bis zu `jobs` Schritte gleichzeitig aus, sobald ihre Abhängigkeiten
+/// erfolgreich waren. Schritte außerhalb von `selected` gelten als erledigt,
+/// Abhängige eines fehlgeschlagenen Schritts werden übersprungen. Außer mit
+/// `on_error: continue` startet nach dem ersten Fehler kein weiterer Schritt,
+/// laufende werden abgewartet.
+/// `done` bekommt jedes Ergebnis und meldet, ob der Schritt erfolgreich war.
+pub async fn run(
+    exec: Arc<Executor>,
+    steps: Arc<Vec<Step>>,
+    deps: &[Vec<usize>],
+    jobs: usize,
+    on_error: OnError,
+    selected: impl Fn(usize) -> bool,
+    mut done: impl FnMut(usize, Duration, Result<StepOutcome>) -> bool,
+) -> Result<()> {
+    let mut state = (0..steps.len())
+        .map(|i| {
+            if selected(i) {
+                State::Pending
+            } else {
+                State::Done
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut running = JoinSet::new();
+    let mut failed = false;
+    loop {
+        // so lange durchgehen, bis sich nichts mehr ändert (Kaskaden)
+        let mut progress = true;
+        while progress {
+            progress = false;
+            for i in 0..steps.len() {
+                if state[i] != State::Pending {
+                    continue;
+                }
+                let stop = exec.stop_reason().or_else(|| {
+                    (failed && on_error != OnError::Continue).then_some("Abbruch nach Fehler")
+                });
+                if let Some(reason) = stop {
+                    println!("[skip] {} ({})", step_name(&steps, i), reason);
+                    let skipped = StepOutcome {
+                        skipped: true,
+                        skip_reason: Some(reason.into()),
+                        ..Default::default()
+                    };
+                    done(i, Duration::ZERO, Ok(skipped));
+                    state[i] = State::Done;
+                    progress = true;
+                } else if deps[i].iter().any(|&d| state[d] == State::Failed) {
+                    println!(
+                        "[skip] {} (Abhängigkeit fehlgeschlagen)",
+                        step_name(&steps, i)
+                    );
+                    let skipped = StepOutcome {
+                        skipped: true,
+                        skip_reason: Some("Abhängigkeit fehlgeschlagen".into()),
+                        ..Default::default()
+                    };
+                    done(i, Duration::ZERO, Ok(skipped));
+                    state[i] = State::Failed;
+                    progress = true;
+                } else if running.len() < jobs && deps[i].iter().all(|&d| state[d] == State::Done) {
+                    let (exec, steps) = (exec.clone(), steps.clone());
+                    running.spawn(async move {
+                        let started = Instant::now();
+                        let res = exec.run_step(&steps[i], i).await;
+                        (i, started.elapsed(), res)
+                    });
+                    state[i] = State::Running;
+                    progress = true;
+                }
+            }
+        }
+        let Some(joined) = running.join_next().await else {
+            break;
+        };
+        let (i, elapsed, res) = joined.context("Schritt-Task abgebrochen")?;
+        state[i] = if done(i, elapsed, res) {
+            State::Done
+        } else {
+            failed = true;
+            State::Failed
+        };
+    }
+    Ok(())
+}
+
+pub fn step_name(steps: &[Step], i: usize) -> String {
+    steps[i]
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("step {}", i + 1))
+}
@@ -1,84 +1,1115 @@
 // src/schema.rs
-use serde::Deserialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
 
-#[derive(Deserialize, Debug)]
+/// Akzeptiert Strings und Zahlen (`port: 22` wie `port: "{{ ssh_port }}"`).
+fn de_opt_string<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    Ok(match Option::<serde_yaml::Value>::deserialize(d)? {
+        None | Some(serde_yaml::Value::Null) => None,
+        Some(serde_yaml::Value::String(s)) => Some(s),
+        Some(serde_yaml::Value::Number(n)) => Some(n.to_string()),
+        Some(serde_yaml::Value::Bool(b)) => Some(b.to_string()),
+        Some(_) => return Err(serde::de::Error::custom("String oder Zahl erwartet")),
+    })
+}
+
+/// `mode` als String (`"0644"`, `"u+x"`) oder Zahl (`420`, `0o644`), siehe `mode::from_number`.
+fn de_mode<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    Ok(match Option::<serde_yaml::Value>::deserialize(d)? {
+        None | Some(serde_yaml::Value::Null) => None,
+        Some(serde_yaml::Value::String(s)) => Some(s),
+        Some(serde_yaml::Value::Number(n)) => {
+            let n = n
+                .as_u64()
+                .ok_or_else(|| serde::de::Error::custom("mode muss positiv und ganzzahlig sein"))?;
+            Some(crate::mode::from_number(n).map_err(serde::de::Error::custom)?)
+        }
+        Some(_) => return Err(serde::de::Error::custom("mode: String oder Zahl erwartet")),
+    })
+}
+
+/// `when` als einzelne Bedingung oder Liste, von der alle gelten müssen.
+fn de_when<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    match Option::<serde_yaml::Value>::deserialize(d)? {
+        Some(serde_yaml::Value::Sequence(items)) => items
+            .into_iter()
+            .map(|v| {
+                Ok(de_opt_string(v)
+                    .map_err(serde::de::Error::custom)?
+                    .unwrap_or_default())
+            })
+            .collect(),
+        Some(v) => Ok(de_opt_string(v)
+            .map_err(serde::de::Error::custom)?
+            .into_iter()
+            .collect()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Für `--explain`: eine einzelne Bedingung wieder als String.
+fn ser_when<S: Serializer>(when: &[String], s: S) -> Result<S::Ok, S::Error> {
+    match when {
+        [one] => s.serialize_str(one),
+        all => s.collect_seq(all),
+    }
+}
+
+/// `env`-Block in Deklarationsreihenfolge; spätere Werte können frühere referenzieren.
+pub type EnvList = Vec<(String, EnvValue)>;
+
+/// Wert eines `env`-Eintrags: `KEY: val` oder `KEY: { value: val, when: cond }`.
+#[derive(Debug, Clone, Default)]
+pub struct EnvValue {
+    pub value: String,
+    pub when: Option<String>, // nur setzen, wenn die Bedingung truthy ist
+}
+
+/// Wie eine Map, aber mit erhaltener Reihenfolge; Werte dürfen Zahlen sein.
+fn de_env<'de, D: Deserializer<'de>>(d: D) -> Result<EnvList, D::Error> {
+    let map = Option::<serde_yaml::Mapping>::deserialize(d)?.unwrap_or_default();
+    map.into_iter()
+        .map(|(k, v)| {
+            let key = match k {
+                serde_yaml::Value::String(s) => s,
+                _ => {
+                    return Err(serde::de::Error::custom(
+                        "env: Schlüssel muss ein String sein",
+                    ));
+                }
+            };
+            let val = match v {
+                serde_yaml::Value::Mapping(m) => de_env_value(&key, m)?,
+                v => EnvValue {
+                    value: de_opt_string(v)
+                        .map_err(|e| serde::de::Error::custom(format!("env.{}: {}", key, e)))?
+                        .unwrap_or_default(),
+                    when: None,
+                },
+            };
+            Ok((key, val))
+        })
+        .collect()
+}
+
+/// `{ value, when }`; andere Schlüssel sind ein Fehler, `value` ist Pflicht.
+fn de_env_value<E: serde::de::Error>(key: &str, m: serde_yaml::Mapping) -> Result<EnvValue, E> {
+    let (mut value, mut when) = (None, None);
+    for (k, v) in m {
+        let text = de_opt_string(v).map_err(|e| E::custom(format!("env.{}: {}", key, e)))?;
+        match k.as_str() {
+            Some("value") => value = text,
+            Some("when") => when = text,
+            _ => {
+                return Err(E::custom(format!(
+                    "env.{}: nur `value` und `when` erlaubt",
+                    key
+                )));
+            }
+        }
+    }
+    let value = value.ok_or_else(|| E::custom(format!("env.{}: `value` fehlt", key)))?;
+    Ok(EnvValue { value, when })
+}
+
+/// Für `--explain`: wieder als Map ausgeben, bedingte Werte als `{ value, when }`.
+fn ser_env<S: Serializer>(env: &EnvList, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_map(env.iter().map(|(k, v)| {
+        let val = match &v.when {
+            None => serde_yaml::Value::String(v.value.clone()),
+            Some(when) => {
+                let mut m = serde_yaml::Mapping::new();
+                m.insert("value".into(), v.value.clone().into());
+                m.insert("when".into(), when.clone().into());
+                serde_yaml::Value::Mapping(m)
+            }
+        };
+        (k, val)
+    }))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Document {
-    #[allow(dead_code)]
-    pub version: u32,
+    pub version: u32, // Schema-Version, höchstens SCHEMA_VERSION
+    #[serde(default, deserialize_with = "de_opt_string")]
+    pub min_version: Option<String>, // mindestens benötigte rust-runner-Version
     #[serde(default)]
     pub globals: serde_yaml::Value,
+    #[serde(default)]
+    pub cwd: Option<String>, // Default für exec/shell, templated
+    #[serde(default)]
+    pub strict_vars: bool, // wie --strict-vars
+    #[serde(default)]
+    pub strict: bool, // strict_vars und Lint-Befunde als Fehler
+    #[serde(default)]
+    pub template_dir: Option<String>, // relativ zum Playbook, wie --template-dir
+    #[serde(default)]
+    pub template_macros: Option<String>, // Tera-Makros, per `{% import "macros" as m %}`
+    #[serde(default)]
+    pub prompts: Vec<Prompt>,
+    #[serde(default)]
+    pub defaults: Defaults,
+    #[serde(default)]
+    #[allow(dead_code)]
+    pub step_templates: HashMap<String, serde_yaml::Value>,
+    #[serde(default)]
+    pub on_failure: OnFailure,
+    #[serde(default)]
+    pub on_error: Option<OnError>, // default: rescue, wenn `rescue` gesetzt ist, sonst abort
+    #[serde(default)]
+    pub rescue: Vec<Step>, // läuft nach einem fehlgeschlagenen Schritt
+    #[serde(default)]
+    pub phase: Option<String>, // Name bei mehreren Dokumenten in einer Datei
+    #[serde(default)]
+    pub continue_on_failure: bool, // spätere Phasen laufen trotz Fehler in dieser
+    #[serde(default)]
+    pub inventory: Inventory, // Host-Gruppen für `ssh.host`, ergänzt durch --inventory
     pub steps: Vec<Step>,
 }
 
-#[derive(Deserialize, Debug)]
+/// Parst ein Dokument: löst YAML-Merge-Keys (`<<: *anchor`), `block:`-Gruppen
+/// und `use:`-Verweise auf `step_templates` auf, bevor in die Typen
+/// deserialisiert wird.
+pub fn parse_document(raw: &str) -> anyhow::Result<Document> {
+    document_from_value(serde_yaml::from_str(raw)?)
+}
+
+/// Parst eine Datei mit mehreren `---`-getrennten Dokumenten als Phasen.
+/// Ein erstes Dokument ohne `steps` ist die Basis und wird tief unter jede
+/// Phase gemergt (Globals, Defaults, ...). Eine einzelne Phase entspricht
+/// `parse_document`.
+pub fn parse_phases(raw: &str) -> anyhow::Result<Vec<Document>> {
+    use serde_yaml::Value;
+    let mut docs = serde_yaml::Deserializer::from_str(raw)
+        .map(Value::deserialize)
+        .collect::<Result<Vec<_>, _>>()?;
+    docs.retain(|d| !d.is_null());
+    // Merge-Keys zuerst, sonst würden `<<`-Einträge über die Basis gemergt
+    for d in &mut docs {
+        d.apply_merge()?;
+    }
+    let base = match docs.first() {
+        Some(first) if docs.len() > 1 && first.get("steps").is_none() => Some(docs.remove(0)),
+        _ => None,
+    };
+    let multi = docs.len() > 1;
+    docs.into_iter()
+        .enumerate()
+        .map(|(i, phase)| {
+            let v = match &base {
+                Some(base) => {
+                    let mut merged = base.clone();
+                    merge_value(&mut merged, phase);
+                    merged
+                }
+                None => phase,
+            };
+            match document_from_value(v) {
+                Err(e) if multi => Err(e.context(format!("Phase {}", i + 1))),
+                res => res,
+            }
+        })
+        .collect()
+}
+
+fn document_from_value(mut v: serde_yaml::Value) -> anyhow::Result<Document> {
+    use anyhow::Context;
+    use serde_yaml::Value;
+    v.apply_merge()?;
+
+    let templates = v
+        .get("step_templates")
+        .and_then(Value::as_mapping)
+        .cloned()
+        .unwrap_or_default();
+    for list in ["steps", "rescue"] {
+        let Some(steps) = v.get_mut(list).and_then(Value::as_sequence_mut) else {
+            continue;
+        };
+        *steps =
+            expand_blocks(std::mem::take(steps)).with_context(|| format!("{}: block", list))?;
+        for (i, step) in steps.iter_mut().enumerate() {
+            let Some(map) = step.as_mapping_mut() else {
+                continue;
+            };
+            let Some(name) = map.remove("use") else {
+                continue;
+            };
+            let tpl = templates.get(&name).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{} {}: unbekanntes step_template {}",
+                    if list == "steps" {
+                        "Schritt"
+                    } else {
+                        "rescue-Schritt"
+                    },
+                    i + 1,
+                    name.as_str().unwrap_or("?")
+                )
+            })?;
+            let mut merged = tpl.clone();
+            merge_value(&mut merged, std::mem::take(step));
+            *step = merged;
+        }
+    }
+    // für check_fields mit dem Dokument vergleichen, das serde daraus macht
+    let raw = v.clone();
+    let doc: Document = serde_yaml::from_value(v)?;
+    // neuere Schema-Versionen kennen womöglich mehr Felder
+    doc.check_version()?;
+    check_fields(&doc, &raw)?;
+    doc.check_actions()?;
+    if doc.on_error == Some(OnError::Rescue) && doc.rescue.is_empty() {
+        anyhow::bail!("on_error: rescue braucht eine rescue-Liste");
+    }
+    Ok(doc)
+}
+
+/// Felder, die ein `block` an seine Schritte weitergibt.
+const BLOCK_KEYS: [&str; 7] = [
+    "when",
+    "env",
+    "tags",
+    "become",
+    "become_user",
+    "become_method",
+    "become_password",
+];
+
+/// Ersetzt `block:`-Einträge durch ihre Schritte. `when` des Blocks kommt vor
+/// die Bedingungen des Schritts, `env` darunter (der Schritt überschreibt
+/// gleichnamige Variablen), `tags` kommen zu denen des Schritts hinzu,
+/// `become*` gilt, wo der Schritt nichts setzt.
+/// Verschachtelte Blöcke werden von innen nach außen aufgelöst.
+fn expand_blocks(steps: Vec<serde_yaml::Value>) -> anyhow::Result<Vec<serde_yaml::Value>> {
+    use anyhow::Context;
+    use serde_yaml::Value;
+    let mut out = Vec::new();
+    for (i, step) in steps.into_iter().enumerate() {
+        let mut group = match step {
+            Value::Mapping(m) if m.contains_key("block") => m,
+            step => {
+                out.push(step);
+                continue;
+            }
+        };
+        let label = match group.get("name").and_then(Value::as_str) {
+            Some(name) => format!("Eintrag {} ({})", i + 1, name),
+            None => format!("Eintrag {}", i + 1),
+        };
+        let children = match group.remove("block") {
+            Some(Value::Sequence(s)) => s,
+            _ => anyhow::bail!("{}: block muss eine Liste von Schritten sein", label),
+        };
+        if let Some(key) = group
+            .keys()
+            .filter_map(Value::as_str)
+            .find(|k| *k != "name" && !BLOCK_KEYS.contains(k))
+        {
+            anyhow::bail!(
+                "{}: `{}` ist in einem block nicht erlaubt (nur name, {})",
+                label,
+                key,
+                BLOCK_KEYS.join(", ")
+            );
+        }
+        for mut child in expand_blocks(children).with_context(|| label.clone())? {
+            let Some(map) = child.as_mapping_mut() else {
+                anyhow::bail!("{}: Schritt ist kein Mapping", label);
+            };
+            inherit(map, &group);
+            out.push(child);
+        }
+    }
+    Ok(out)
+}
+
+/// Überträgt die `BLOCK_KEYS` eines Blocks auf einen seiner Schritte.
+fn inherit(step: &mut serde_yaml::Mapping, block: &serde_yaml::Mapping) {
+    use serde_yaml::Value;
+    let as_list = |v: Option<Value>| match v {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Sequence(s)) => s,
+        Some(v) => vec![v],
+    };
+    if let Some(when) = block.get("when") {
+        let mut all = as_list(Some(when.clone()));
+        all.extend(as_list(step.remove("when")));
+        step.insert("when".into(), Value::Sequence(all));
+    }
+    if let Some(tags) = block.get("tags") {
+        let mut all = as_list(Some(tags.clone()));
+        for tag in as_list(step.remove("tags")) {
+            if !all.contains(&tag) {
+                all.push(tag);
+            }
+        }
+        step.insert("tags".into(), Value::Sequence(all));
+    }
+    if let Some(Value::Mapping(env)) = block.get("env") {
+        let merged = match step.remove("env") {
+            None | Some(Value::Null) => env.clone(),
+            // gleichnamige Variablen des Schritts stehen hinter denen des Blocks
+            Some(Value::Mapping(own)) => {
+                let mut merged = env.clone();
+                merged.retain(|k, _| !own.contains_key(k));
+                merged.extend(own);
+                merged
+            }
+            // falscher Typ, wird beim Deserialisieren gemeldet
+            Some(other) => {
+                step.insert("env".into(), other);
+                Default::default()
+            }
+        };
+        if !merged.is_empty() {
+            step.insert("env".into(), Value::Mapping(merged));
+        }
+    }
+    for key in ["become", "become_user", "become_method", "become_password"] {
+        if let (Some(v), false) = (block.get(key), step.contains_key(key)) {
+            step.insert(key.into(), v.clone());
+        }
+    }
+}
+
+/// Höchste Schema-`version`, die dieses Binary versteht.
+pub const SCHEMA_VERSION: u32 = 1;
+/// Ältere Versionen laufen noch, aber mit Warnung.
+const DEPRECATED_BELOW: u32 = 1;
+
+impl Document {
+    /// Lehnt Playbooks für ein neueres Schema oder ein neueres Binary ab,
+    /// statt unbekannte Felder stillschweigend falsch zu deuten.
+    fn check_version(&self) -> anyhow::Result<()> {
+        if self.version > SCHEMA_VERSION {
+            anyhow::bail!(
+                "Playbook verlangt Schema-Version {}, dieses rust-runner {} unterstützt höchstens {}",
+                self.version,
+                env!("CARGO_PKG_VERSION"),
+                SCHEMA_VERSION
+            );
+        }
+        if self.version < DEPRECATED_BELOW {
+            eprintln!(
+                "[version] Schema-Version {} ist veraltet, bitte auf version: {} umstellen",
+                self.version, SCHEMA_VERSION
+            );
+        }
+        if let Some(min) = &self.min_version {
+            let parse = |v: &str| -> anyhow::Result<Vec<u64>> {
+                v.trim()
+                    .split('.')
+                    .map(|p| p.parse::<u64>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| anyhow::anyhow!("min_version '{}' (erwartet z. B. 0.3.0)", v))
+            };
+            let (need, have) = (parse(min)?, parse(env!("CARGO_PKG_VERSION"))?);
+            if have < need {
+                anyhow::bail!(
+                    "Playbook verlangt rust-runner >= {}, installiert ist {}",
+                    min,
+                    env!("CARGO_PKG_VERSION")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Jeder Schritt braucht genau einen Aktionsblock; sonst würde `run_action`
+    /// stillschweigend den ersten nehmen. Meldet alle betroffenen Schritte.
+    fn check_actions(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+        let lists = [("Schritt", &self.steps), ("rescue-Schritt", &self.rescue)];
+        for (label, steps) in lists {
+            for (i, step) in steps.iter().enumerate() {
+                let location = match &step.name {
+                    Some(name) => format!("{} {} ({})", label, i + 1, name),
+                    None => format!("{} {}", label, i + 1),
+                };
+                match step.actions().as_slice() {
+                    [_] => {}
+                    [] => errors.push(format!(
+                        "{}: kein Aktionsblock, erwartet wird genau einer von {}",
+                        location,
+                        ACTIONS.join(", ")
+                    )),
+                    several => errors.push(format!(
+                        "{}: mehrere Aktionsblöcke ({}), erlaubt ist genau einer",
+                        location,
+                        several.join(", ")
+                    )),
+                }
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(anyhow::anyhow!(errors.join("\n"))),
+        }
+    }
+}
+
+/// Schlüssel, die serde sonst stillschweigend ignoriert (Tippfehler wie
+/// `comand`), sind ein Fehler mit Vorschlag. `raw` ist die Eingabe, bei
+/// Playbooks nach Merges, `block` und `use:`. `deny_unknown_fields` taugt
+/// dafür nicht, es verträgt sich nicht mit `flatten`.
+fn check_fields<T: Serialize>(value: &T, raw: &serde_yaml::Value) -> anyhow::Result<()> {
+    let known = serde_yaml::to_value(value)?;
+    let mut errors = Vec::new();
+    unknown_fields(raw, &known, "", &mut errors);
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(anyhow::anyhow!(errors.join("\n"))),
+    }
+}
+
+/// Vergleicht die Eingabe mit dem wieder serialisierten Dokument, in dem jedes
+/// bekannte Feld steht (ohne Wert als `null`). Variablen und Vorlagen haben
+/// beliebige Schlüssel und werden übergangen.
+fn unknown_fields(
+    raw: &serde_yaml::Value,
+    known: &serde_yaml::Value,
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    use serde_yaml::Value;
+    match (raw, known) {
+        (Value::Mapping(raw), Value::Mapping(known)) => {
+            for (key, v) in raw {
+                let name = match key.as_str() {
+                    Some(k) => k.to_string(),
+                    None => serde_yaml::to_string(key).unwrap_or_default().trim().into(),
+                };
+                let child = match path {
+                    "" => name.clone(),
+                    p => format!("{}.{}", p, name),
+                };
+                match known.get(key) {
+                    None => {
+                        let fields = known.keys().filter_map(Value::as_str).collect::<Vec<_>>();
+                        let hint = match suggest(&name, fields.iter().copied()) {
+                            Some(s) => format!("meintest du `{}`?", s),
+                            None => format!("erlaubt: {}", fields.join(", ")),
+                        };
+                        errors.push(match path {
+                            "" => format!("unbekanntes Feld `{}`, {}", name, hint),
+                            p => format!("{}: unbekanntes Feld `{}`, {}", p, name, hint),
+                        });
+                    }
+                    Some(_) if matches!(name.as_str(), "globals" | "vars" | "step_templates") => {}
+                    Some(k) => unknown_fields(v, k, &child, errors),
+                }
+            }
+        }
+        (Value::Sequence(raw), Value::Sequence(known)) => {
+            for (i, (r, k)) in raw.iter().zip(known).enumerate() {
+                unknown_fields(r, k, &format!("{}[{}]", path, i), errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Ähnlichster Name für einen Tippfehler: höchstens ein Drittel der Zeichen
+/// (mindestens eines) verschieden.
+fn suggest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max = (name.chars().count() / 3).max(1);
+    candidates
+        .map(|c| (distance(name, c), c))
+        .filter(|(d, _)| *d <= max)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// Levenshtein-Distanz über Zeichen; vertauschte Nachbarn (`usre`) zählen
+/// wie ein einzelner Fehler.
+fn distance(a: &str, b: &str) -> usize {
+    let (a, b) = (a.chars().collect::<Vec<_>>(), b.chars().collect::<Vec<_>>());
+    // d[i][j]: Distanz der ersten i Zeichen von a zu den ersten j von b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j - 1] + cost)
+                .min(d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Tiefer Merge: Mappings werden rekursiv zusammengeführt, sonst gewinnt `over`.
+pub fn merge_value(base: &mut serde_yaml::Value, over: serde_yaml::Value) {
+    match (base, over) {
+        (serde_yaml::Value::Mapping(b), serde_yaml::Value::Mapping(o)) => {
+            for (k, v) in o {
+                match b.get_mut(&k) {
+                    Some(existing) => merge_value(existing, v),
+                    None => {
+                        b.insert(k, v);
+                    }
+                }
+            }
+        }
+        (b, o) => *b = o,
+    }
+}
+
+/// Für `--explain`: das Dokument nach Merges, `use:` und Defaults als YAML,
+/// ohne Tera-Ausdrücke zu rendern. Geheime prompts werden maskiert, leere
+/// Felder weggelassen.
+pub fn explain(mut doc: Document) -> anyhow::Result<String> {
+    use serde_yaml::Value;
+    let d = &doc.defaults;
+    for step in doc.steps.iter_mut().chain(&mut doc.rescue) {
+        step.timeout = step.timeout.or(d.timeout);
+        step.retry = step.retry.or(d.retry);
+        step.retry_backoff = step
+            .retry_backoff
+            .take()
+            .or_else(|| d.retry_backoff.clone());
+        step.capture_limit = step.capture_limit.or(d.capture_limit);
+        // bei einer Host-Gruppe greifen die Defaults erst nach Host und Gruppe
+        let ssh = step
+            .ssh
+            .as_mut()
+            .map(|s| &mut s.target)
+            .filter(|t| !doc.inventory.contains_key(&t.host));
+        let remote = step.conf.as_mut().and_then(|c| c.remote.as_mut());
+        for target in ssh.into_iter().chain(remote) {
+            target.user = target.user.take().or_else(|| d.ssh.user.clone());
+            target.port = target.port.take().or_else(|| d.ssh.port.clone());
+            target.proxy_jump = target
+                .proxy_jump
+                .take()
+                .or_else(|| d.ssh.proxy_jump.clone());
+        }
+    }
+    // schon per `use:` in die Schritte gemergt
+    doc.step_templates.clear();
+    for p in doc.prompts.iter().filter(|p| p.secret) {
+        if let Some(v) = doc.globals.get_mut(p.name.as_str()) {
+            *v = Value::String("***".into());
+        }
+    }
+
+    let mut v = serde_yaml::to_value(&doc)?;
+    prune(&mut v);
+    Ok(serde_yaml::to_string(&v)?)
+}
+
+/// Entfernt `null`, leere Listen/Maps und `false`-Defaults aus Mappings.
+/// `diff` ist optional und mit `false` eine Aussage, es bleibt;
+/// Variablen (`globals`, `vars`) bleiben unverändert.
+fn prune(v: &mut serde_yaml::Value) {
+    use serde_yaml::Value;
+    match v {
+        Value::Mapping(m) => {
+            for (k, v) in m.iter_mut() {
+                if !matches!(k.as_str(), Some("globals" | "vars")) {
+                    prune(v);
+                }
+            }
+            m.retain(|k, v| match v {
+                Value::Null => false,
+                Value::Bool(false) => k.as_str() == Some("diff"),
+                Value::Sequence(s) => !s.is_empty(),
+                Value::Mapping(m) => !m.is_empty(),
+                _ => true,
+            });
+        }
+        Value::Sequence(s) => s.iter_mut().for_each(prune),
+        _ => {}
+    }
+}
+
+/// Werte für Schritte, die kein eigenes `timeout`/`retry` setzen.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Defaults {
+    #[serde(default)]
+    pub timeout: Option<u64>, // Sekunden
+    #[serde(default)]
+    pub retry: Option<u32>,
+    #[serde(default)]
+    pub retry_backoff: Option<Backoff>,
+    #[serde(default)]
+    pub capture_limit: Option<usize>, // Bytes pro Stream, 0 = unbegrenzt
+    #[serde(default)]
+    pub ssh: SshDefaults,
+}
+
+/// Wartezeit vor der n-ten Wiederholung: `delay * factor^(n-1)`, höchstens
+/// `max`, dann mit `jitter` gestreut.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Backoff {
+    #[serde(default)]
+    pub delay: Option<f64>, // Sekunden vor der ersten Wiederholung, default 1
+    #[serde(default)]
+    pub factor: Option<f64>, // default 2, 1 = gleichbleibend
+    #[serde(default)]
+    pub max: Option<f64>, // Sekunden, default 30
+    #[serde(default)]
+    pub jitter: Jitter,
+}
+
+/// Streuung der Wartezeit `d`, damit viele Schritte nicht gleichzeitig wiederholen.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Jitter {
+    #[default]
+    Full, // zufällig zwischen 0 und d
+    Equal, // d/2 plus zufällig bis d/2
+    None,  // genau d
+}
+
+/// Verhalten nach einem fehlgeschlagenen Schritt.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    Abort,    // keine weiteren Schritte
+    Continue, // folgende Schritte trotzdem ausführen
+    Rescue,   // abbrechen und die `rescue`-Schritte ausführen
+}
+
+/// Benachrichtigungen, wenn ein Schritt fehlschlägt.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct OnFailure {
+    #[serde(default)]
+    pub command: Option<String>, // Details per RR_*-Umgebungsvariablen
+    #[serde(default)]
+    pub http: Option<HttpNotify>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HttpNotify {
+    pub url: String, // bekommt die Details als JSON per POST
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// Host-Gruppen nach Namen.
+pub type Inventory = BTreeMap<String, HostGroup>;
+
+/// Eine Gruppe im Inventory; ihre Verbindungsparameter gelten für alle Hosts,
+/// die keine eigenen setzen.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HostGroup {
+    pub hosts: Vec<InventoryHost>,
+    #[serde(flatten)]
+    pub ssh: SshDefaults,
+}
+
+/// `- web1` oder `- { host: web1, port: 2222 }`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum InventoryHost {
+    Name(String),
+    Entry {
+        host: String,
+        #[serde(flatten)]
+        ssh: SshDefaults,
+    },
+}
+
+impl InventoryHost {
+    pub fn name(&self) -> &str {
+        match self {
+            InventoryHost::Name(h) | InventoryHost::Entry { host: h, .. } => h,
+        }
+    }
+}
+
+/// Liest eine Inventory-Datei (`--inventory`): Gruppen wie unter `inventory:`.
+pub fn load_inventory(path: &str) -> anyhow::Result<Inventory> {
+    use anyhow::Context;
+    let raw = std::fs::read_to_string(path).with_context(|| format!("Inventory {} lesen", path))?;
+    let value: serde_yaml::Value =
+        serde_yaml::from_str(&raw).with_context(|| format!("Inventory {} ungültig", path))?;
+    let inventory: Inventory = serde_yaml::from_value(value.clone())
+        .with_context(|| format!("Inventory {} ungültig", path))?;
+    check_fields(&inventory, &value).with_context(|| format!("Inventory {} ungültig", path))?;
+    Ok(inventory)
+}
+
+/// Dokumentweite ssh-Defaults (templated), überschreibbar pro Schritt.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct SshDefaults {
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default, deserialize_with = "de_opt_string")]
+    pub port: Option<String>,
+    #[serde(default)]
+    pub key_path: Option<String>,
+    #[serde(default)]
+    pub proxy_jump: Option<String>,
+    #[serde(default)]
+    pub program: Option<String>, // ssh-Binary, z. B. für Installationen außerhalb von PATH
+    #[serde(default)]
+    pub connect_timeout: Option<u64>, // Sekunden für Verbindungsaufbau und Anmeldung
+}
+
+impl SshDefaults {
+    /// Eigene Werte, fehlende aus `fallback`.
+    pub fn or(&self, fallback: &SshDefaults) -> SshDefaults {
+        SshDefaults {
+            user: self.user.clone().or_else(|| fallback.user.clone()),
+            port: self.port.clone().or_else(|| fallback.port.clone()),
+            key_path: self.key_path.clone().or_else(|| fallback.key_path.clone()),
+            proxy_jump: self
+                .proxy_jump
+                .clone()
+                .or_else(|| fallback.proxy_jump.clone()),
+            program: self.program.clone().or_else(|| fallback.program.clone()),
+            connect_timeout: self.connect_timeout.or(fallback.connect_timeout),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Prompt {
+    pub name: String,
+    #[serde(default)]
+    pub prompt: Option<String>, // Anzeigetext, default: name
+    #[serde(default)]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub secret: bool, // verdeckte Eingabe
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "lowercase")]
 pub struct SshAuth {
-    pub kind: String, // "password" | "key"
-    #[allow(dead_code)]
+    pub kind: String,             // "password" | "key" | "agent"
     pub password: Option<String>, // templated
     pub key_path: Option<String>, // templated
     #[allow(dead_code)]
     pub passphrase: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct SshSpec {
+/// Verbindungsparameter eines ssh-Ziels, gemeinsam für `ssh` und `conf.remote`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SshTarget {
     pub host: String,
     pub user: Option<String>,
-    pub auth: Option<SshAuth>,
-    pub command: String,
+    #[serde(default, deserialize_with = "de_opt_string")]
+    pub port: Option<String>, // templated
     #[serde(default)]
-    pub env: HashMap<String, String>,
+    pub proxy_jump: Option<String>, // templated, `ssh -J`
+    pub auth: Option<SshAuth>,
     #[serde(default)]
     pub check_host: Option<String>, // "yes" | "no" | "fingerprint"
+    #[serde(default)]
+    pub agent_forward: bool, // `ssh -A`
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SshSpec {
+    #[serde(flatten)]
+    pub target: SshTarget,
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub script: Option<String>, // templated, per stdin in eine Remote-Temp-Datei
+    #[serde(default)]
+    pub executable: Option<String>, // Interpreter für script, default: "bash"
+    #[serde(default, deserialize_with = "de_env", serialize_with = "ser_env")]
+    pub env: EnvList,
+    #[serde(default)]
+    pub cwd: Option<String>, // remote: `cd <cwd> && ...`
+    #[serde(default)]
+    pub env_mode: SshEnvMode,
+}
+
+/// Wie `env` eines ssh-Schritts auf den Host kommt.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SshEnvMode {
+    #[default]
+    Inline, // `KEY=val command`, braucht eine POSIX-Login-Shell
+    Env,     // `env KEY=val sh -c 'command'`
+    SendEnv, // `-o SendEnv=KEY`, Werte aus der lokalen Umgebung von ssh
+    SetEnv,  // `-o SetEnv=KEY=val`, OpenSSH >= 7.8
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ExecSpec {
     pub cmd: String,
     #[serde(default)]
-    pub args: Vec<String>,
+    pub args: Args,
     #[serde(default)]
-    pub env: HashMap<String, String>,
+    pub args_file: Option<String>, // templated, ein Argument pro Zeile, nach args
+    #[serde(default, deserialize_with = "de_env", serialize_with = "ser_env")]
+    pub env: EnvList,
     #[serde(default)]
     pub cwd: Option<String>,
+    #[serde(default)]
+    pub stdin_from: Option<String>, // Name eines früheren Schritts, dessen stdout nach stdin geht
+    #[serde(default)]
+    pub creates: Option<String>, // templated, existiert: Schritt überspringen
+    #[serde(default)]
+    pub removes: Option<String>, // templated, fehlt: Schritt überspringen
+    #[serde(default, flatten)]
+    pub output: OutputSpec,
+}
+
+/// `args` als Liste (jedes Element ein Argument) oder als String, der nach
+/// dem Rendern wie von einer Shell in Wörter zerlegt wird (`words::split`).
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Args {
+    List(Vec<String>),
+    Line(String),
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args::List(Vec::new())
+    }
 }
 
-#[derive(Deserialize, Debug)]
+/// Umleitung von stdout/stderr in Dateien statt auf die Konsole.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct OutputSpec {
+    #[serde(default)]
+    pub stdout_file: Option<String>, // templated, relativ zum cwd des Schritts
+    #[serde(default)]
+    pub stderr_file: Option<String>,
+    #[serde(default)]
+    pub append_output: bool, // anhängen statt überschreiben
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ShellSpec {
-    pub command: String,
     #[serde(default)]
-    pub env: HashMap<String, String>,
+    pub command: Option<String>,
+    #[serde(default)]
+    pub script: Option<String>, // templated, als Datei an `executable` übergeben
+    #[serde(default)]
+    pub executable: Option<String>, // Interpreter für script, default: "bash"
+    #[serde(default, deserialize_with = "de_env", serialize_with = "ser_env")]
+    pub env: EnvList,
     #[serde(default)]
     pub cwd: Option<String>,
     #[serde(default)]
     pub shell: Option<String>, // default: "bash -c"
+    #[serde(default)]
+    pub stdin_from: Option<String>, // Name eines früheren Schritts, dessen stdout nach stdin geht
+    #[serde(default)]
+    pub creates: Option<String>, // templated, existiert: Schritt überspringen
+    #[serde(default)]
+    pub removes: Option<String>, // templated, fehlt: Schritt überspringen
+    #[serde(default, flatten)]
+    pub output: OutputSpec,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ConfSpec {
-    pub dest: String,
-    pub template: String,
+    pub dest: String, // Verzeichnis oder `/` am Ende: Dateiname aus template_file
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub template_file: Option<String>, // templated, relativ zum Playbook
+    #[serde(default)]
+    pub from_command: Option<String>, // templated, stdout wird der Inhalt
+    #[serde(default)]
+    pub raw: bool, // Inhalt unverändert schreiben, ohne Tera
     #[serde(default)]
     pub backup: bool,
     #[serde(default)]
+    pub backup_dir: Option<String>, // templated, default: Verzeichnis von dest
+    #[serde(default)]
+    pub backup_keep: Option<usize>, // ältere Backups werden gelöscht
+    #[serde(default, deserialize_with = "de_mode")]
     pub mode: Option<String>,
+    #[serde(default, deserialize_with = "de_mode")]
+    pub dir_mode: Option<String>, // neu angelegte Elternverzeichnisse, default: 0755
+    #[serde(default, deserialize_with = "de_opt_string")]
+    pub umask: Option<String>, // oktal, für neue Dateien ohne mode und Verzeichnisse ohne dir_mode
+    #[serde(default)]
+    pub validate: Option<String>, // templated, `%s` = temporäre Datei
+    #[serde(default)]
+    pub rollback_on_failure: bool, // Backup zurückspielen, wenn validate scheitert
+    #[serde(default)]
+    pub diff: Option<bool>, // Unified-Diff ausgeben, default: --diff
+    #[serde(default)]
+    pub remote: Option<SshTarget>, // dest auf diesem Host schreiben statt lokal
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AssertSpec {
+    pub that: Vec<String>, // templated Ausdrücke
+    #[serde(default)]
+    pub msg: Option<String>,
+}
+
+/// Einzelne Zeile in einer Datei sicherstellen oder entfernen, statt die
+/// ganze Datei wie `conf` neu zu schreiben.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LineInFileSpec {
+    pub path: String, // templated
+    #[serde(default)]
+    pub line: Option<String>, // templated, Pflicht für state: present
+    #[serde(default)]
+    pub regexp: Option<String>, // templated, zu ersetzende bzw. zu entfernende Zeilen
+    #[serde(default)]
+    pub state: LineState,
+    #[serde(default)]
+    pub insertafter: Option<String>, // Regex oder "EOF"
+    #[serde(default)]
+    pub insertbefore: Option<String>, // Regex oder "BOF"
+    #[serde(default)]
+    pub create: bool, // fehlende Datei anlegen statt Fehler
+    #[serde(default)]
+    pub backup: bool,
+    #[serde(default)]
+    pub backup_dir: Option<String>, // templated, default: Verzeichnis von path
+    #[serde(default)]
+    pub backup_keep: Option<usize>,
+    #[serde(default)]
+    pub diff: Option<bool>, // default: --diff
 }
 
-#[derive(Deserialize, Debug)]
+/// Steuerung des Laufs statt einer Aktion.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Meta {
+    EndRun, // keine weiteren Schritte, der Lauf gilt als erfolgreich
+}
+
+/// Format, in dem `parse` die Standardausgabe eines Schritts liest.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Parse {
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineState {
+    #[default]
+    Present,
+    Absent,
+}
+
+/// Datei per HTTP(S) holen, optional mit Prüfsumme.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DownloadSpec {
+    pub url: String,  // templated
+    pub dest: String, // templated, Zieldatei
+    #[serde(default)]
+    pub checksum: Option<String>, // templated, "sha256:<hex>" | "sha512:<hex>"
+}
+
+/// Aktionsblöcke eines Schritts, in der Reihenfolge, in der `run_action` sie prüft.
+pub const ACTIONS: [&str; 8] = [
+    "shell",
+    "exec",
+    "conf",
+    "ssh",
+    "assert",
+    "download",
+    "lineinfile",
+    "meta",
+];
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Step {
     pub name: Option<String>,
     #[serde(default)]
-    pub when: Option<bool>,
+    pub depends_on: Vec<String>, // Namen anderer Schritte, für --jobs
     #[serde(default)]
-    #[allow(dead_code)]
-    pub timeout: Option<u64>,
+    pub tags: Vec<String>, // Kennzeichnung, siehe `list-tags`
+    #[serde(default)]
+    pub vars: serde_yaml::Value, // nur für diesen Schritt, tief über die Globals gemergt
+    #[serde(default, deserialize_with = "de_when", serialize_with = "ser_when")]
+    pub when: Vec<String>, // Bedingungen wie bei until, alle müssen gelten
+    #[serde(default)]
+    pub timeout: Option<u64>, // Sekunden
     #[serde(default)]
-    #[allow(dead_code)]
     pub retry: Option<u32>,
     #[serde(default)]
-    pub env: HashMap<String, String>,
+    pub retry_backoff: Option<Backoff>, // ersetzt defaults.retry_backoff
+    #[serde(default, deserialize_with = "de_opt_string")]
+    pub retry_delay: Option<String>, // templated, Sekunden je Wiederholung, mit `attempt`/`retries`
+    #[serde(default)]
+    pub retry_on_rc: Vec<i32>,
+    #[serde(default)]
+    pub retry_on_stderr: Option<String>, // Regex
+    #[serde(default)]
+    pub success_codes: Vec<i32>, // Exit-Codes, die als Erfolg gelten, default: [0]
+    #[serde(default)]
+    pub fail_on_stderr: bool, // Fehler bei jeder Ausgabe auf stderr, auch mit rc 0
+    #[serde(default)]
+    pub until: Option<String>, // Bedingung über rc/stdout/stderr
+    #[serde(default)]
+    pub retries: Option<u32>, // für until, default: 3
+    #[serde(default)]
+    pub delay: Option<u64>, // Sekunden zwischen until-Versuchen, default: 5
+    #[serde(default)]
+    pub delay_before: Option<u64>, // Sekunden Pause vor der Aktion, nach `when`
+    #[serde(default)]
+    pub delay_after: Option<u64>, // Sekunden Pause nach erfolgreicher Aktion
+    #[serde(default)]
+    pub r#async: Option<u64>, // Sekunden, bis status_cmd Erfolg melden muss
+    #[serde(default)]
+    pub poll: Option<u64>, // Sekunden zwischen status_cmd-Aufrufen, default: 10
+    #[serde(default)]
+    pub status_cmd: Option<String>, // templated, Exit-Code 0: Hintergrundarbeit fertig
+    #[serde(default, deserialize_with = "de_env", serialize_with = "ser_env")]
+    pub env: EnvList,
+    #[serde(default)]
+    pub clean_env: bool, // nur explizit deklarierte Variablen
+    #[serde(default)]
+    pub unset_env: Vec<String>,
+    #[serde(default)]
+    pub combine_output: bool, // stdout/stderr in Ankunftsreihenfolge
+    #[serde(default)]
+    pub raw_output: bool, // Bytes sofort durchreichen statt Zeilen mit Präfix
+    #[serde(default)]
+    pub parse: Option<Parse>, // stdout strukturiert unter `steps.<name>.json`
+    #[serde(default)]
+    pub capture_limit: Option<usize>, // gesammelte Bytes pro Stream, 0 = unbegrenzt
+    #[serde(default)]
+    pub r#loop: Option<serde_yaml::Value>, // Liste oder "{{ variable }}", ein Durchlauf je `item`
+    #[serde(default)]
+    pub loop_parallel: Option<usize>, // gleichzeitige Durchläufe, default 1
+    #[serde(default)]
+    pub loop_continue_on_failure: bool, // nach einem Fehler trotzdem alle Durchläufe starten
+    #[serde(default)]
+    pub check: bool, // Kommando ist lesend und läuft auch mit --check
+    #[serde(default)]
+    pub dry_run_safe: bool, // shell/exec/ssh ist lesend und läuft auch im Dry-Run
+    #[serde(default)]
+    pub delegate_to: Option<String>, // templated, shell/exec per ssh auf diesem Host
+    #[serde(default)]
+    pub r#become: bool, // shell/exec/ssh als become_user ausführen
+    #[serde(default)]
+    pub become_user: Option<String>, // templated, default: root
+    #[serde(default)]
+    pub become_method: Option<String>, // "sudo" | "su" | "doas", default: sudo
+    #[serde(default)]
+    pub become_password: Option<String>, // templated, sudo über ssh/delegate_to
+    #[serde(default)]
+    pub no_log: bool, // keine Ausgabe, Kommando im Header ausgeblendet
+    #[serde(default)]
+    pub pre: Option<String>, // Shell-Snippet vor der Aktion
+    #[serde(default)]
+    pub post: Option<String>, // Shell-Snippet nach der Aktion
+    #[serde(default)]
+    pub post_always: bool, // post auch nach Fehlern (finally)
+    #[serde(default)]
+    pub on_success: Option<String>, // Shell-Snippet nach erfolgreicher Aktion und post
+    #[serde(default)]
+    pub on_failure: Option<String>, // Shell-Snippet nach einem Fehler, mit RR_ERROR usw.
+    #[serde(default)]
+    pub fail_on_hook_error: bool, // fehlgeschlagenes on_success lässt den Schritt scheitern
     #[serde(default)]
     pub exec: Option<ExecSpec>,
     #[serde(default)]
@@ -87,4 +1118,33 @@ pub struct Step {
     pub ssh: Option<SshSpec>,
     #[serde(default)]
     pub conf: Option<ConfSpec>,
+    #[serde(default)]
+    pub assert: Option<AssertSpec>,
+    #[serde(default)]
+    pub download: Option<DownloadSpec>,
+    #[serde(default)]
+    pub lineinfile: Option<LineInFileSpec>,
+    #[serde(default)]
+    pub meta: Option<Meta>,
+}
+
+impl Step {
+    /// Gesetzte Aktionsblöcke, siehe `ACTIONS`.
+    pub fn actions(&self) -> Vec<&'static str> {
+        let set = [
+            self.shell.is_some(),
+            self.exec.is_some(),
+            self.conf.is_some(),
+            self.ssh.is_some(),
+            self.assert.is_some(),
+            self.download.is_some(),
+            self.lineinfile.is_some(),
+            self.meta.is_some(),
+        ];
+        ACTIONS
+            .into_iter()
+            .zip(set)
+            .filter_map(|(name, set)| set.then_some(name))
+            .collect()
+    }
 }
@@ -0,0 +1,40 @@
+// src/scratch.rs
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Laufweites Scratch-Verzeichnis (`{{ run.tmpdir }}`). Wird beim Drop gelöscht,
+/// also auch nach Fehlern und bei Ctrl-C, außer mit `--keep-tmp`.
+pub struct ScratchDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl ScratchDir {
+    /// Legt `rust-runner-<pid>-<zeit>` im System-Temp-Verzeichnis an (Unix: 0700).
+    pub fn create(keep: bool) -> Result<Self> {
+        let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%S%3f");
+        let path =
+            std::env::temp_dir().join(format!("rust-runner-{}-{}", std::process::id(), stamp));
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder
+            .create(&path)
+            .with_context(|| format!("Scratch-Verzeichnis {} anlegen", path.display()))?;
+        Ok(Self { path, keep })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if self.keep {
+            eprintln!("[tmp] behalten: {}", self.path.display());
+        } else if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            eprintln!("[tmp] {} nicht gelöscht: {}", self.path.display(), e);
+        }
+    }
+}
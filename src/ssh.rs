@@ -0,0 +1,61 @@
+// src/ssh.rs
+
+/// Effektive Verbindungsparameter eines ssh-Schritts (bereits gerendert).
+#[derive(Debug, Clone)]
+pub struct SshConn {
+    pub program: String, // ssh-Binary, default: "ssh"
+    pub host: String,
+    pub user: String,
+    pub port: Option<String>,
+    pub key_path: Option<String>,
+    pub password: Option<String>,
+    pub proxy_jump: Option<String>,
+    pub check_host: Option<String>, // "yes" | "no" | "fingerprint"
+    pub agent_forward: bool,
+    pub connect_timeout: Option<u64>, // Sekunden, `-o ConnectTimeout`
+}
+
+impl SshConn {
+    /// ssh-Argumente bis einschließlich `user@host`; bei Passwort über `sshpass -e`
+    /// (das Passwort selbst kommt per `SSHPASS`-Umgebungsvariable).
+    pub fn base_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.password.is_some() {
+            args.extend(["sshpass", "-e"].map(String::from));
+        }
+        args.push(self.program.clone());
+        match self.check_host.as_deref() {
+            Some("no") | None => args.extend(
+                [
+                    "-o",
+                    "StrictHostKeyChecking=no",
+                    "-o",
+                    "UserKnownHostsFile=/dev/null",
+                ]
+                .map(String::from),
+            ),
+            Some("yes") => {}
+            Some("fingerprint") => {} // TODO: known_hosts Handling
+            _ => {}
+        }
+        if let Some(p) = &self.port {
+            args.extend(["-p".to_string(), p.clone()]);
+        }
+        if let Some(j) = &self.proxy_jump {
+            args.extend(["-J".to_string(), j.clone()]);
+        }
+        if let Some(k) = &self.key_path {
+            // nur diesen Key anbieten, auch wenn der Agent weitere hält
+            args.extend(["-i".to_string(), k.clone()]);
+            args.extend(["-o", "IdentitiesOnly=yes"].map(String::from));
+        }
+        if let Some(t) = self.connect_timeout {
+            args.extend(["-o".to_string(), format!("ConnectTimeout={}", t)]);
+        }
+        if self.agent_forward {
+            args.push("-A".to_string());
+        }
+        args.push(format!("{}@{}", self.user, self.host));
+        args
+    }
+}
@@ -0,0 +1,76 @@
+// src/state.rs
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// State-Datei neben dem Playbook: `deploy.yaml` → `deploy.yaml.state`.
+pub fn path_for(playbook: &str) -> String {
+    format!("{}.state", playbook)
+}
+
+/// Hashes der zuletzt erfolgreichen Schritte für `--incremental`, je Schritt
+/// eine Zeile `<sha256>\t<schlüssel>`.
+pub struct State {
+    path: String,
+    force: bool, // --force: nichts überspringen, aber neu aufzeichnen
+    entries: Mutex<BTreeMap<String, String>>,
+}
+
+impl State {
+    /// Liest `path`; eine fehlende Datei ist ein leerer Stand.
+    pub fn load(path: &str, force: bool) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        match std::fs::read_to_string(path) {
+            Ok(raw) => {
+                for line in raw.lines().filter(|l| !l.trim().is_empty()) {
+                    let (hash, key) = line
+                        .split_once('\t')
+                        .with_context(|| format!("{}: ungültige Zeile '{}'", path, line))?;
+                    entries.insert(key.to_string(), hash.to_string());
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("{} lesen", path)),
+        }
+        Ok(Self {
+            path: path.to_string(),
+            force,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Wurde der Schritt mit genau diesem Hash zuletzt erfolgreich ausgeführt?
+    pub fn unchanged(&self, key: &str, hash: &str) -> bool {
+        !self.force && self.lock().get(key).is_some_and(|h| h == hash)
+    }
+
+    /// Merkt sich einen Erfolg (`Some`) bzw. vergisst den Schritt (`None`).
+    pub fn record(&self, key: &str, hash: Option<String>) {
+        match hash {
+            Some(h) => self.lock().insert(key.to_string(), h),
+            None => self.lock().remove(key),
+        };
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let body = self
+            .lock()
+            .iter()
+            .map(|(key, hash)| format!("{}\t{}\n", hash, key))
+            .collect::<String>();
+        std::fs::write(&self.path, body).with_context(|| format!("{} schreiben", self.path))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, String>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// sha256 als Hex-String.
+pub fn hash(text: &str) -> String {
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
@@ -1,44 +1,392 @@
 // src/template.rs
 use anyhow::{Context, Result};
 use serde_yaml::Value;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tera::Tera;
+use tera::ast::{ExprVal, LogicOperator, Node};
 
 pub struct Renderer {
-    tera: Tera,
+    tera: Arc<Tera>, // geteilt mit den include-Funktionen
+    strict: bool,
+    base_dir: Option<PathBuf>, // für include_file/include_template
 }
 
 impl Renderer {
     pub fn new() -> Self {
-        // leere Tera-Instanz für String-Rendering
+        // leere Tera-Instanz für String-Rendering, siehe `load_dir`
         let mut tera = Tera::default();
-        tera.autoescape_on(vec![]);
-        Self { tera }
+        configure(&mut tera);
+        Self {
+            tera: Arc::new(tera),
+            strict: false,
+            base_dir: None,
+        }
+    }
+
+    /// Lädt alle Dateien unter `dir` als benannte Templates (Name = Pfad relativ
+    /// zu `dir`), damit `{% include %}`, `{% extends %}` und `{% import %}` auflösen.
+    pub fn load_dir(&mut self, dir: &std::path::Path) -> Result<()> {
+        if !dir.is_dir() {
+            anyhow::bail!("template_dir {} ist kein Verzeichnis", dir.display());
+        }
+        let glob = dir.join("**").join("*");
+        let mut tera = Tera::new(&glob.to_string_lossy())
+            .with_context(|| format!("template_dir {} laden", dir.display()))?;
+        configure(&mut tera);
+        self.tera = Arc::new(tera);
+        Ok(())
+    }
+
+    /// Registriert `src` als benanntes Template, etwa für `{% import %}`; ersetzt
+    /// eine gleichnamige Datei aus `template_dir`.
+    pub fn add_template(&mut self, name: &str, src: &str) -> Result<()> {
+        Arc::make_mut(&mut self.tera)
+            .add_raw_template(name, src)
+            .with_context(|| format!("Template {} übernehmen", name))
+    }
+
+    /// Strikt: auch Bedingungen (`{% if x %}`, `when`, `assert`) mit undefinierten
+    /// Variablen sind ein Fehler. Ausgaben wie `{{ x }}` scheitern bei Tera ohnehin.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Basis für relative Pfade in `include_file`/`include_template`.
+    pub fn set_base_dir(&mut self, dir: Option<PathBuf>) {
+        self.base_dir = dir;
     }
 
     pub fn render_str<S: AsRef<str>>(&self, s: S, ctx: &Value) -> Result<String> {
-        let mut t = self.tera.clone();
+        let s = s.as_ref();
+        self.render_inner(s, ctx, false, false)
+            .map_err(|e| TemplateError::new(s, e).into())
+    }
+
+    /// Wie `render_str` für Pfade, die danach `paths::expand` durchlaufen: `$`
+    /// in Werten aus `ENV` wird zu `$$` und bleibt dadurch unverändert.
+    pub fn render_path<S: AsRef<str>>(&self, s: S, ctx: &Value) -> Result<String> {
+        let s = s.as_ref();
+        self.render_inner(s, ctx, false, true)
+            .map_err(|e| TemplateError::new(s, e).into())
+    }
+
+    /// Wie `render_str`, aber jede Ausgabe `{{ ... }}` wird für eine POSIX-Shell
+    /// gequotet; `| safe` (oder `| quote`) übernimmt den Wert unverändert.
+    pub fn render_shell<S: AsRef<str>>(&self, s: S, ctx: &Value) -> Result<String> {
+        let s = s.as_ref();
+        self.render_inner(s, ctx, true, false)
+            .map_err(|e| TemplateError::new(s, e).into())
+    }
+
+    fn render_inner(&self, s: &str, ctx: &Value, shell: bool, path: bool) -> Result<String> {
+        let mut t = (*self.tera).clone();
+        // Autoescape hängt bei Tera an der Endung des Template-Namens
+        let name = if shell {
+            t.autoescape_on(vec![".sh"]);
+            t.set_escape_fn(shell_quote);
+            "inline.sh"
+        } else {
+            "inline"
+        };
         // dynamische Template-Quelle
-        t.add_raw_template("inline", s.as_ref())
-            .context("add template")?;
+        t.add_raw_template(name, s).context("add template")?;
         let cjson = serde_json::to_value(ctx)?;
+        if self.strict {
+            check_defined(&t.get_template(name)?.ast, &cjson)?;
+        }
         let mut c = tera::Context::from_value(cjson)?;
         // ENV verfügbar machen
-        c.insert(
-            "ENV",
-            &std::env::vars().collect::<std::collections::HashMap<_, _>>(),
-        );
-        Ok(t.render("inline", &c)?)
+        let env = std::env::vars().map(|(k, v)| match path {
+            true => (k, v.replace('$', "$$")),
+            false => (k, v),
+        });
+        c.insert("ENV", &env.collect::<HashMap<_, _>>());
+        let c = Arc::new(c);
+        register_includes(&mut t, &self.tera, self.base_dir.clone(), &c, Vec::new());
+        Ok(t.render(name, &c)?)
     }
 
-    pub fn render_map(
+    /// Wertet einen Ausdruck als Bedingung aus. Ausdrücke ohne `{{`/`{%`
+    /// werden als Tera-`if`-Bedingung interpretiert (`x == 1`).
+    pub fn eval_bool(&self, expr: &str, ctx: &Value) -> Result<bool> {
+        let src = if expr.contains("{{") || expr.contains("{%") {
+            expr.to_string()
+        } else {
+            format!("{{% if {} %}}true{{% else %}}false{{% endif %}}", expr)
+        };
+        // im Fehler den Ausdruck zeigen, nicht die if-Hülle
+        let out = self
+            .render_inner(&src, ctx, false, false)
+            .map_err(|e| TemplateError::new(expr, e))?;
+        Ok(is_truthy(&out))
+    }
+}
+
+/// Gemeinsame Einstellungen: kein HTML-Autoescape, Filter `quote`/`sh`.
+fn configure(tera: &mut Tera) {
+    tera.autoescape_on(vec![]);
+    tera.register_filter("quote", Quote);
+    tera.register_filter("sh", Quote);
+}
+
+/// `include_file(path="...")` fügt den Inhalt einer Datei unverändert ein,
+/// `include_template(path="...")` rendert ihn vorher mit demselben Kontext
+/// (darin sind beide Funktionen wieder verfügbar). Relative Pfade gelten ab
+/// `base`. Tera kennt nur benannte Argumente, daher `path=`. `stack` sind die
+/// gerade gerenderten `include_template`-Dateien, um Zyklen abzufangen.
+fn register_includes(
+    t: &mut Tera,
+    proto: &Arc<Tera>,
+    base: Option<PathBuf>,
+    ctx: &Arc<tera::Context>,
+    stack: Vec<PathBuf>,
+) {
+    let file_base = base.clone();
+    t.register_function(
+        "include_file",
+        move |args: &HashMap<String, serde_json::Value>| {
+            let path = include_path(args, "include_file", file_base.as_deref())?;
+            read_include(&path, "include_file").map(Into::into)
+        },
+    );
+    let (proto, ctx) = (proto.clone(), ctx.clone());
+    t.register_function(
+        "include_template",
+        move |args: &HashMap<String, serde_json::Value>| {
+            let path = include_path(args, "include_template", base.as_deref())?;
+            if stack.contains(&path) {
+                let chain = stack
+                    .iter()
+                    .chain([&path])
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>();
+                return Err(tera::Error::msg(format!(
+                    "include_template: Zyklus {}",
+                    chain.join(" -> ")
+                )));
+            }
+            let src = read_include(&path, "include_template")?;
+            let mut inner = (*proto).clone();
+            let mut nested = stack.clone();
+            nested.push(path.clone());
+            register_includes(&mut inner, &proto, base.clone(), &ctx, nested);
+            let name = path.to_string_lossy();
+            inner.add_raw_template(&name, &src)?;
+            inner.render(&name, &ctx).map(Into::into)
+        },
+    );
+}
+
+fn include_path(
+    args: &HashMap<String, serde_json::Value>,
+    func: &str,
+    base: Option<&Path>,
+) -> tera::Result<PathBuf> {
+    let file = args
+        .get("path")
+        .and_then(serde_json::Value::as_str)
+        .ok_or_else(|| tera::Error::msg(format!("{}: path=\"...\" fehlt", func)))?;
+    Ok(match base {
+        Some(b) => b.join(file),
+        None => file.into(),
+    })
+}
+
+fn read_include(path: &Path, func: &str) -> tera::Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| tera::Error::msg(format!("{}: {} lesen: {}", func, path.display(), e)))
+}
+
+fn shell_quote(s: &str) -> String {
+    shell_escape::unix::escape(s.into()).into_owned()
+}
+
+/// `{{ path | quote }}`: ein Wort für eine POSIX-Shell, Listen als einzeln
+/// gequotete, durch Leerzeichen getrennte Wörter. Das Ergebnis gilt als
+/// sicher und wird in `ssh.command` nicht ein zweites Mal gequotet.
+struct Quote;
+
+impl tera::Filter for Quote {
+    fn filter(
         &self,
-        map: &std::collections::HashMap<String, String>,
-        ctx: &Value,
-    ) -> Result<std::collections::HashMap<String, String>> {
-        let mut out = std::collections::HashMap::new();
-        for (k, v) in map {
-            out.insert(k.clone(), self.render_str(v, ctx)?);
-        }
-        Ok(out)
+        value: &serde_json::Value,
+        _: &std::collections::HashMap<String, serde_json::Value>,
+    ) -> tera::Result<serde_json::Value> {
+        let word = |v: &serde_json::Value| match v {
+            serde_json::Value::String(s) => shell_quote(s),
+            other => shell_quote(&other.to_string()),
+        };
+        Ok(match value {
+            serde_json::Value::Array(items) => items.iter().map(word).collect::<Vec<_>>().join(" "),
+            v => word(v),
+        }
+        .into())
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// Fehler beim Rendern mit Ausschnitt der Template-Quelle.
+#[derive(Debug, thiserror::Error)]
+#[error("Template `{snippet}`")]
+pub struct TemplateError {
+    pub snippet: String,
+    #[source]
+    source: Box<dyn std::error::Error + Send + Sync>,
+}
+
+impl TemplateError {
+    fn new(src: &str, err: anyhow::Error) -> Self {
+        const MAX: usize = 60;
+        let first = src.lines().next().unwrap_or_default();
+        let mut snippet: String = first.chars().take(MAX).collect();
+        if first.chars().count() > MAX || src.lines().nth(1).is_some() {
+            snippet.push('…');
+        }
+        Self {
+            snippet,
+            source: err.into(),
+        }
+    }
+}
+
+/// Leere Strings, `false`, `0`, `no`, `none` und `null` gelten als falsy.
+pub fn is_truthy(s: &str) -> bool {
+    !matches!(
+        s.trim().to_ascii_lowercase().as_str(),
+        "" | "false" | "0" | "no" | "none" | "null"
+    )
+}
+
+/// Bricht ab, wenn eine Bedingung auf eine Variable verweist, die weder im
+/// Kontext noch lokal (`set`, `for`) definiert ist.
+fn check_defined(ast: &[Node], ctx: &serde_json::Value) -> Result<()> {
+    let (mut idents, mut locals) = (Vec::new(), HashSet::new());
+    collect_conditions(ast, &mut idents, &mut locals);
+    for ident in idents {
+        let root = ident.split(['.', '[']).next().unwrap_or_default();
+        if root == "ENV" || locals.contains(root) || ident.contains('[') {
+            continue;
+        }
+        if tera::dotted_pointer(ctx, &ident).is_none() {
+            anyhow::bail!("Undefinierte Variable `{}` (strict)", ident);
+        }
+    }
+    Ok(())
+}
+
+fn collect_conditions(nodes: &[Node], idents: &mut Vec<String>, locals: &mut HashSet<String>) {
+    for node in nodes {
+        match node {
+            Node::If(cond, _) => {
+                for (_, expr, body) in &cond.conditions {
+                    bool_idents(expr, idents);
+                    collect_conditions(body, idents, locals);
+                }
+                if let Some((_, body)) = &cond.otherwise {
+                    collect_conditions(body, idents, locals);
+                }
+            }
+            Node::Forloop(_, f, _) => {
+                locals.insert(f.value.clone());
+                locals.extend(f.key.clone());
+                locals.insert("loop".into());
+                collect_conditions(&f.body, idents, locals);
+                if let Some(body) = &f.empty_body {
+                    collect_conditions(body, idents, locals);
+                }
+            }
+            Node::Set(_, set) => {
+                locals.insert(set.key.clone());
+            }
+            Node::MacroDefinition(_, m, _) => {
+                locals.extend(m.args.keys().cloned());
+                collect_conditions(&m.body, idents, locals);
+            }
+            Node::Block(_, b, _) => collect_conditions(&b.body, idents, locals),
+            Node::FilterSection(_, f, _) => collect_conditions(&f.body, idents, locals),
+            _ => {}
+        }
+    }
+}
+
+/// Nur nackte Identifier in booleschen Positionen sind bei Tera nachsichtig.
+fn bool_idents(expr: &tera::ast::Expr, out: &mut Vec<String>) {
+    match &expr.val {
+        ExprVal::Ident(name) if expr.filters.is_empty() => out.push(name.clone()),
+        ExprVal::Logic(l) if matches!(l.operator, LogicOperator::And | LogicOperator::Or) => {
+            bool_idents(&l.lhs, out);
+            bool_idents(&l.rhs, out);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(yaml: &str) -> Value {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn quote_filter() {
+        let r = Renderer::new();
+        let c = ctx(
+            r#"{p: "/srv/my app", q: "it's", d: "$HOME", s: "a; rm -rf /", b: "`id`", plain: "/etc/x.conf", n: 5}"#,
+        );
+        for (tpl, want) in [
+            ("{{ p | quote }}", "'/srv/my app'"),
+            ("{{ q | quote }}", r"'it'\''s'"),
+            ("{{ d | quote }}", "'$HOME'"),
+            ("{{ s | sh }}", "'a; rm -rf /'"),
+            ("{{ b | sh }}", "'`id`'"),
+            ("{{ plain | quote }}", "/etc/x.conf"),
+            ("{{ n | quote }}", "5"),
+            ("{{ [p, plain] | quote }}", "'/srv/my app' /etc/x.conf"),
+        ] {
+            assert_eq!(r.render_str(tpl, &c).unwrap(), want, "{}", tpl);
+        }
+    }
+
+    #[test]
+    fn render_shell_quotes_only_values() {
+        let r = Renderer::new();
+        let c = ctx(r#"{p: "/srv/my app", s: "x; echo pwned", b: "$(id) `id`"}"#);
+        // der wörtliche Text bleibt, wie er ist, auch mit Shell-Zeichen
+        assert_eq!(
+            r.render_shell("ls -l {{ p }} | grep 'x y'; echo $HOME", &c)
+                .unwrap(),
+            "ls -l '/srv/my app' | grep 'x y'; echo $HOME"
+        );
+        assert_eq!(
+            r.render_shell("echo {{ s }}", &c).unwrap(),
+            "echo 'x; echo pwned'"
+        );
+        assert_eq!(
+            r.render_shell("echo {{ b }}", &c).unwrap(),
+            "echo '$(id) `id`'"
+        );
+        // quote und safe werden nicht ein zweites Mal gequotet
+        assert_eq!(
+            r.render_shell("cd {{ p | quote }}", &c).unwrap(),
+            "cd '/srv/my app'"
+        );
+        assert_eq!(
+            r.render_shell("{{ s | safe }}", &c).unwrap(),
+            "x; echo pwned"
+        );
+    }
+
+    #[test]
+    fn render_str_does_not_quote() {
+        let r = Renderer::new();
+        let c = ctx(r#"{p: "/srv/my app"}"#);
+        assert_eq!(r.render_str("cd {{ p }}", &c).unwrap(), "cd /srv/my app");
     }
 }
@@ -0,0 +1,86 @@
+// src/watch.rs
+use anyhow::{Context, Result};
+use notify_crate::{EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+
+/// So lange muss nach einer Änderung Ruhe sein, bevor neu gestartet wird;
+/// Editoren schreiben beim Speichern oft mehrfach.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Beobachtet einzelne Dateien und ganze Verzeichnisse für `--watch`.
+/// Dateien werden über ihr Elternverzeichnis beobachtet, damit auch Editoren
+/// erkannt werden, die beim Speichern eine neue Datei an die Stelle schreiben.
+pub struct Watcher {
+    _inner: RecommendedWatcher,
+    rx: UnboundedReceiver<Vec<PathBuf>>,
+    files: HashSet<PathBuf>,
+    dirs: Vec<PathBuf>, // rekursiv
+}
+
+impl Watcher {
+    /// Fehlende Pfade werden übersprungen, bis auf die Elternverzeichnisse
+    /// der Dateien muss nichts existieren.
+    pub fn new(files: &[PathBuf], dirs: &[PathBuf]) -> Result<Self> {
+        let (tx, rx) = unbounded_channel();
+        let mut inner = notify_crate::recommended_watcher(
+            move |res: notify_crate::Result<notify_crate::Event>| {
+                if let Ok(ev) = res
+                    && !matches!(ev.kind, EventKind::Access(_))
+                {
+                    let _ = tx.send(ev.paths);
+                }
+            },
+        )
+        .context("Dateibeobachtung starten")?;
+        let files = files
+            .iter()
+            .map(std::path::absolute)
+            .collect::<Result<HashSet<_>, _>>()?;
+        let dirs = dirs
+            .iter()
+            .map(std::path::absolute)
+            .collect::<Result<Vec<_>, _>>()?;
+        let parents = files
+            .iter()
+            .filter_map(|f| f.parent())
+            .collect::<BTreeSet<_>>();
+        for dir in parents.into_iter().filter(|d| d.is_dir()) {
+            inner
+                .watch(dir, RecursiveMode::NonRecursive)
+                .with_context(|| format!("{} beobachten", dir.display()))?;
+        }
+        for dir in dirs.iter().filter(|d| d.is_dir()) {
+            inner
+                .watch(dir, RecursiveMode::Recursive)
+                .with_context(|| format!("{} beobachten", dir.display()))?;
+        }
+        Ok(Self {
+            _inner: inner,
+            rx,
+            files,
+            dirs,
+        })
+    }
+
+    /// Wartet auf die erste Änderung an einem beobachteten Pfad und dann, bis
+    /// `DEBOUNCE` lang nichts mehr kommt. Liefert alle geänderten Pfade.
+    pub async fn changed(&mut self) -> Result<Vec<PathBuf>> {
+        let mut changed = BTreeSet::new();
+        while changed.is_empty() {
+            let paths = self.rx.recv().await.context("Dateibeobachtung beendet")?;
+            changed.extend(paths.into_iter().filter(|p| self.relevant(p)));
+        }
+        while let Ok(more) = tokio::time::timeout(DEBOUNCE, self.rx.recv()).await {
+            let paths = more.context("Dateibeobachtung beendet")?;
+            changed.extend(paths.into_iter().filter(|p| self.relevant(p)));
+        }
+        Ok(changed.into_iter().collect())
+    }
+
+    fn relevant(&self, path: &Path) -> bool {
+        self.files.contains(path) || self.dirs.iter().any(|d| path.starts_with(d))
+    }
+}
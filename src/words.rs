@@ -0,0 +1,127 @@
+// src/words.rs
+use anyhow::Result;
+
+/// Zerlegt eine Zeile wie eine POSIX-Shell in Wörter, aber ohne Expansion:
+/// Leerraum trennt, `'...'` ist wörtlich, in `"..."` maskiert `\` nur
+/// `"`, `\`, `$` und `` ` ``, außerhalb von Quotes maskiert `\` jedes Zeichen.
+/// `$VAR`, `*` und `~` bleiben unverändert. `""` ergibt ein leeres Wort.
+pub fn split(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut cur = String::new();
+    let mut in_word = false; // auch leere Quotes sind ein Wort
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut cur));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => cur.push(c),
+                        None => anyhow::bail!("fehlendes ' in: {}", line),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => cur.push(c),
+                            Some(c) => {
+                                cur.push('\\');
+                                cur.push(c);
+                            }
+                            None => anyhow::bail!("fehlendes \" in: {}", line),
+                        },
+                        Some(c) => cur.push(c),
+                        None => anyhow::bail!("fehlendes \" in: {}", line),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some(c) => cur.push(c),
+                    None => anyhow::bail!("\\ am Zeilenende in: {}", line),
+                }
+            }
+            c => {
+                in_word = true;
+                cur.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(cur);
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split;
+
+    fn words(line: &str) -> Vec<String> {
+        split(line).unwrap()
+    }
+
+    #[test]
+    fn whitespace_separates() {
+        assert_eq!(words("  -a   b\tc\n"), ["-a", "b", "c"]);
+        assert!(words("   ").is_empty());
+    }
+
+    #[test]
+    fn single_quotes_are_literal() {
+        assert_eq!(
+            words(r#"'a b' 'x\y' '"q"' '$HOME'"#),
+            ["a b", r"x\y", r#""q""#, "$HOME"]
+        );
+    }
+
+    #[test]
+    fn double_quotes() {
+        assert_eq!(words(r#""a b" "it's" "$HOME""#), ["a b", "it's", "$HOME"]);
+        // nur ", \, $ und ` werden maskiert, sonst bleibt der Backslash
+        assert_eq!(
+            words(r#""a\"b" "c\\d" "\$x" "\`y" "\n""#),
+            [r#"a"b"#, r"c\d", "$x", "`y", r"\n"]
+        );
+    }
+
+    #[test]
+    fn backslash_outside_quotes() {
+        assert_eq!(words(r"a\ b c\\d \'e \n"), ["a b", r"c\d", "'e", "n"]);
+    }
+
+    #[test]
+    fn quotes_join_with_adjacent_text() {
+        assert_eq!(words(r#"--opt='a b'"c"d"#), ["--opt=a bcd"]);
+    }
+
+    #[test]
+    fn empty_quotes_are_words() {
+        assert_eq!(words("'' a \"\""), ["", "a", ""]);
+    }
+
+    #[test]
+    fn unterminated() {
+        for line in ["'abc", "\"abc", "a \"b\\\"", "abc\\"] {
+            assert!(split(line).is_err(), "{}", line);
+        }
+        assert!(
+            split("'abc")
+                .unwrap_err()
+                .to_string()
+                .contains("fehlendes '")
+        );
+    }
+}
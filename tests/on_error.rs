@@ -0,0 +1,76 @@
+// tests/on_error.rs
+//! `on_error` unter `--jobs`: nach einem Fehler startet außer mit `continue`
+//! kein weiterer Schritt, auch kein unabhängiger.
+#![cfg(unix)]
+
+use rust_runner::{RunOptions, run_document, schema};
+
+/// Zustand je Schritt in Dokumentreihenfolge: `ok`, `skip` oder `fail`.
+async fn states(on_error: &str, jobs: usize) -> Vec<String> {
+    let yaml = format!(
+        "version: 1\n\
+         on_error: {on_error}\n\
+         rescue:\n  \
+           - name: cleanup\n    \
+             shell: {{ command: 'true' }}\n\
+         steps:\n  \
+           - name: fails\n    \
+             shell: {{ command: 'exit 1' }}\n  \
+           - name: after\n    \
+             depends_on: [fails]\n    \
+             shell: {{ command: 'true' }}\n  \
+           - name: independent-a\n    \
+             shell: {{ command: 'true' }}\n  \
+           - name: independent-b\n    \
+             shell: {{ command: 'true' }}\n"
+    );
+    let doc = schema::parse_document(&yaml).unwrap();
+    let report = run_document(
+        doc,
+        RunOptions {
+            jobs: Some(jobs),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(!report.success());
+    report
+        .records
+        .iter()
+        .map(|r| match &r.result {
+            Ok(o) if o.skipped => format!("{}: skip", r.name),
+            Ok(_) => format!("{}: ok", r.name),
+            Err(_) => format!("{}: fail", r.name),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn abort_and_rescue_start_nothing_after_failure() {
+    for on_error in ["abort", "rescue"] {
+        let mut want = vec![
+            "fails: fail",
+            "after: skip",
+            "independent-a: skip",
+            "independent-b: skip",
+        ];
+        if on_error == "rescue" {
+            want.push("[rescue] cleanup: ok");
+        }
+        assert_eq!(states(on_error, 1).await, want, "{}", on_error);
+    }
+}
+
+#[tokio::test]
+async fn continue_runs_independent_steps() {
+    assert_eq!(
+        states("continue", 1).await,
+        [
+            "fails: fail",
+            "after: skip",
+            "independent-a: ok",
+            "independent-b: ok"
+        ]
+    );
+}
@@ -0,0 +1,49 @@
+// tests/paths.rs
+//! `~` und `$VAR` in Pfadfeldern werden nach dem Rendern expandiert: Werte aus
+//! globals zählen mit, Werte aus `{{ ENV.X }}` bleiben, wie sie sind.
+#![cfg(unix)]
+
+const PLAYBOOK: &str = r#"
+version: 1
+globals: { home_file: "~/home.txt", sub: "$RR_SUB" }
+steps:
+  - name: tilde-from-globals
+    conf: { dest: "{{ home_file }}", template: x }
+  - name: var-from-globals
+    conf: { dest: "out/{{ sub }}.txt", template: x }
+  - name: env-value-literal
+    conf: { dest: "out/{{ ENV.RR_RAW }}", template: x }
+  - name: var-in-source
+    conf: { dest: "out/$RR_SUB-src.txt", template: x }
+"#;
+
+#[test]
+fn expand_after_render() {
+    let dir = std::env::temp_dir().join(format!("rr-paths-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("out/$RR_SUB")).unwrap();
+    std::fs::write(dir.join("pb.yaml"), PLAYBOOK).unwrap();
+    let out = std::process::Command::new(env!("CARGO_BIN_EXE_rust-runner"))
+        .args(["--no-config", "pb.yaml"])
+        .current_dir(&dir)
+        .env("HOME", &dir)
+        .env("RR_SUB", "sub")
+        .env("RR_RAW", "$RR_SUB/{{ nope }}.txt")
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}{}",
+        String::from_utf8_lossy(&out.stdout),
+        String::from_utf8_lossy(&out.stderr)
+    );
+    for file in [
+        "home.txt",
+        "out/sub.txt",
+        "out/$RR_SUB/{{ nope }}.txt",
+        "out/sub-src.txt",
+    ] {
+        assert!(dir.join(file).is_file(), "{} fehlt", file);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,205 @@
+// tests/precedence.rs
+//! Welche Quelle gewinnt, wenn eine Variable mehrfach gesetzt ist: geerbte
+//! Umgebung, `env` des Schritts und des shell-Blocks je nach `--env-precedence`,
+//! globals, `vars` eines Schritts und `--vars-file` im Template-Kontext sowie
+//! `template_dir` aus Kommandozeile, Konfigurationsdatei und Playbook.
+#![cfg(unix)]
+
+use std::path::PathBuf;
+
+/// Eigenes Verzeichnis je Test mit den angegebenen Dateien.
+fn setup(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rr-prec-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (file, content) in files {
+        let path = dir.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+    dir
+}
+
+/// Startet das Binary in `dir` mit `RR_PREC=proc` in der Umgebung und liefert
+/// die Ausgabezeilen der Schritte (ohne Präfix) bzw. stdout ohne Schritte.
+/// Ohne `--config` in `args` wird keine Konfigurationsdatei geladen.
+fn run(dir: &PathBuf, args: &[&str]) -> Vec<String> {
+    let no_config = (!args.contains(&"--config")).then_some("--no-config");
+    let out = std::process::Command::new(env!("CARGO_BIN_EXE_rust-runner"))
+        .args(no_config)
+        .args(args)
+        .current_dir(dir)
+        .env("RR_PREC", "proc")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(
+        out.status.success(),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let _ = std::fs::remove_dir_all(dir);
+    let steps = stdout
+        .lines()
+        .filter_map(|l| l.split_once("][out] ").map(|(_, v)| v.to_string()))
+        .collect::<Vec<_>>();
+    match steps.is_empty() {
+        true => stdout.lines().map(str::to_string).collect(),
+        false => steps,
+    }
+}
+
+/// Ein Schritt je Fall, jeder gibt `$RR_PREC` aus.
+const ENV_PLAYBOOK: &str = r#"
+version: 1
+steps:
+  - name: process-only
+    shell: { command: 'echo "$RR_PREC"' }
+  - name: process-step
+    env: { RR_PREC: step }
+    shell: { command: 'echo "$RR_PREC"' }
+  - name: process-block
+    shell: { command: 'echo "$RR_PREC"', env: { RR_PREC: block } }
+  - name: step-block
+    env: { RR_PREC: step }
+    shell: { command: 'echo "$RR_PREC"', env: { RR_PREC: block } }
+  - name: clean-step
+    clean_env: true
+    env: { RR_PREC: step }
+    shell: { command: 'echo "$RR_PREC"' }
+  - name: unset-step
+    unset_env: [RR_PREC]
+    env: { RR_PREC: step }
+    shell: { command: 'echo "$RR_PREC"' }
+  - name: step-sees-process
+    env: { OTHER: "{{ env.RR_PREC }}" }
+    shell: { command: 'echo "$OTHER"' }
+"#;
+
+#[test]
+fn env_explicit_first() {
+    // default und ausdrücklich gleich: deklariert schlägt geerbt, der Block
+    // (später gerendert) den Schritt
+    for args in [
+        &["pb.yaml"][..],
+        &["--env-precedence", "explicit-first", "pb.yaml"],
+    ] {
+        let dir = setup("explicit", &[("pb.yaml", ENV_PLAYBOOK)]);
+        assert_eq!(
+            run(&dir, args),
+            ["proc", "step", "block", "block", "step", "step", "proc"]
+        );
+    }
+}
+
+#[test]
+fn env_process_first() {
+    let dir = setup("process", &[("pb.yaml", ENV_PLAYBOOK)]);
+    // geerbt schlägt Schritt und Block; ohne geerbten Wert (clean_env,
+    // unset_env) gilt wieder das deklarierte env, der Block vor dem Schritt
+    assert_eq!(
+        run(&dir, &["--env-precedence", "process-first", "pb.yaml"]),
+        ["proc", "proc", "proc", "proc", "step", "step", "proc"]
+    );
+}
+
+#[test]
+fn step_vars_over_globals() {
+    let pb = r#"
+version: 1
+globals: { who: global, other: global }
+steps:
+  - name: globals
+    shell: { command: "echo {{ who }}" }
+  - name: vars
+    vars: { who: step }
+    shell: { command: "echo {{ who }}-{{ other }}" }
+  - name: loop
+    vars: { who: step }
+    loop: [item]
+    shell: { command: "echo {{ who }}-{{ item }}" }
+  - name: after
+    shell: { command: "echo {{ who }}" }
+"#;
+    let dir = setup("vars", &[("pb.yaml", pb)]);
+    // vars gelten nur im eigenen Schritt, danach wieder die globals
+    assert_eq!(
+        run(&dir, &["pb.yaml"]),
+        ["global", "step-global", "step-item", "global"]
+    );
+}
+
+#[test]
+fn vars_file_over_globals_and_prompts() {
+    let pb = r#"
+version: 1
+globals: { a: global, b: global, c: { x: global, y: global } }
+prompts:
+  - { name: b, default: prompt }
+  - { name: d, default: prompt }
+steps: []
+"#;
+    let files = [
+        ("pb.yaml", pb),
+        ("vars.yaml", "{ a: file, c: { x: file }, d: file }\n"),
+        ("t.j2", "{{ a }} {{ b }} {{ c.x }} {{ c.y }} {{ d }}\n"),
+    ];
+    // globals vor prompt-Default, --vars-file vor beiden, tief gemischt
+    let dir = setup("vars-file", &files);
+    assert_eq!(
+        run(
+            &dir,
+            &[
+                "template",
+                "t.j2",
+                "--playbook",
+                "pb.yaml",
+                "--non-interactive"
+            ]
+        ),
+        ["global global global global prompt"]
+    );
+    let dir = setup("vars-file", &files);
+    assert_eq!(
+        run(
+            &dir,
+            &[
+                "template",
+                "t.j2",
+                "--playbook",
+                "pb.yaml",
+                "--vars-file",
+                "vars.yaml",
+                "--non-interactive"
+            ]
+        ),
+        ["file global file global file"]
+    );
+}
+
+#[test]
+fn template_dir_cli_over_config_over_playbook() {
+    let files = [
+        ("pb.yaml", "version: 1\ntemplate_dir: doc\nsteps: []\n"),
+        ("cfg.yaml", "template_dir: cfg\n"),
+        ("t.j2", "{% include \"inc.j2\" %}"),
+        ("doc/inc.j2", "doc\n"),
+        ("cfg/inc.j2", "cfg\n"),
+        ("cli/inc.j2", "cli\n"),
+    ];
+    let base = ["template", "t.j2", "--playbook", "pb.yaml"];
+    for (extra, want) in [
+        (&[][..], "doc"),
+        (&["--config", "cfg.yaml"][..], "cfg"),
+        (&["--template-dir", "cli"][..], "cli"),
+        (
+            &["--config", "cfg.yaml", "--template-dir", "cli"][..],
+            "cli",
+        ),
+    ] {
+        let dir = setup("template-dir", &files);
+        let args = base.iter().chain(extra).copied().collect::<Vec<_>>();
+        assert_eq!(run(&dir, &args), [want], "{:?}", extra);
+    }
+}
@@ -0,0 +1,142 @@
+// tests/stress_output.rs
+//! Belastungstest für die Ausgabe paralleler Schritte: mehrere Kinder schreiben
+//! gleichzeitig viele nummerierte Zeilen auf stdout und stderr. Jede Zeile muss
+//! genau einmal, vollständig und je Stream in der richtigen Reihenfolge ankommen.
+#![cfg(unix)]
+
+use rust_runner::executor::StepOutcome;
+use rust_runner::observer::{Observer, OutputLine, StepStart};
+use rust_runner::{RunOptions, run_document, schema};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+const STEPS: [&str; 4] = ["a", "b", "c", "d"];
+const LINES: usize = 2000;
+// länger als PIPE_BUF (4096): ohne gemeinsamen Lock käme eine Zeile in Stücken an
+const WIDTH: usize = 5000;
+
+/// Vier gleichzeitig laufende Schritte, zwei davon mit `combine_output`.
+fn playbook() -> String {
+    let mut yaml = format!(
+        "version: 1\n\
+         step_templates:\n  \
+           noisy:\n    \
+             shell:\n      \
+               command: |\n        \
+                 pad=$(head -c {WIDTH} /dev/zero | tr '\\0' x)\n        \
+                 for i in $(seq {LINES}); do\n          \
+                   echo \"$TAG out $i $pad#end\"\n          \
+                   echo \"$TAG err $i $pad#end\" >&2\n        \
+                 done\n\
+         steps:\n"
+    );
+    for (n, tag) in STEPS.iter().enumerate() {
+        yaml += &format!(
+            "  - name: noisy-{tag}\n    use: noisy\n    combine_output: {}\n    env: {{ TAG: {tag} }}\n",
+            n < 2
+        );
+    }
+    yaml
+}
+
+/// Prüft die Zeilen eines Streams: `<tag> <src> <i> x...#end` für i = 1..=LINES.
+fn check_stream(tag: &str, src: &str, lines: &[String]) {
+    assert_eq!(lines.len(), LINES, "{tag}/{src}: Zahl der Zeilen");
+    let pad = "x".repeat(WIDTH);
+    for (i, line) in lines.iter().enumerate() {
+        assert_eq!(
+            line,
+            &format!("{tag} {src} {} {pad}#end", i + 1),
+            "{tag}/{src}: Zeile {}",
+            i + 1
+        );
+    }
+}
+
+/// Sammelt jede Zeile mit Schritt und Stream.
+#[derive(Default)]
+struct Collect(Mutex<BTreeMap<(usize, String), Vec<String>>>);
+
+impl Observer for Collect {
+    fn on_step_start(&self, _: &StepStart) {}
+
+    fn on_step_output(&self, l: &OutputLine) {
+        let mut lines = self.0.lock().unwrap();
+        let key = (l.idx, l.src.to_string());
+        lines.entry(key).or_default().push(l.line.to_string());
+    }
+
+    fn on_step_end(&self, _: usize, _: &str, _: &anyhow::Result<StepOutcome>) {}
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn parallel_steps_deliver_every_line_in_order() {
+    let doc = schema::parse_document(&playbook()).unwrap();
+    let observer = Arc::new(Collect::default());
+    let report = run_document(
+        doc,
+        RunOptions {
+            jobs: Some(STEPS.len()),
+            observer: Some(observer.clone()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(report.success());
+
+    let lines = observer.0.lock().unwrap();
+    assert_eq!(lines.len(), STEPS.len() * 2);
+    for (idx, tag) in STEPS.iter().enumerate() {
+        for src in ["out", "err"] {
+            check_stream(tag, src, &lines[&(idx, src.to_string())]);
+        }
+    }
+}
+
+/// Dasselbe über die Konsole des Binaries: keine Zeile darf zerstückelt
+/// oder mit einer anderen verschränkt auf stdout bzw. stderr landen.
+#[test]
+fn console_lines_are_not_interleaved() {
+    let dir = std::env::temp_dir().join(format!("rr-stress-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("stress.yaml");
+    std::fs::write(&file, playbook()).unwrap();
+    let out = std::process::Command::new(env!("CARGO_BIN_EXE_rust-runner"))
+        .args(["--no-config", "--jobs", "4"])
+        .arg(&file)
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(out.status.success());
+
+    // je Schritt und Stream die Zeilen in Ankunftsreihenfolge, ohne Präfix
+    let mut streams = BTreeMap::<(String, String), Vec<String>>::new();
+    for text in [&out.stdout, &out.stderr] {
+        for line in String::from_utf8(text.clone()).unwrap().lines() {
+            let Some(rest) = line.strip_prefix('[') else {
+                continue;
+            };
+            // `[1:noisy-a][out] a out 1 xxx#end`
+            let Some((prefix, text)) = rest.split_once("] ") else {
+                continue;
+            };
+            let Some((step, src)) = prefix.split_once("][") else {
+                continue;
+            };
+            let tag = step.rsplit('-').next().unwrap().to_string();
+            let src = text.split(' ').nth(1).unwrap_or(src).to_string();
+            streams
+                .entry((tag, src))
+                .or_default()
+                .push(text.to_string());
+        }
+    }
+    assert_eq!(streams.len(), STEPS.len() * 2);
+    for tag in STEPS {
+        for src in ["out", "err"] {
+            check_stream(tag, src, &streams[&(tag.to_string(), src.to_string())]);
+        }
+    }
+}